@@ -0,0 +1,100 @@
+#![feature(test)]
+extern crate test;
+
+extern crate biscuit_auth as biscuit;
+
+use biscuit::datalog::{self, SymbolTable, World};
+use test::Bencher;
+
+/// independent rules, each joining a few hundred facts in a world of a few
+/// thousand, to compare `World::run` with and without the `parallel` feature.
+/// With the feature, `world_run_independent_rules_sequential` runs the same
+/// rules in a single thread pool, where they are applied sequentially:
+///
+/// ```text
+/// cargo bench --bench world --features parallel world_run_independent_rules
+/// ```
+#[bench]
+fn world_run_independent_rules(b: &mut Bencher) {
+  let world = independent_rules();
+
+  b.iter(|| run_independent_rules(&world));
+}
+
+#[cfg(feature = "parallel")]
+#[bench]
+fn world_run_independent_rules_sequential(b: &mut Bencher) {
+  let world = independent_rules();
+  let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+  b.iter(|| pool.install(|| run_independent_rules(&world)));
+}
+
+fn independent_rules() -> World {
+  let mut syms = SymbolTable::new();
+  let mut world = World::new();
+
+  for i in 0..8 {
+    let link = syms.insert(&format!("link{}", i));
+    let path = syms.insert(&format!("path{}", i));
+
+    for k in 0..200 {
+      world.add_fact(datalog::fact(link, &[datalog::int(k), datalog::int((k * 7 + i) % 200)]));
+    }
+
+    let a = datalog::var(&mut syms, "a");
+    let b = datalog::var(&mut syms, "b");
+    let c = datalog::var(&mut syms, "c");
+    world.add_rule(datalog::rule(
+      path,
+      &[&a, &c],
+      &[datalog::pred(link, &[&a, &b]), datalog::pred(link, &[&b, &c])],
+    ));
+  }
+
+  world
+}
+
+fn run_independent_rules(world: &World) {
+  let mut w = world.clone();
+  w.run().unwrap();
+  assert_eq!(w.facts.len(), 3200);
+}
+
+/// a rule joining a derived predicate, empty when `World::run` starts, with
+/// a very selective one. Once the derived predicate has grown, the selective
+/// predicate must be joined first
+#[bench]
+fn world_run_derived_predicate(b: &mut Bencher) {
+  let mut syms = SymbolTable::new();
+  let mut world = World::new();
+
+  let base = syms.insert("base");
+  let derived = syms.insert("derived");
+  let selected = syms.insert("selected");
+  let out = syms.insert("out");
+
+  for i in 0..2000 {
+    world.add_fact(datalog::fact(base, &[datalog::int(i), datalog::int(i * 2)]));
+  }
+  world.add_fact(datalog::fact(selected, &[datalog::int(42)]));
+
+  let x = datalog::var(&mut syms, "x");
+  let y = datalog::var(&mut syms, "y");
+  world.add_rule(datalog::rule(
+    derived,
+    &[&x, &y],
+    &[datalog::pred(base, &[&x, &y])],
+  ));
+  world.add_rule(datalog::rule(
+    out,
+    &[&y],
+    &[datalog::pred(derived, &[&x, &y]), datalog::pred(selected, &[&x])],
+  ));
+
+  b.iter(|| {
+    let mut w = world.clone();
+    w.run().unwrap();
+    assert_eq!(w.facts.len(), 4002);
+  });
+}
@@ -0,0 +1,583 @@
+//! macros checking biscuit datalog at compile time
+//!
+//! the `fact!`, `rule!`, `caveat!` and `block!` macros parse their datalog
+//! with the `biscuit_auth` parser while the crate is compiled, so syntax
+//! errors and unsafe rules are reported by the compiler instead of
+//! `error::Token::ParseError` at runtime. They generate the
+//! `biscuit_auth::token::builder` values directly.
+//!
+//! parameters like `{user}` are replaced by Rust values, either passed as
+//! named arguments or taken from the variable with the same name. Values
+//! are converted with `Atom::from`, so they must be integers, strings,
+//! booleans, dates, byte arrays, sets or atoms:
+//!
+//! ```rust
+//! use biscuit_quote::{block, caveat, fact, rule};
+//! use biscuit_auth::token::builder::BlockBuilder;
+//!
+//! let user = "alice";
+//! let f = fact!("user(#authority, {user})");
+//! let r = rule!(
+//!     "*can_read($0) <- right(#authority, $0, #read), owner({owner}, $0)",
+//!     owner = user
+//! );
+//! let c = caveat!(r#"*check($0) <- resource(#ambient, $0) @ $0 under "/home""#);
+//!
+//! let block = block!(
+//!     BlockBuilder::new(1),
+//!     r#"
+//!     owner({user}, "/home/alice");
+//!     caveat *check($0) <- resource(#ambient, $0), owner({user}, $0);
+//!     "#
+//! );
+//! ```
+extern crate proc_macro;
+
+use biscuit_auth::{
+    error, parser,
+    token::builder::{
+        Atom, BytesConstraint, Caveat, Constraint, ConstraintKind, DateConstraint, Fact,
+        IntConstraint, Predicate, RegexPattern, Rule, SetConstraint, StrConstraint,
+        SymbolConstraint,
+    },
+};
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::UNIX_EPOCH,
+};
+use syn::{
+    parse::{ParseStream, Parser},
+    Expr, Ident, LitStr, Token,
+};
+
+/// builds a `Fact`
+///
+/// ```rust
+/// # use biscuit_quote::fact;
+/// let f = fact!(r#"right(#authority, {resource}, #read)"#, resource = "file1");
+/// assert_eq!(f.to_string(), r#"right(#authority, "file1", #read)"#);
+/// ```
+#[proc_macro]
+pub fn fact(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input.into(), Kind::Fact).into()
+}
+
+/// builds a `Rule`
+#[proc_macro]
+pub fn rule(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input.into(), Kind::Rule).into()
+}
+
+/// builds a `Caveat`, with queries separated by `||`
+#[proc_macro]
+pub fn caveat(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input.into(), Kind::Caveat).into()
+}
+
+/// adds facts, rules and caveats to a `BlockBuilder`
+///
+/// the first argument is the block builder, that the macro returns with
+/// the new elements. The statements are separated by `;`, and caveats
+/// start with the `caveat` keyword:
+///
+/// ```rust
+/// # use biscuit_quote::block;
+/// # use biscuit_auth::token::builder::BlockBuilder;
+/// let block = block!(
+///     BlockBuilder::new(1),
+///     r#"
+///     *can_read($0) <- right(#authority, $0, #read);
+///     caveat *check($0) <- resource(#ambient, $0), can_read($0);
+///     "#
+/// );
+/// assert_eq!(block.rules.len(), 1);
+/// assert_eq!(block.caveats.len(), 1);
+/// ```
+#[proc_macro]
+pub fn block(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input.into(), Kind::Block).into()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Fact,
+    Rule,
+    Caveat,
+    Block,
+}
+
+/// arguments of a macro: `[builder,] "datalog" [, name = value]*`
+struct Input {
+    builder: Option<Expr>,
+    datalog: LitStr,
+    params: Vec<(Ident, Expr)>,
+}
+
+impl Input {
+    fn parse(input: ParseStream, with_builder: bool) -> syn::Result<Self> {
+        let builder = if with_builder {
+            let builder = input.parse()?;
+            input.parse::<Token![,]>()?;
+            Some(builder)
+        } else {
+            None
+        };
+
+        let datalog = input.parse()?;
+
+        let mut params = Vec::new();
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let name = input.parse()?;
+            input.parse::<Token![=]>()?;
+            params.push((name, input.parse()?));
+        }
+
+        Ok(Input {
+            builder,
+            datalog,
+            params,
+        })
+    }
+}
+
+fn expand(input: TokenStream, kind: Kind) -> TokenStream {
+    match try_expand(input, kind) {
+        Ok(tokens) => tokens,
+        Err(e) => e.to_compile_error(),
+    }
+}
+
+fn try_expand(input: TokenStream, kind: Kind) -> syn::Result<TokenStream> {
+    let input = (|stream: ParseStream| Input::parse(stream, kind == Kind::Block)).parse2(input)?;
+    let text = input.datalog.value();
+    let span = input.datalog.span();
+    let b = quote!(::biscuit_auth::token::builder);
+
+    match kind {
+        Kind::Fact => {
+            let fact = parse_complete(&text, span, |i| parser::fact(i).map_err(|_| ()))?;
+            let gen = Generator::new(parameters(std::iter::once(&fact.0)), &input.params, span)?;
+            let bindings = &gen.bindings;
+            let fact = gen.fact(&fact);
+            Ok(quote!({
+                #(#bindings)*
+                #fact
+            }))
+        }
+        Kind::Rule => {
+            let rule = parse_complete(&text, span, |i| parser::rule(i).map_err(|_| ()))?;
+            check_rule(&rule, span)?;
+            let gen = Generator::new(parameters(rule_predicates(&rule)), &input.params, span)?;
+            let bindings = &gen.bindings;
+            let rule = gen.rule(&rule)?;
+            Ok(quote!({
+                #(#bindings)*
+                #rule
+            }))
+        }
+        Kind::Caveat => {
+            let caveat = parse_complete(&text, span, |i| parser::caveat(i).map_err(|_| ()))?;
+            for query in caveat.queries.iter() {
+                check_rule(query, span)?;
+            }
+            let gen = Generator::new(
+                parameters(caveat.queries.iter().flat_map(rule_predicates)),
+                &input.params,
+                span,
+            )?;
+            let bindings = &gen.bindings;
+            let caveat = gen.caveat(&caveat)?;
+            Ok(quote!({
+                #(#bindings)*
+                #caveat
+            }))
+        }
+        Kind::Block => {
+            let block = parse_complete(&text, span, |i| parser::block(i).map_err(|_| ()))?;
+            for rule in block
+                .rules
+                .iter()
+                .chain(block.caveats.iter().flat_map(|c| c.queries.iter()))
+            {
+                check_rule(rule, span)?;
+            }
+
+            let predicates = block
+                .facts
+                .iter()
+                .map(|f| &f.0)
+                .chain(block.rules.iter().flat_map(rule_predicates))
+                .chain(
+                    block
+                        .caveats
+                        .iter()
+                        .flat_map(|c| c.queries.iter().flat_map(rule_predicates)),
+                );
+            let gen = Generator::new(parameters(predicates), &input.params, span)?;
+            let bindings = &gen.bindings;
+            let builder = &input.builder;
+            let facts = block.facts.iter().map(|f| gen.fact(f));
+            let rules = block
+                .rules
+                .iter()
+                .map(|r| gen.rule(r))
+                .collect::<syn::Result<Vec<_>>>()?;
+            let caveats = block
+                .caveats
+                .iter()
+                .map(|c| gen.caveat(c))
+                .collect::<syn::Result<Vec<_>>>()?;
+
+            Ok(quote!({
+                let mut __biscuit_block: #b::BlockBuilder = #builder;
+                #(#bindings)*
+                #(__biscuit_block.facts.push(#facts);)*
+                #(__biscuit_block.rules.push(#rules);)*
+                #(__biscuit_block.caveats.push(#caveats);)*
+                __biscuit_block
+            }))
+        }
+    }
+}
+
+/// runs a parser, requiring it to consume the whole input
+fn parse_complete<T, F>(text: &str, span: Span, f: F) -> syn::Result<T>
+where
+    F: for<'a> Fn(&'a str) -> Result<(&'a str, T), ()>,
+{
+    match f(text.trim_start()) {
+        Ok((remaining, value)) if remaining.trim().is_empty() => Ok(value),
+        Ok((remaining, _)) => Err(syn::Error::new(
+            span,
+            format!("could not parse the datalog, unexpected input: {}", remaining.trim()),
+        )),
+        Err(()) => Err(syn::Error::new(
+            span,
+            match invalid_regex(text) {
+                Some(e) => e,
+                None => "could not parse the datalog".to_string(),
+            },
+        )),
+    }
+}
+
+/// the parser rejects invalid regexes without saying why, so the patterns
+/// after `matches` are compiled again to explain the error
+fn invalid_regex(text: &str) -> Option<String> {
+    text.match_indices("matches")
+        .filter_map(|(index, _)| {
+            let rest = text[index + "matches".len()..].trim_start().strip_prefix('/')?;
+            let mut pattern = String::new();
+            let mut chars = rest.chars();
+            loop {
+                match chars.next()? {
+                    '/' => break,
+                    // same escapes as the parser
+                    '\\' => pattern.push(match chars.next()? {
+                        '\\' => '\\',
+                        '"' => '"',
+                        'n' => '\n',
+                        '/' => '/',
+                        _ => return None,
+                    }),
+                    c => pattern.push(c),
+                }
+            }
+            RegexPattern::new(&pattern)
+                .err()
+                .map(|e| format!("invalid regex /{}/: {}", pattern, e))
+        })
+        .next()
+}
+
+fn to_syn_error(e: error::Token, span: Span) -> syn::Error {
+    match e {
+        error::Token::UnsafeRule(error::UnsafeRule { rule, variables }) => syn::Error::new(
+            span,
+            format!(
+                "the variables {} of the rule `{}` do not appear in its body",
+                variables
+                    .iter()
+                    .map(|v| format!("${}", v))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                rule
+            ),
+        ),
+        e => syn::Error::new(span, e.to_string()),
+    }
+}
+
+/// rejects unsafe rules, and parameters in constraints: their kind depends
+/// on the type of the value, that is only known at runtime
+fn check_rule(rule: &Rule, span: Span) -> syn::Result<()> {
+    rule.validate_variables().map_err(|e| to_syn_error(e, span))?;
+
+    for constraint in rule.2.iter() {
+        if let ConstraintKind::Parameter(_, name) = &constraint.kind {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "the parameter {{{}}} cannot be used in a constraint by the macros, \
+                     use the `*_with_params` methods of the builders instead",
+                    name
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn rule_predicates(rule: &Rule) -> impl Iterator<Item = &Predicate> {
+    std::iter::once(&rule.0).chain(rule.1.iter())
+}
+
+fn parameters<'a>(predicates: impl Iterator<Item = &'a Predicate>) -> BTreeSet<String> {
+    predicates
+        .flat_map(|p| p.ids.iter())
+        .filter_map(|id| match id {
+            Atom::Parameter(p) => Some(p.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// generates the code building the datalog elements
+struct Generator {
+    /// evaluates the value of each parameter once
+    bindings: Vec<TokenStream>,
+    /// variable holding the value of each parameter
+    params: HashMap<String, Ident>,
+    /// span of the datalog string, for errors
+    span: Span,
+}
+
+impl Generator {
+    fn new(
+        used: BTreeSet<String>,
+        explicit: &[(Ident, Expr)],
+        span: Span,
+    ) -> syn::Result<Self> {
+        for (name, _) in explicit.iter() {
+            if !used.contains(&name.to_string()) {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!("the parameter {{{}}} does not appear in the datalog", name),
+                ));
+            }
+        }
+
+        let b = quote!(::biscuit_auth::token::builder);
+        let mut bindings = Vec::new();
+        let mut params = HashMap::new();
+
+        for name in used {
+            let value = match explicit.iter().find(|(n, _)| *n == name) {
+                Some((_, value)) => quote!(#value),
+                None => {
+                    // the value is taken from the variable with the same name
+                    let mut ident = syn::parse_str::<Ident>(&name).map_err(|_| {
+                        syn::Error::new(span, format!("no value for the parameter {{{}}}", name))
+                    })?;
+                    ident.set_span(span);
+                    quote!(#ident)
+                }
+            };
+
+            let ident = format_ident!("__biscuit_param_{}", name);
+            bindings.push(quote!(let #ident: #b::Atom = #b::Atom::from(#value);));
+            params.insert(name, ident);
+        }
+
+        Ok(Generator {
+            bindings,
+            params,
+            span,
+        })
+    }
+
+    fn atom(&self, atom: &Atom) -> TokenStream {
+        let b = quote!(::biscuit_auth::token::builder);
+        match atom {
+            Atom::Symbol(s) => quote!(#b::Atom::Symbol(#s.to_string())),
+            Atom::Variable(v) => quote!(#b::Atom::Variable(#v.to_string())),
+            Atom::Integer(i) => quote!(#b::Atom::Integer(#i)),
+            Atom::Str(s) => quote!(#b::Atom::Str(#s.to_string())),
+            Atom::Date(d) => quote!(#b::Atom::Date(#d)),
+            Atom::Bytes(v) => quote!(#b::Atom::Bytes(vec![#(#v),*])),
+            Atom::Bool(v) => quote!(#b::Atom::Bool(#v)),
+            Atom::Set(s) => {
+                let set = self.set(s);
+                quote!(#b::Atom::Set(#set))
+            }
+            Atom::Parameter(p) => {
+                let ident = &self.params[p];
+                quote!(::std::clone::Clone::clone(&#ident))
+            }
+        }
+    }
+
+    fn set(&self, set: &BTreeSet<Atom>) -> TokenStream {
+        let elements = set.iter().map(|a| self.atom(a));
+        quote!(vec![#(#elements),*].into_iter().collect())
+    }
+
+    fn predicate(&self, predicate: &Predicate) -> TokenStream {
+        let b = quote!(::biscuit_auth::token::builder);
+        let name = &predicate.name;
+        let ids = predicate.ids.iter().map(|id| self.atom(id));
+        quote!(#b::Predicate {
+            name: #name.to_string(),
+            ids: vec![#(#ids),*],
+        })
+    }
+
+    fn fact(&self, fact: &Fact) -> TokenStream {
+        let b = quote!(::biscuit_auth::token::builder);
+        let predicate = self.predicate(&fact.0);
+        quote!(#b::Fact(#predicate))
+    }
+
+    fn rule(&self, rule: &Rule) -> syn::Result<TokenStream> {
+        let b = quote!(::biscuit_auth::token::builder);
+        let head = self.predicate(&rule.0);
+        let body = rule.1.iter().map(|p| self.predicate(p));
+        let constraints = rule
+            .2
+            .iter()
+            .map(|c| self.constraint(c))
+            .collect::<syn::Result<Vec<_>>>()?;
+        Ok(quote!(#b::Rule(#head, vec![#(#body),*], vec![#(#constraints),*])))
+    }
+
+    fn caveat(&self, caveat: &Caveat) -> syn::Result<TokenStream> {
+        let b = quote!(::biscuit_auth::token::builder);
+        let queries = caveat
+            .queries
+            .iter()
+            .map(|q| self.rule(q))
+            .collect::<syn::Result<Vec<_>>>()?;
+        Ok(quote!(#b::Caveat {
+            queries: vec![#(#queries),*],
+        }))
+    }
+
+    fn constraint(&self, constraint: &Constraint) -> syn::Result<TokenStream> {
+        let b = quote!(::biscuit_auth::token::builder);
+        let id = &constraint.id;
+        let kind = match &constraint.kind {
+            ConstraintKind::Integer(c) => {
+                let c = match c {
+                    IntConstraint::Lower(i) => quote!(Lower(#i)),
+                    IntConstraint::Larger(i) => quote!(Larger(#i)),
+                    IntConstraint::LowerOrEqual(i) => quote!(LowerOrEqual(#i)),
+                    IntConstraint::LargerOrEqual(i) => quote!(LargerOrEqual(#i)),
+                    IntConstraint::Equal(i) => quote!(Equal(#i)),
+                    IntConstraint::In(h) => {
+                        let h = sorted(h.iter());
+                        quote!(In(vec![#(#h),*].into_iter().collect()))
+                    }
+                    IntConstraint::NotIn(h) => {
+                        let h = sorted(h.iter());
+                        quote!(NotIn(vec![#(#h),*].into_iter().collect()))
+                    }
+                };
+                quote!(#b::ConstraintKind::Integer(#b::IntConstraint::#c))
+            }
+            ConstraintKind::String(c) => {
+                let c = match c {
+                    StrConstraint::Prefix(s) => quote!(Prefix(#s.to_string())),
+                    StrConstraint::Suffix(s) => quote!(Suffix(#s.to_string())),
+                    StrConstraint::Equal(s) => quote!(Equal(#s.to_string())),
+                    StrConstraint::Regex(r) => {
+                        let r = r.as_str();
+                        // checked again with the limits used at runtime, so the
+                        // generated code cannot fail
+                        RegexPattern::new(r).map_err(|e| {
+                            syn::Error::new(self.span, format!("invalid regex /{}/: {}", r, e))
+                        })?;
+                        quote!(Regex(#b::RegexPattern::new(#r).expect("regex checked at compile time")))
+                    }
+                    StrConstraint::PathPrefix(s) => quote!(PathPrefix(#s.to_string())),
+                    StrConstraint::Glob(s) => quote!(Glob(#s.to_string())),
+                    StrConstraint::In(h) => {
+                        let h = sorted(h.iter());
+                        quote!(In(vec![#(#h.to_string()),*].into_iter().collect()))
+                    }
+                    StrConstraint::NotIn(h) => {
+                        let h = sorted(h.iter());
+                        quote!(NotIn(vec![#(#h.to_string()),*].into_iter().collect()))
+                    }
+                };
+                quote!(#b::ConstraintKind::String(#b::StrConstraint::#c))
+            }
+            ConstraintKind::Date(c) => {
+                let (c, date) = match c {
+                    DateConstraint::Before(date) => (quote!(Before), date),
+                    DateConstraint::After(date) => (quote!(After), date),
+                };
+                let secs = date
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|_| {
+                        syn::Error::new(self.span, "dates before the Unix epoch are not supported")
+                    })?
+                    .as_secs();
+                quote!(#b::ConstraintKind::Date(#b::DateConstraint::#c(
+                    ::std::time::UNIX_EPOCH + ::std::time::Duration::from_secs(#secs)
+                )))
+            }
+            ConstraintKind::Symbol(c) => {
+                let (c, h) = match c {
+                    SymbolConstraint::In(h) => (quote!(In), h),
+                    SymbolConstraint::NotIn(h) => (quote!(NotIn), h),
+                };
+                let h = sorted(h.iter());
+                quote!(#b::ConstraintKind::Symbol(#b::SymbolConstraint::#c(
+                    vec![#(#h.to_string()),*].into_iter().collect()
+                )))
+            }
+            ConstraintKind::Bytes(c) => {
+                let c = match c {
+                    BytesConstraint::Equal(v) => quote!(Equal(vec![#(#v),*])),
+                    BytesConstraint::In(h) => {
+                        let h = sorted(h.iter()).into_iter().map(|v| quote!(vec![#(#v),*]));
+                        quote!(In(vec![#(#h),*].into_iter().collect()))
+                    }
+                    BytesConstraint::NotIn(h) => {
+                        let h = sorted(h.iter()).into_iter().map(|v| quote!(vec![#(#v),*]));
+                        quote!(NotIn(vec![#(#h),*].into_iter().collect()))
+                    }
+                };
+                quote!(#b::ConstraintKind::Bytes(#b::BytesConstraint::#c))
+            }
+            ConstraintKind::Set(c) => {
+                let (c, set) = match c {
+                    SetConstraint::Contains(set) => (quote!(Contains), set),
+                    SetConstraint::Intersection(set) => (quote!(Intersection), set),
+                };
+                let set = self.set(set);
+                quote!(#b::ConstraintKind::Set(#b::SetConstraint::#c(#set)))
+            }
+            ConstraintKind::Parameter(..) => unreachable!("rejected by check_rule"),
+        };
+
+        Ok(quote!(#b::Constraint {
+            id: #id.to_string(),
+            kind: #kind,
+        }))
+    }
+}
+
+/// hash sets are generated in a stable order, to keep builds reproducible
+fn sorted<'a, T: Ord + 'a>(values: impl Iterator<Item = &'a T>) -> Vec<&'a T> {
+    let mut values = values.collect::<Vec<_>>();
+    values.sort();
+    values
+}
@@ -0,0 +1,137 @@
+use biscuit_auth::{
+    crypto::KeyPair,
+    token::{
+        builder::{self, Atom, BlockBuilder},
+        Biscuit,
+    },
+};
+use biscuit_quote::{block, caveat, fact, rule};
+use rand::{prelude::StdRng, SeedableRng};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    time::{Duration, SystemTime},
+};
+
+#[test]
+fn same_as_parser() {
+    let f = fact!(r#"right(#authority, "file1", [1, 2], hex:0a0b, true, 2020-01-01T00:00:00Z)"#);
+    assert_eq!(
+        f,
+        builder::Fact::try_from(
+            r#"right(#authority, "file1", [1, 2], hex:0a0b, true, 2020-01-01T00:00:00Z)"#
+        )
+        .unwrap()
+    );
+
+    let rules = [
+        "*r($0) <- a($0) @ $0 < 1, $0 > 2, $0 <= 3, $0 >= 4, $0 == 5, $0 in [6, 7], $0 not in [8]",
+        r#"*r($0) <- a($0) @ $0 matches "pre*", $0 matches "*suf", $0 == "eq", $0 in ["a", "b"], $0 not in ["c"]"#,
+        r#"*r($0) <- a($0) @ $0 matches /^a+$/, $0 under "/home", $0 glob "/a/**""#,
+        "*r($0) <- a($0) @ $0 <= 2030-01-01T00:00:00Z, $0 >= 2020-01-01T00:00:00Z",
+        "*r($0) <- a($0) @ $0 in [#a, #b], $0 not in [#c]",
+        "*r($0) <- a($0) @ $0 == hex:0102, $0 in [hex:03, hex:04], $0 not in [hex:05]",
+        "*r($0) <- a($0) @ $0 contains [1, #a], $0 intersection [\"x\"]",
+    ];
+    let macro_rules = [
+        rule!("*r($0) <- a($0) @ $0 < 1, $0 > 2, $0 <= 3, $0 >= 4, $0 == 5, $0 in [6, 7], $0 not in [8]"),
+        rule!(r#"*r($0) <- a($0) @ $0 matches "pre*", $0 matches "*suf", $0 == "eq", $0 in ["a", "b"], $0 not in ["c"]"#),
+        rule!(r#"*r($0) <- a($0) @ $0 matches /^a+$/, $0 under "/home", $0 glob "/a/**""#),
+        rule!("*r($0) <- a($0) @ $0 <= 2030-01-01T00:00:00Z, $0 >= 2020-01-01T00:00:00Z"),
+        rule!("*r($0) <- a($0) @ $0 in [#a, #b], $0 not in [#c]"),
+        rule!("*r($0) <- a($0) @ $0 == hex:0102, $0 in [hex:03, hex:04], $0 not in [hex:05]"),
+        rule!("*r($0) <- a($0) @ $0 contains [1, #a], $0 intersection [\"x\"]"),
+    ];
+    for (text, rule) in rules.iter().zip(macro_rules.iter()) {
+        assert_eq!(rule, &builder::Rule::try_from(*text).unwrap());
+    }
+
+    let c = caveat!("*check($0) <- resource(#ambient, $0) || *check($0) <- operation(#ambient, $0)");
+    assert_eq!(
+        c,
+        builder::Caveat::try_from(
+            "*check($0) <- resource(#ambient, $0) || *check($0) <- operation(#ambient, $0)"
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn parameters() {
+    let user = "alice\", #admin";
+    let id = 42i64;
+    let expires = SystemTime::now() + Duration::from_secs(60);
+
+    let f = fact!("user(#authority, {user}, {id}, {expires})");
+    let mut params = HashMap::new();
+    params.insert("user".to_string(), builder::string(user));
+    params.insert("id".to_string(), builder::int(id));
+    params.insert("expires".to_string(), builder::date(&expires));
+    let mut expected = builder::Fact::try_from("user(#authority, {user}, {id}, {expires})").unwrap();
+    expected.apply_parameters(&params).unwrap();
+    assert_eq!(f, expected);
+    assert_eq!(f.0.ids[1], Atom::Str(user.to_string()));
+
+    // named arguments take precedence, and can be any expression
+    let r = rule!(
+        "*owned($0) <- owner({user}, $0), resource(#ambient, $0)",
+        user = format!("{}-{}", "bob", id),
+    );
+    assert_eq!(r.1[0].ids[0], Atom::Str("bob-42".to_string()));
+
+    // values are evaluated once, even if the parameter is used several times
+    let mut calls = 0;
+    let mut next = || {
+        calls += 1;
+        calls
+    };
+    let c = caveat!(
+        "*check($0) <- a({v}, $0) || *check($0) <- b({v}, $0)",
+        v = next() as i64
+    );
+    assert_eq!(c.queries[0].1[0].ids[0], Atom::Integer(1));
+    assert_eq!(c.queries[1].1[0].ids[0], Atom::Integer(1));
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn token() {
+    let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+    let root = KeyPair::new(&mut rng);
+
+    let mut builder = Biscuit::builder(&root);
+    let resource = "file1";
+    builder
+        .add_authority_fact(fact!("right(#authority, {resource}, #read)"))
+        .unwrap();
+    let biscuit1 = builder.build(&mut rng).unwrap();
+
+    let block2 = block!(
+        biscuit1.create_block(),
+        r#"
+        *readable($0) <- right(#authority, $0, #read);
+        caveat *check($0) <- resource(#ambient, $0), readable($0);
+        caveat *check(#read) <- operation(#ambient, {op});
+        "#,
+        op = builder::s("read"),
+    );
+    assert_eq!(block2.index, 1);
+    assert_eq!(block2.rules.len(), 1);
+    assert_eq!(block2.caveats.len(), 2);
+
+    let keypair2 = KeyPair::new(&mut rng);
+    let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+
+    let mut verifier = biscuit2.verify(root.public()).unwrap();
+    verifier.add_resource("file1");
+    verifier.add_operation("read");
+    verifier.verify().unwrap();
+
+    let mut verifier = biscuit2.verify(root.public()).unwrap();
+    verifier.add_resource("file2");
+    verifier.add_operation("read");
+    verifier.verify().unwrap_err();
+
+    let empty = block!(BlockBuilder::new(1), "");
+    assert!(empty.facts.is_empty() && empty.rules.is_empty() && empty.caveats.is_empty());
+}
@@ -0,0 +1,5 @@
+use biscuit_quote::fact;
+
+fn main() {
+    let _ = fact!("right(#authority, ");
+}
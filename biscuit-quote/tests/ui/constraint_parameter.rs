@@ -0,0 +1,5 @@
+use biscuit_quote::rule;
+
+fn main() {
+    let _ = rule!("*r($0) <- a($0) @ $0 > {min}", min = 1);
+}
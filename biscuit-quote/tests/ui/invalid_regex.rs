@@ -0,0 +1,5 @@
+use biscuit_quote::caveat;
+
+fn main() {
+    let _ = caveat!("*r($0) <- a($0) @ $0 matches /a(/");
+}
@@ -0,0 +1,5 @@
+use biscuit_quote::rule;
+
+fn main() {
+    let _ = rule!("*r($0, $1) <- a($0)");
+}
@@ -472,9 +472,9 @@ fn invalid_block_fact_authority<T: Rng + CryptoRng>(rng: &mut T, target: &str, r
 
     block2.add_caveat(rule(
         "caveat1",
-        &[var("0")],
+        &[s("read")],
         &[pred("operation", &[s("ambient"), s("read")])],
-    ));
+    )).unwrap();
 
     block2.add_fact(fact(
         "right",
@@ -516,9 +516,9 @@ fn invalid_block_fact_ambient<T: Rng + CryptoRng>(rng: &mut T, target: &str, roo
 
     block2.add_caveat(rule(
         "caveat1",
-        &[var("0")],
+        &[s("read")],
         &[pred("operation", &[s("ambient"), s("read")])],
-    ));
+    )).unwrap();
 
     block2.add_fact(fact("right", &[s("ambient"), string("file1"), s("write")]));
 
@@ -856,7 +856,7 @@ fn regex_constraint<T: Rng + CryptoRng>(rng: &mut T, target: &str, root: &KeyPai
         &[
           Constraint {
             id: "0".to_string(),
-            kind: ConstraintKind::String(StrConstraint::Regex("file[0-9]+.txt".to_string())),
+            kind: ConstraintKind::String(StrConstraint::Regex(RegexPattern::new("file[0-9]+.txt").unwrap())),
           },
         ]
     ));
@@ -6,6 +6,7 @@ use std::{
     cell::RefCell,
 };
 
+#[derive(Clone)]
 enum Error {
     Biscuit(crate::error::Token),
     InvalidArgument,
@@ -20,6 +21,144 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::InvalidArgument => ErrorKind::InvalidArgument,
+            Error::Biscuit(e) => {
+                use crate::error::*;
+                match e {
+                Token::InternalError => ErrorKind::InternalError,
+                Token::Format(Format::Signature(Signature::InvalidFormat)) => ErrorKind::FormatSignatureInvalidFormat,
+                Token::Format(Format::Signature(Signature::InvalidSignature)) => ErrorKind::FormatSignatureInvalidSignature,
+                Token::Format(Format::SealedSignature) => ErrorKind::FormatSealedSignature,
+                Token::Format(Format::UnknownSealingKey(_)) => ErrorKind::FormatUnknownSealingKey,
+                Token::Format(Format::EmptyKeys) => ErrorKind::FormatEmptyKeys,
+                Token::Format(Format::UnknownPublicKey) => ErrorKind::FormatUnknownPublickKey,
+                Token::Format(Format::DeserializationError(_)) => ErrorKind::FormatDeserializationError,
+                Token::Format(Format::SerializationError(_)) => ErrorKind::FormatSerializationError,
+                Token::Format(Format::BlockDeserializationError(_)) => ErrorKind::FormatBlockDeserializationError,
+                Token::Format(Format::BlockSerializationError(_)) => ErrorKind::FormatBlockSerializationError,
+                Token::Format(Format::UnsafeRule(_)) => ErrorKind::FormatUnsafeRule,
+                Token::Format(Format::TooLarge(_)) => ErrorKind::FormatTooLarge,
+                Token::Format(Format::TooManyBlocks(_)) => ErrorKind::FormatTooManyBlocks,
+                Token::Format(Format::TooManySymbols(_)) => ErrorKind::FormatTooManySymbols,
+                Token::Format(Format::TooManyFacts(_)) => ErrorKind::FormatTooManyFacts,
+                Token::Format(Format::TooManyRules(_)) => ErrorKind::FormatTooManyRules,
+                Token::Format(Format::TooManyCaveats(_)) => ErrorKind::FormatTooManyCaveats,
+                Token::Format(Format::StringTooLong(_)) => ErrorKind::FormatStringTooLong,
+                Token::Format(Format::RegexTooLarge(_)) => ErrorKind::FormatRegexTooLarge,
+                Token::Format(Format::InvalidRegex(_)) => ErrorKind::FormatInvalidRegex,
+                Token::InvalidAuthorityIndex(_) => ErrorKind::InvalidAuthorityIndex,
+                Token::InvalidBlockIndex(_) => ErrorKind::InvalidBlockIndex,
+                Token::SymbolTableOverlap => ErrorKind::SymbolTableOverlap,
+                Token::MissingSymbols => ErrorKind::MissingSymbols,
+                Token::Sealed => ErrorKind::Sealed,
+                Token::ParseError => ErrorKind::ParseError,
+                Token::UnsafeRule(_) => ErrorKind::UnsafeRule,
+                Token::Possession(Possession::MissingHolderKey) => ErrorKind::PossessionMissingHolderKey,
+                Token::Possession(Possession::MultipleHolderKeys) => ErrorKind::PossessionMultipleHolderKeys,
+                Token::Possession(Possession::InvalidHolderKey) => ErrorKind::PossessionInvalidHolderKey,
+                Token::Possession(Possession::KeyMismatch) => ErrorKind::PossessionKeyMismatch,
+                Token::Possession(Possession::InvalidSignature) => ErrorKind::PossessionInvalidSignature,
+                Token::Parameters(Parameters::Missing(_)) => ErrorKind::ParametersMissing,
+                Token::Parameters(Parameters::Unused(_)) => ErrorKind::ParametersUnused,
+                Token::Parameters(Parameters::InvalidValue(_)) => ErrorKind::ParametersInvalidValue,
+                Token::InvalidSet(_) => ErrorKind::InvalidSet,
+                Token::FailedLogic(Logic::InvalidAuthorityFact(_)) => ErrorKind::LogicInvalidAuthorityFact,
+                Token::FailedLogic(Logic::InvalidAmbientFact(_)) => ErrorKind::LogicInvalidAmbientFact,
+                Token::FailedLogic(Logic::InvalidBlockFact(_,_)) => ErrorKind::LogicInvalidBlockFact,
+                Token::FailedLogic(Logic::InvalidBlockRule(_,_)) => ErrorKind::LogicInvalidBlockRule,
+                Token::FailedLogic(Logic::FailedCaveats(_)) => ErrorKind::LogicFailedCaveats,
+                Token::FailedLogic(Logic::TooManyIterations(_)) => ErrorKind::LogicTooManyIterations,
+                }
+            }
+        }
+    }
+
+    fn failed_caveats(&self) -> &[crate::error::FailedCaveat] {
+        use crate::error::*;
+        match self {
+            Error::Biscuit(Token::FailedLogic(Logic::FailedCaveats(v))) => &v[..],
+            _ => &[],
+        }
+    }
+
+    fn caveat_id(&self, caveat_index: u64) -> u64 {
+        use crate::error::*;
+        match self.failed_caveats().get(caveat_index as usize) {
+            Some(FailedCaveat::Block(FailedBlockCaveat { caveat_id, ..}))
+            | Some(FailedCaveat::Expired(FailedBlockCaveat { caveat_id, ..}))
+            | Some(FailedCaveat::NotYetValid(FailedBlockCaveat { caveat_id, ..})) => *caveat_id as u64,
+            Some(FailedCaveat::Verifier(FailedVerifierCaveat { caveat_id, ..})) => *caveat_id as u64,
+            None => u64::MAX,
+        }
+    }
+
+    fn caveat_block_id(&self, caveat_index: u64) -> u64 {
+        use crate::error::*;
+        match self.failed_caveats().get(caveat_index as usize) {
+            Some(FailedCaveat::Block(FailedBlockCaveat { block_id, ..}))
+            | Some(FailedCaveat::Expired(FailedBlockCaveat { block_id, ..}))
+            | Some(FailedCaveat::NotYetValid(FailedBlockCaveat { block_id, ..})) => *block_id as u64,
+            _ => u64::MAX,
+        }
+    }
+
+    fn caveat_rule(&self, caveat_index: u64) -> Option<&str> {
+        use crate::error::*;
+        match self.failed_caveats().get(caveat_index as usize) {
+            Some(FailedCaveat::Block(FailedBlockCaveat { rule, ..}))
+            | Some(FailedCaveat::Expired(FailedBlockCaveat { rule, ..}))
+            | Some(FailedCaveat::NotYetValid(FailedBlockCaveat { rule, ..})) => Some(rule),
+            Some(FailedCaveat::Verifier(FailedVerifierCaveat { rule, ..})) => Some(rule),
+            None => None,
+        }
+    }
+
+    fn caveat_is_verifier(&self, caveat_index: u64) -> bool {
+        use crate::error::*;
+        match self.failed_caveats().get(caveat_index as usize) {
+            Some(FailedCaveat::Verifier(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn caveat_is_expired(&self, caveat_index: u64) -> bool {
+        use crate::error::*;
+        matches!(self.failed_caveats().get(caveat_index as usize), Some(FailedCaveat::Expired(_)))
+    }
+
+    fn caveat_is_not_yet_valid(&self, caveat_index: u64) -> bool {
+        use crate::error::*;
+        matches!(self.failed_caveats().get(caveat_index as usize), Some(FailedCaveat::NotYetValid(_)))
+    }
+}
+
+/// error returned through the `error` out parameter of the `*_with_error`
+/// variants of the API functions. The functions without that suffix keep
+/// their original signature and only report errors in the thread local
+/// last error, read with the `error_*` functions
+///
+/// it is owned by the caller, must be freed with `biscuit_error_free`, and
+/// can be used from any thread. The strings returned by its accessors
+/// live as long as the error
+pub struct BiscuitError {
+    error: Error,
+    message: CString,
+    caveat_rules: Vec<CString>,
+}
+
+impl BiscuitError {
+    fn new(error: Error) -> Self {
+        let message = CString::new(error.to_string()).unwrap_or_default();
+        let caveat_rules = (0..error.failed_caveats().len() as u64)
+            .map(|i| CString::new(error.caveat_rule(i).unwrap_or("")).unwrap_or_default())
+            .collect();
+
+        BiscuitError { error, message, caveat_rules }
+    }
+}
 
 thread_local! {
     static LAST_ERROR: RefCell<Option<Error>> = RefCell::new(None);
@@ -31,6 +170,17 @@ fn update_last_error(err: Error) {
     });
 }
 
+/// reports the error through the `error` out parameter if it is not null,
+/// and in the thread local last error, for the `error_*` functions
+///
+/// `*error` is expected to be null: a previous error stored there is not freed
+unsafe fn update_error(error: *mut *mut BiscuitError, err: Error) {
+    if !error.is_null() {
+        *error = Box::into_raw(Box::new(BiscuitError::new(err.clone())));
+    }
+    update_last_error(err);
+}
+
 #[no_mangle]
 pub extern fn error_message() -> *const c_char {
     thread_local! {
@@ -75,43 +225,36 @@ pub enum ErrorKind {
     LogicFailedCaveats,
     ParseError,
     None,
+    // new variants go after `None` so the existing values do not change
+    FormatUnsafeRule,
+    UnsafeRule,
+    FormatUnknownSealingKey,
+    PossessionMissingHolderKey,
+    PossessionMultipleHolderKeys,
+    PossessionInvalidHolderKey,
+    PossessionKeyMismatch,
+    PossessionInvalidSignature,
+    FormatTooLarge,
+    FormatTooManyBlocks,
+    FormatTooManySymbols,
+    FormatTooManyFacts,
+    FormatTooManyRules,
+    FormatTooManyCaveats,
+    FormatStringTooLong,
+    FormatRegexTooLarge,
+    FormatInvalidRegex,
+    ParametersMissing,
+    ParametersUnused,
+    ParametersInvalidValue,
+    LogicTooManyIterations,
+    InvalidSet,
 }
 
 #[no_mangle]
 pub extern fn error_kind() -> ErrorKind {
     LAST_ERROR.with(|prev| {
         match *prev.borrow() {
-            Some(ref err) => {
-                match err {
-                    Error::InvalidArgument => ErrorKind::InvalidArgument,
-                    Error::Biscuit(e) => {
-                        use crate::error::*;
-                        match e {
-                            Token::InternalError => ErrorKind::InternalError,
-                            Token::Format(Format::Signature(Signature::InvalidFormat)) => ErrorKind::FormatSignatureInvalidFormat,
-                            Token::Format(Format::Signature(Signature::InvalidSignature)) => ErrorKind::FormatSignatureInvalidSignature,
-                            Token::Format(Format::SealedSignature) => ErrorKind::FormatSealedSignature,
-                            Token::Format(Format::EmptyKeys) => ErrorKind::FormatEmptyKeys,
-                            Token::Format(Format::UnknownPublicKey) => ErrorKind::FormatUnknownPublickKey,
-                            Token::Format(Format::DeserializationError(_)) => ErrorKind::FormatDeserializationError,
-                            Token::Format(Format::SerializationError(_)) => ErrorKind::FormatSerializationError,
-                            Token::Format(Format::BlockDeserializationError(_)) => ErrorKind::FormatBlockDeserializationError,
-                            Token::Format(Format::BlockSerializationError(_)) => ErrorKind::FormatBlockSerializationError,
-                            Token::InvalidAuthorityIndex(_) => ErrorKind::InvalidAuthorityIndex,
-                            Token::InvalidBlockIndex(_) => ErrorKind::InvalidBlockIndex,
-                            Token::SymbolTableOverlap => ErrorKind::SymbolTableOverlap,
-                            Token::MissingSymbols => ErrorKind::MissingSymbols,
-                            Token::Sealed => ErrorKind::Sealed,
-                            Token::ParseError => ErrorKind::ParseError,
-                            Token::FailedLogic(Logic::InvalidAuthorityFact(_)) => ErrorKind::LogicInvalidAuthorityFact,
-                            Token::FailedLogic(Logic::InvalidAmbientFact(_)) => ErrorKind::LogicInvalidAmbientFact,
-                            Token::FailedLogic(Logic::InvalidBlockFact(_,_)) => ErrorKind::LogicInvalidBlockFact,
-                            Token::FailedLogic(Logic::InvalidBlockRule(_,_)) => ErrorKind::LogicInvalidBlockRule,
-                            Token::FailedLogic(Logic::FailedCaveats(_)) => ErrorKind::LogicFailedCaveats,
-                        }
-                    }
-                }
-            },
+            Some(ref err) => err.kind(),
             None => ErrorKind::None,
         }
     })
@@ -119,54 +262,30 @@ pub extern fn error_kind() -> ErrorKind {
 
 #[no_mangle]
 pub extern fn error_caveat_count() -> u64 {
-    use crate::error::*;
     LAST_ERROR.with(|prev| {
         match *prev.borrow() {
-            Some(Error::Biscuit(Token::FailedLogic(Logic::FailedCaveats(ref v))))
-                => v.len() as u64,
-            _ => 0,
+            Some(ref err) => err.failed_caveats().len() as u64,
+            None => 0,
         }
     })
 }
 
 #[no_mangle]
 pub extern fn error_caveat_id(caveat_index: u64) -> u64 {
-    use crate::error::*;
     LAST_ERROR.with(|prev| {
         match *prev.borrow() {
-            Some(Error::Biscuit(Token::FailedLogic(Logic::FailedCaveats(ref v))))
-                => {
-                    if caveat_index >= v.len() as u64 {
-                        u64::MAX
-                    } else {
-                        match v[caveat_index as usize] {
-                            FailedCaveat::Block(FailedBlockCaveat { caveat_id, ..}) => caveat_id as u64,
-                            FailedCaveat::Verifier(FailedVerifierCaveat { caveat_id, ..}) => caveat_id as u64,
-                        }
-                    }
-                },
-            _ => u64::MAX,
+            Some(ref err) => err.caveat_id(caveat_index),
+            None => u64::MAX,
         }
     })
 }
 
 #[no_mangle]
 pub extern fn error_caveat_block_id(caveat_index: u64) -> u64 {
-    use crate::error::*;
     LAST_ERROR.with(|prev| {
         match *prev.borrow() {
-            Some(Error::Biscuit(Token::FailedLogic(Logic::FailedCaveats(ref v))))
-                => {
-                    if caveat_index >= v.len() as u64 {
-                        u64::MAX
-                    } else {
-                        match v[caveat_index as usize] {
-                            FailedCaveat::Block(FailedBlockCaveat { block_id, ..}) => block_id as u64,
-                            _ => u64::MAX,
-                        }
-                    }
-                },
-            _ => u64::MAX,
+            Some(ref err) => err.caveat_block_id(caveat_index),
+            None => u64::MAX,
         }
     })
 }
@@ -175,70 +294,165 @@ pub extern fn error_caveat_block_id(caveat_index: u64) -> u64 {
 /// the string is overwritten on each call
 #[no_mangle]
 pub extern fn error_caveat_rule(caveat_index: u64) -> *const c_char {
-    use crate::error::*;
     thread_local! {
         static CAVEAT_RULE: RefCell<Option<CString>> = RefCell::new(None);
     }
 
     LAST_ERROR.with(|prev| {
-        match *prev.borrow() {
-            Some(Error::Biscuit(Token::FailedLogic(Logic::FailedCaveats(ref v))))
-                => {
-                    if caveat_index >= v.len() as u64 {
-                        std::ptr::null()
-                    } else {
-                        let rule = match &v[caveat_index as usize] {
-                            FailedCaveat::Block(FailedBlockCaveat { rule, ..}) => rule,
-                            FailedCaveat::Verifier(FailedVerifierCaveat { rule, ..}) => rule,
-                        };
-                        let err = CString::new(rule.clone()).ok();
-                        CAVEAT_RULE.with(|ret| {
-                            *ret.borrow_mut() = err;
-                            ret.borrow().as_ref().map(|x| x.as_ptr()).unwrap_or(std::ptr::null())
-                        })
-                    }
-                },
-            _ => std::ptr::null(),
+        match prev.borrow().as_ref().and_then(|err| err.caveat_rule(caveat_index)) {
+            Some(rule) => {
+                let rule = CString::new(rule).ok();
+                CAVEAT_RULE.with(|ret| {
+                    *ret.borrow_mut() = rule;
+                    ret.borrow().as_ref().map(|x| x.as_ptr()).unwrap_or(std::ptr::null())
+                })
+            },
+            None => std::ptr::null(),
         }
     })
 }
 
 #[no_mangle]
 pub extern fn error_caveat_is_verifier(caveat_index: u64) -> bool {
-    use crate::error::*;
     LAST_ERROR.with(|prev| {
         match *prev.borrow() {
-            Some(Error::Biscuit(Token::FailedLogic(Logic::FailedCaveats(ref v))))
-                => {
-                    if caveat_index >= v.len() as u64 {
-                        false
-                    } else {
-                        match v[caveat_index as usize] {
-                            FailedCaveat::Block(FailedBlockCaveat { .. }) => false,
-                            FailedCaveat::Verifier(FailedVerifierCaveat { .. }) => true,
-                        }
-                    }
-                },
-            _ => false,
+            Some(ref err) => err.caveat_is_verifier(caveat_index),
+            None => false,
         }
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_kind(
+    error: Option<&BiscuitError>,
+) -> ErrorKind {
+    match error {
+        Some(error) => error.error.kind(),
+        None => ErrorKind::None,
+    }
+}
+
+/// the string is freed along with the error
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_message(
+    error: Option<&BiscuitError>,
+) -> *const c_char {
+    match error {
+        Some(error) => error.message.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_caveat_count(
+    error: Option<&BiscuitError>,
+) -> u64 {
+    match error {
+        Some(error) => error.error.failed_caveats().len() as u64,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_caveat_id(
+    error: Option<&BiscuitError>,
+    caveat_index: u64,
+) -> u64 {
+    match error {
+        Some(error) => error.error.caveat_id(caveat_index),
+        None => u64::MAX,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_caveat_block_id(
+    error: Option<&BiscuitError>,
+    caveat_index: u64,
+) -> u64 {
+    match error {
+        Some(error) => error.error.caveat_block_id(caveat_index),
+        None => u64::MAX,
+    }
+}
+
+/// the string is freed along with the error
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_caveat_rule(
+    error: Option<&BiscuitError>,
+    caveat_index: u64,
+) -> *const c_char {
+    match error.and_then(|error| error.caveat_rules.get(caveat_index as usize)) {
+        Some(rule) => rule.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_caveat_is_verifier(
+    error: Option<&BiscuitError>,
+    caveat_index: u64,
+) -> bool {
+    match error {
+        Some(error) => error.error.caveat_is_verifier(caveat_index),
+        None => false,
+    }
+}
+
+/// true if the failed caveat is an expiration caveat
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_caveat_is_expired(
+    error: Option<&BiscuitError>,
+    caveat_index: u64,
+) -> bool {
+    match error {
+        Some(error) => error.error.caveat_is_expired(caveat_index),
+        None => false,
+    }
+}
+
+/// true if the failed caveat is a not before caveat
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_caveat_is_not_yet_valid(
+    error: Option<&BiscuitError>,
+    caveat_index: u64,
+) -> bool {
+    match error {
+        Some(error) => error.error.caveat_is_not_yet_valid(caveat_index),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_error_free(
+    _error: Option<Box<BiscuitError>>,
+) {
+}
+
 pub struct Biscuit(crate::token::Biscuit);
 pub struct KeyPair(crate::crypto::KeyPair);
 pub struct PublicKey(crate::crypto::PublicKey);
 pub struct BiscuitBuilder<'a>(crate::token::builder::BiscuitBuilder<'a>);
 pub struct BlockBuilder(crate::token::builder::BlockBuilder);
 pub struct Verifier<'a>(crate::token::verifier::Verifier<'a>);
+pub struct QueryResult(Vec<crate::token::builder::Fact>);
 
 #[no_mangle]
 pub unsafe extern "C" fn key_pair_new<'a>(
     seed_ptr: *const u8,
     seed_len: usize,
+) -> Option<Box<KeyPair>> {
+    key_pair_new_with_error(seed_ptr, seed_len, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn key_pair_new_with_error<'a>(
+    seed_ptr: *const u8,
+    seed_len: usize,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<KeyPair>> {
     let slice = std::slice::from_raw_parts(seed_ptr, seed_len);
     if slice.len() != 32 {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return None;
     }
 
@@ -253,9 +467,17 @@ pub unsafe extern "C" fn key_pair_new<'a>(
 #[no_mangle]
 pub unsafe extern "C" fn key_pair_public(
     kp: Option<&KeyPair>,
+) -> Option<Box<PublicKey>> {
+    key_pair_public_with_error(kp, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn key_pair_public_with_error(
+    kp: Option<&KeyPair>,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<PublicKey>> {
     if kp.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let  kp = kp?;
 
@@ -267,9 +489,18 @@ pub unsafe extern "C" fn key_pair_public(
 pub unsafe extern "C" fn key_pair_serialize(
     kp: Option<&KeyPair>,
     buffer_ptr: *mut u8,
+) -> usize {
+    key_pair_serialize_with_error(kp, buffer_ptr, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn key_pair_serialize_with_error(
+    kp: Option<&KeyPair>,
+    buffer_ptr: *mut u8,
+    error: *mut *mut BiscuitError,
 ) -> usize {
     if kp.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return 0;
     }
     let  kp = kp.unwrap();
@@ -284,12 +515,20 @@ pub unsafe extern "C" fn key_pair_serialize(
 #[no_mangle]
 pub unsafe extern "C" fn key_pair_deserialize(
     buffer_ptr: *mut u8,
+) -> Option<Box<KeyPair>> {
+    key_pair_deserialize_with_error(buffer_ptr, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn key_pair_deserialize_with_error(
+    buffer_ptr: *mut u8,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<KeyPair>> {
     let input_slice = std::slice::from_raw_parts_mut(buffer_ptr, 32);
 
     match crate::crypto::PrivateKey::from_bytes(input_slice) {
         None => {
-            update_last_error(Error::InvalidArgument);
+            update_error(error, Error::InvalidArgument);
             None
         },
         Some(privkey) => {
@@ -310,9 +549,18 @@ pub unsafe extern "C" fn key_pair_free(
 pub unsafe extern "C" fn public_key_serialize(
     kp: Option<&PublicKey>,
     buffer_ptr: *mut u8,
+) -> usize {
+    public_key_serialize_with_error(kp, buffer_ptr, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn public_key_serialize_with_error(
+    kp: Option<&PublicKey>,
+    buffer_ptr: *mut u8,
+    error: *mut *mut BiscuitError,
 ) -> usize {
     if kp.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return 0;
     }
     let  kp = kp.unwrap();
@@ -327,12 +575,20 @@ pub unsafe extern "C" fn public_key_serialize(
 #[no_mangle]
 pub unsafe extern "C" fn public_key_deserialize(
     buffer_ptr: *mut u8,
+) -> Option<Box<PublicKey>> {
+    public_key_deserialize_with_error(buffer_ptr, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn public_key_deserialize_with_error(
+    buffer_ptr: *mut u8,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<PublicKey>> {
     let input_slice = std::slice::from_raw_parts_mut(buffer_ptr, 32);
 
     match crate::crypto::PublicKey::from_bytes(input_slice) {
         None => {
-            update_last_error(Error::InvalidArgument);
+            update_error(error, Error::InvalidArgument);
             None
         },
         Some(pubkey) => {
@@ -341,6 +597,34 @@ pub unsafe extern "C" fn public_key_deserialize(
     }
 }
 
+/// returns the short printable fingerprint of the public key
+#[no_mangle]
+pub unsafe extern "C" fn public_key_fingerprint(
+    kp: Option<&PublicKey>,
+) -> *mut c_char {
+    public_key_fingerprint_with_error(kp, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn public_key_fingerprint_with_error(
+    kp: Option<&PublicKey>,
+    error: *mut *mut BiscuitError,
+) -> *mut c_char {
+    if kp.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+    let kp = kp.unwrap();
+
+    match CString::new(kp.0.fingerprint()) {
+        Ok(s) => s.into_raw(),
+        Err(_) => {
+            update_error(error, Error::InvalidArgument);
+            std::ptr::null_mut()
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn public_key_free(
     _kp: Option<Box<PublicKey>>,
@@ -351,9 +635,17 @@ pub unsafe extern "C" fn public_key_free(
 #[no_mangle]
 pub unsafe extern "C" fn biscuit_builder<'a>(
     key_pair: Option<&'a KeyPair>,
+) -> Option<Box<BiscuitBuilder<'a>>> {
+    biscuit_builder_with_error(key_pair, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_builder_with_error<'a>(
+    key_pair: Option<&'a KeyPair>,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<BiscuitBuilder<'a>>> {
     if key_pair.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let key_pair = key_pair?;
 
@@ -366,9 +658,18 @@ pub unsafe extern "C" fn biscuit_builder<'a>(
 pub unsafe extern "C" fn biscuit_builder_add_authority_fact<'a>(
     builder: Option<&mut BiscuitBuilder<'a>>,
     fact: *const c_char,
+) -> bool {
+    biscuit_builder_add_authority_fact_with_error(builder, fact, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_builder_add_authority_fact_with_error<'a>(
+    builder: Option<&mut BiscuitBuilder<'a>>,
+    fact: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let builder = builder.unwrap();
@@ -376,7 +677,7 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_fact<'a>(
     let fact = CStr::from_ptr(fact);
     let s = fact.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
@@ -384,7 +685,7 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_fact<'a>(
         .0
         .add_authority_fact(s.unwrap())
         .map_err(|e| {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
         })
         .is_ok()
 }
@@ -393,9 +694,18 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_fact<'a>(
 pub unsafe extern "C" fn biscuit_builder_add_authority_rule<'a>(
     builder: Option<&mut BiscuitBuilder<'a>>,
     rule: *const c_char,
+) -> bool {
+    biscuit_builder_add_authority_rule_with_error(builder, rule, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_builder_add_authority_rule_with_error<'a>(
+    builder: Option<&mut BiscuitBuilder<'a>>,
+    rule: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let builder = builder.unwrap();
@@ -403,7 +713,7 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_rule<'a>(
     let rule = CStr::from_ptr(rule);
     let s = rule.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
@@ -411,7 +721,7 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_rule<'a>(
         .0
         .add_authority_rule(s.unwrap())
         .map_err(|e| {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
         })
         .is_ok()
 }
@@ -420,9 +730,18 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_rule<'a>(
 pub unsafe extern "C" fn biscuit_builder_add_authority_caveat<'a>(
     builder: Option<&mut BiscuitBuilder<'a>>,
     caveat: *const c_char,
+) -> bool {
+    biscuit_builder_add_authority_caveat_with_error(builder, caveat, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_builder_add_authority_caveat_with_error<'a>(
+    builder: Option<&mut BiscuitBuilder<'a>>,
+    caveat: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let builder = builder.unwrap();
@@ -430,7 +749,7 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_caveat<'a>(
     let caveat = CStr::from_ptr(caveat);
     let s = caveat.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
@@ -438,7 +757,7 @@ pub unsafe extern "C" fn biscuit_builder_add_authority_caveat<'a>(
         .0
         .add_authority_caveat(s.unwrap())
         .map_err(|e| {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
         })
         .is_ok()
 }
@@ -448,14 +767,25 @@ pub unsafe extern "C" fn biscuit_builder_build<'a>(
     builder: Option<&BiscuitBuilder<'a>>,
     seed_ptr: *const u8,
     seed_len: usize,
+) -> Option<Box<Biscuit>> {
+    biscuit_builder_build_with_error(builder, seed_ptr, seed_len, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_builder_build_with_error<'a>(
+    builder: Option<&BiscuitBuilder<'a>>,
+    seed_ptr: *const u8,
+    seed_len: usize,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<Biscuit>> {
     if builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let builder = builder?;
 
     let slice = std::slice::from_raw_parts(seed_ptr, seed_len);
     if slice.len() != 32 {
+        update_error(error, Error::InvalidArgument);
         return None;
     }
 
@@ -463,7 +793,13 @@ pub unsafe extern "C" fn biscuit_builder_build<'a>(
     seed.copy_from_slice(slice);
 
     let mut rng: StdRng = SeedableRng::from_seed(seed);
-    (*builder).0.clone().build(&mut rng).map(Biscuit).map(Box::new).ok()
+    match (*builder).0.clone().build(&mut rng) {
+        Ok(token) => Some(Box::new(Biscuit(token))),
+        Err(e) => {
+            update_error(error, Error::Biscuit(e));
+            None
+        }
+    }
 }
 
 #[no_mangle]
@@ -476,10 +812,25 @@ pub unsafe extern "C" fn biscuit_builder_free<'a>(
 pub unsafe extern "C" fn biscuit_from(
     biscuit_ptr: *const u8,
     biscuit_len: usize,
+) -> Option<Box<Biscuit>> {
+    biscuit_from_with_error(biscuit_ptr, biscuit_len, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_from_with_error(
+    biscuit_ptr: *const u8,
+    biscuit_len: usize,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<Biscuit>> {
     let biscuit = std::slice::from_raw_parts(biscuit_ptr, biscuit_len);
 
-    crate::token::Biscuit::from(biscuit).map(Biscuit).map(Box::new).ok()
+    match crate::token::Biscuit::from(biscuit) {
+        Ok(token) => Some(Box::new(Biscuit(token))),
+        Err(e) => {
+            update_error(error, Error::Biscuit(e));
+            None
+        }
+    }
 }
 
 #[no_mangle]
@@ -488,19 +839,44 @@ pub unsafe extern "C" fn biscuit_from_sealed(
     biscuit_len: usize,
     secret_ptr: *const u8,
     secret_len: usize,
+) -> Option<Box<Biscuit>> {
+    biscuit_from_sealed_with_error(biscuit_ptr, biscuit_len, secret_ptr, secret_len, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_from_sealed_with_error(
+    biscuit_ptr: *const u8,
+    biscuit_len: usize,
+    secret_ptr: *const u8,
+    secret_len: usize,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<Biscuit>> {
     let biscuit = std::slice::from_raw_parts(biscuit_ptr, biscuit_len);
     let secret = std::slice::from_raw_parts(secret_ptr, secret_len);
 
-    crate::token::Biscuit::from_sealed(biscuit, secret).map(Biscuit).map(Box::new).ok()
+    match crate::token::Biscuit::from_sealed(biscuit, secret) {
+        Ok(token) => Some(Box::new(Biscuit(token))),
+        Err(e) => {
+            update_error(error, Error::Biscuit(e));
+            None
+        }
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn biscuit_serialized_size(
     biscuit: Option<&Biscuit>,
+) -> usize {
+    biscuit_serialized_size_with_error(biscuit, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_serialized_size_with_error(
+    biscuit: Option<&Biscuit>,
+    error: *mut *mut BiscuitError,
 ) -> usize {
     if biscuit.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return 0;
     }
 
@@ -509,7 +885,7 @@ pub unsafe extern "C" fn biscuit_serialized_size(
     match biscuit.0.serialized_size() {
         Ok(sz) => sz,
         Err(e) => {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
             return 0;
         }
     }
@@ -518,9 +894,17 @@ pub unsafe extern "C" fn biscuit_serialized_size(
 #[no_mangle]
 pub unsafe extern "C" fn biscuit_sealed_size(
     biscuit: Option<&Biscuit>,
+) -> usize {
+    biscuit_sealed_size_with_error(biscuit, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_sealed_size_with_error(
+    biscuit: Option<&Biscuit>,
+    error: *mut *mut BiscuitError,
 ) -> usize {
     if biscuit.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return 0;
     }
 
@@ -529,7 +913,7 @@ pub unsafe extern "C" fn biscuit_sealed_size(
     match biscuit.0.sealed_size() {
         Ok(sz) => sz,
         Err(e) => {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
             return 0;
         }
     }
@@ -539,9 +923,18 @@ pub unsafe extern "C" fn biscuit_sealed_size(
 pub unsafe extern "C" fn biscuit_serialize(
     biscuit: Option<&Biscuit>,
     buffer_ptr: *mut u8,
+) -> usize {
+    biscuit_serialize_with_error(biscuit, buffer_ptr, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_serialize_with_error(
+    biscuit: Option<&Biscuit>,
+    buffer_ptr: *mut u8,
+    error: *mut *mut BiscuitError,
 ) -> usize {
     if biscuit.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return 0;
     }
 
@@ -552,7 +945,7 @@ pub unsafe extern "C" fn biscuit_serialize(
             let size = match biscuit.0.serialized_size() {
                 Ok(sz) => sz,
                 Err(e) => {
-                    update_last_error(Error::Biscuit(e));
+                    update_error(error, Error::Biscuit(e));
                     return 0;
                 }
             };
@@ -563,7 +956,7 @@ pub unsafe extern "C" fn biscuit_serialize(
             v.len()
         },
         Err(e) => {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
             0
         }
     }
@@ -575,9 +968,20 @@ pub unsafe extern "C" fn biscuit_serialize_sealed(
     secret_ptr: *const u8,
     secret_len: usize,
     buffer_ptr: *mut u8,
+) -> usize {
+    biscuit_serialize_sealed_with_error(biscuit, secret_ptr, secret_len, buffer_ptr, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_serialize_sealed_with_error(
+    biscuit: Option<&Biscuit>,
+    secret_ptr: *const u8,
+    secret_len: usize,
+    buffer_ptr: *mut u8,
+    error: *mut *mut BiscuitError,
 ) -> usize {
     if biscuit.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return 0;
     }
 
@@ -589,7 +993,7 @@ pub unsafe extern "C" fn biscuit_serialize_sealed(
             let size = match biscuit.0.serialized_size() {
                 Ok(sz) => sz,
                 Err(e) => {
-                    update_last_error(Error::Biscuit(e));
+                    update_error(error, Error::Biscuit(e));
                     return 0;
                 }
             };
@@ -600,7 +1004,7 @@ pub unsafe extern "C" fn biscuit_serialize_sealed(
             v.len()
         },
         Err(e) => {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
             0
         }
     }
@@ -609,9 +1013,17 @@ pub unsafe extern "C" fn biscuit_serialize_sealed(
 #[no_mangle]
 pub unsafe extern "C" fn biscuit_create_block(
     biscuit: Option<&Biscuit>,
+) -> Option<Box<BlockBuilder>> {
+    biscuit_create_block_with_error(biscuit, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_create_block_with_error(
+    biscuit: Option<&Biscuit>,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<BlockBuilder>> {
     if biscuit.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let biscuit = biscuit?;
 
@@ -625,25 +1037,37 @@ pub unsafe extern "C" fn biscuit_append_block(
     key_pair: Option<&KeyPair>,
     seed_ptr: *const u8,
     seed_len: usize,
+) -> Option<Box<Biscuit>> {
+    biscuit_append_block_with_error(biscuit, block_builder, key_pair, seed_ptr, seed_len, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_append_block_with_error(
+    biscuit: Option<&Biscuit>,
+    block_builder: Option<&BlockBuilder>,
+    key_pair: Option<&KeyPair>,
+    seed_ptr: *const u8,
+    seed_len: usize,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<Biscuit>> {
     if biscuit.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let biscuit = biscuit?;
 
     if block_builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let builder = block_builder?;
 
     if key_pair.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let key_pair = key_pair?;
 
     let slice = std::slice::from_raw_parts(seed_ptr, seed_len);
     if slice.len() != 32 {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return None;
     }
 
@@ -655,7 +1079,7 @@ pub unsafe extern "C" fn biscuit_append_block(
     match biscuit.0.append(&mut rng, &key_pair.0, builder.0.clone()) {
         Ok(token) => Some(Box::new(Biscuit(token))),
         Err(e) => {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
             None
         }
     }
@@ -666,32 +1090,144 @@ pub unsafe extern "C" fn biscuit_append_block(
 pub unsafe extern "C" fn biscuit_verify<'a, 'b>(
     biscuit: Option<&'a Biscuit>,
     root: Option<&'b PublicKey>,
+) -> Option<Box<Verifier<'a>>> {
+    biscuit_verify_with_error(biscuit, root, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_verify_with_error<'a, 'b>(
+    biscuit: Option<&'a Biscuit>,
+    root: Option<&'b PublicKey>,
+    error: *mut *mut BiscuitError,
 ) -> Option<Box<Verifier<'a>>> {
     if biscuit.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let biscuit = biscuit?;
     if root.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
     }
     let root = root?;
 
-    (*biscuit).0.verify((*root).0).map(Verifier).map(Box::new).ok()
+    match (*biscuit).0.verify((*root).0) {
+        Ok(verifier) => Some(Box::new(Verifier(verifier))),
+        Err(e) => {
+            update_error(error, Error::Biscuit(e));
+            None
+        }
+    }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn biscuit_free(
-    _biscuit: Option<Box<Biscuit>>,
-) {
+pub unsafe extern "C" fn biscuit_block_count(
+    biscuit: Option<&Biscuit>,
+) -> usize {
+    biscuit_block_count_with_error(biscuit, std::ptr::null_mut())
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn block_builder_add_fact(
-    builder: Option<&mut BlockBuilder>,
-    fact: *const c_char,
+pub unsafe extern "C" fn biscuit_block_count_with_error(
+    biscuit: Option<&Biscuit>,
+    error: *mut *mut BiscuitError,
+) -> usize {
+    if biscuit.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return 0;
+    }
+    let biscuit = biscuit.unwrap();
+
+    biscuit.0.block_count()
+}
+
+/// returns the context of the block at `block_index` (0 is the authority block)
+///
+/// the returned string must be freed with `string_free`. If the block has
+/// no context, this returns a null pointer without setting an error
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_block_context(
+    biscuit: Option<&Biscuit>,
+    block_index: u32,
+) -> *mut c_char {
+    biscuit_block_context_with_error(biscuit, block_index, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_block_context_with_error(
+    biscuit: Option<&Biscuit>,
+    block_index: u32,
+    error: *mut *mut BiscuitError,
+) -> *mut c_char {
+    if biscuit.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+    let biscuit = biscuit.unwrap();
+
+    match biscuit.0.context().get(block_index as usize) {
+        None => {
+            update_error(error, Error::InvalidArgument);
+            std::ptr::null_mut()
+        },
+        Some(None) => std::ptr::null_mut(),
+        Some(Some(context)) => match CString::new(context.clone()) {
+            Ok(s) => s.into_raw(),
+            Err(_) => {
+                update_error(error, Error::InvalidArgument);
+                std::ptr::null_mut()
+            }
+        },
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_print(
+    biscuit: Option<&Biscuit>,
+) -> *mut c_char {
+    biscuit_print_with_error(biscuit, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_print_with_error(
+    biscuit: Option<&Biscuit>,
+    error: *mut *mut BiscuitError,
+) -> *mut c_char {
+    if biscuit.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+    let biscuit = biscuit.unwrap();
+
+    match CString::new(biscuit.0.print()) {
+        Ok(s) => s.into_raw(),
+        Err(_) => {
+            update_error(error, Error::InvalidArgument);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_free(
+    _biscuit: Option<Box<Biscuit>>,
+) {
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn block_builder_add_fact(
+    builder: Option<&mut BlockBuilder>,
+    fact: *const c_char,
+) -> bool {
+    block_builder_add_fact_with_error(builder, fact, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn block_builder_add_fact_with_error(
+    builder: Option<&mut BlockBuilder>,
+    fact: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let builder = builder.unwrap();
@@ -699,20 +1235,33 @@ pub unsafe extern "C" fn block_builder_add_fact(
     let fact = CStr::from_ptr(fact);
     let s = fact.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
-    builder.0.add_fact(s.unwrap()).is_ok()
+    builder.0.add_fact(s.unwrap())
+        .map_err(|e| {
+            update_error(error, Error::Biscuit(e));
+        })
+        .is_ok()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn block_builder_add_rule(
     builder: Option<&mut BlockBuilder>,
     rule: *const c_char,
+) -> bool {
+    block_builder_add_rule_with_error(builder, rule, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn block_builder_add_rule_with_error(
+    builder: Option<&mut BlockBuilder>,
+    rule: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let builder = builder.unwrap();
@@ -720,20 +1269,33 @@ pub unsafe extern "C" fn block_builder_add_rule(
     let rule = CStr::from_ptr(rule);
     let s = rule.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
-    builder.0.add_rule(s.unwrap()).is_ok()
+    builder.0.add_rule(s.unwrap())
+        .map_err(|e| {
+            update_error(error, Error::Biscuit(e));
+        })
+        .is_ok()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn block_builder_add_caveat(
     builder: Option<&mut BlockBuilder>,
     caveat: *const c_char,
+) -> bool {
+    block_builder_add_caveat_with_error(builder, caveat, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn block_builder_add_caveat_with_error(
+    builder: Option<&mut BlockBuilder>,
+    caveat: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if builder.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let builder = builder.unwrap();
@@ -741,11 +1303,46 @@ pub unsafe extern "C" fn block_builder_add_caveat(
     let caveat = CStr::from_ptr(caveat);
     let s = caveat.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
-    builder.0.add_caveat(s.unwrap()).is_ok()
+    builder.0.add_caveat(s.unwrap())
+        .map_err(|e| {
+            update_error(error, Error::Biscuit(e));
+        })
+        .is_ok()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn block_builder_set_context(
+    builder: Option<&mut BlockBuilder>,
+    context: *const c_char,
+) -> bool {
+    block_builder_set_context_with_error(builder, context, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn block_builder_set_context_with_error(
+    builder: Option<&mut BlockBuilder>,
+    context: *const c_char,
+    error: *mut *mut BiscuitError,
+) -> bool {
+    if builder.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+    let builder = builder.unwrap();
+
+    let context = CStr::from_ptr(context);
+    let s = context.to_str();
+    if s.is_err() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+
+    builder.0.set_context(s.unwrap().to_string());
+    true
 }
 
 #[no_mangle]
@@ -758,9 +1355,18 @@ pub unsafe extern "C" fn block_builder_free(
 pub unsafe extern "C" fn verifier_add_fact(
     verifier: Option<&mut Verifier>,
     fact: *const c_char,
+) -> bool {
+    verifier_add_fact_with_error(verifier, fact, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_fact_with_error(
+    verifier: Option<&mut Verifier>,
+    fact: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if verifier.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let verifier = verifier.unwrap();
@@ -768,20 +1374,33 @@ pub unsafe extern "C" fn verifier_add_fact(
     let fact = CStr::from_ptr(fact);
     let s = fact.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
-    verifier.0.add_fact(s.unwrap()).is_ok()
+    verifier.0.add_fact(s.unwrap())
+        .map_err(|e| {
+            update_error(error, Error::Biscuit(e));
+        })
+        .is_ok()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn verifier_add_rule(
     verifier: Option<&mut Verifier>,
     rule: *const c_char,
+) -> bool {
+    verifier_add_rule_with_error(verifier, rule, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_rule_with_error(
+    verifier: Option<&mut Verifier>,
+    rule: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if verifier.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let verifier = verifier.unwrap();
@@ -789,20 +1408,33 @@ pub unsafe extern "C" fn verifier_add_rule(
     let rule = CStr::from_ptr(rule);
     let s = rule.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
-    verifier.0.add_rule(s.unwrap()).is_ok()
+    verifier.0.add_rule(s.unwrap())
+        .map_err(|e| {
+            update_error(error, Error::Biscuit(e));
+        })
+        .is_ok()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn verifier_add_caveat(
     verifier: Option<&mut Verifier>,
     caveat: *const c_char,
+) -> bool {
+    verifier_add_caveat_with_error(verifier, caveat, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_caveat_with_error(
+    verifier: Option<&mut Verifier>,
+    caveat: *const c_char,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if verifier.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let verifier = verifier.unwrap();
@@ -810,23 +1442,212 @@ pub unsafe extern "C" fn verifier_add_caveat(
     let caveat = CStr::from_ptr(caveat);
     let s = caveat.to_str();
     if s.is_err() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
 
     verifier.0.add_caveat(s.unwrap())
         .map_err(|e| {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
         })
         .is_ok()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_resource(
+    verifier: Option<&mut Verifier>,
+    resource: *const c_char,
+) -> bool {
+    verifier_add_resource_with_error(verifier, resource, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_resource_with_error(
+    verifier: Option<&mut Verifier>,
+    resource: *const c_char,
+    error: *mut *mut BiscuitError,
+) -> bool {
+    if verifier.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+    let verifier = verifier.unwrap();
+
+    let resource = CStr::from_ptr(resource);
+    let s = resource.to_str();
+    if s.is_err() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+
+    verifier.0.add_resource(s.unwrap());
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_operation(
+    verifier: Option<&mut Verifier>,
+    operation: *const c_char,
+) -> bool {
+    verifier_add_operation_with_error(verifier, operation, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_operation_with_error(
+    verifier: Option<&mut Verifier>,
+    operation: *const c_char,
+    error: *mut *mut BiscuitError,
+) -> bool {
+    if verifier.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+    let verifier = verifier.unwrap();
+
+    let operation = CStr::from_ptr(operation);
+    let s = operation.to_str();
+    if s.is_err() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+
+    verifier.0.add_operation(s.unwrap());
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_set_time(
+    verifier: Option<&mut Verifier>,
+) -> bool {
+    verifier_set_time_with_error(verifier, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_set_time_with_error(
+    verifier: Option<&mut Verifier>,
+    error: *mut *mut BiscuitError,
+) -> bool {
+    if verifier.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+    let verifier = verifier.unwrap();
+
+    verifier.0.set_time();
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_block_keys(
+    verifier: Option<&mut Verifier>,
+) -> bool {
+    verifier_add_block_keys_with_error(verifier, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_add_block_keys_with_error(
+    verifier: Option<&mut Verifier>,
+    error: *mut *mut BiscuitError,
+) -> bool {
+    if verifier.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+    let verifier = verifier.unwrap();
+
+    verifier.0.add_block_keys();
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_revocation_check(
+    verifier: Option<&mut Verifier>,
+    ids_ptr: *const i64,
+    ids_len: usize,
+) -> bool {
+    verifier_revocation_check_with_error(verifier, ids_ptr, ids_len, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_revocation_check_with_error(
+    verifier: Option<&mut Verifier>,
+    ids_ptr: *const i64,
+    ids_len: usize,
+    error: *mut *mut BiscuitError,
+) -> bool {
+    if verifier.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+    let verifier = verifier.unwrap();
+
+    if ids_ptr.is_null() && ids_len != 0 {
+        update_error(error, Error::InvalidArgument);
+        return false;
+    }
+
+    let ids = if ids_len == 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(ids_ptr, ids_len)
+    };
+
+    verifier.0.revocation_check(ids);
+    true
+}
+
+/// runs a query on the verifier's world
+///
+/// the result must be freed with `query_result_free`
+#[no_mangle]
+pub unsafe extern "C" fn verifier_query(
+    verifier: Option<&mut Verifier>,
+    rule: *const c_char,
+) -> Option<Box<QueryResult>> {
+    verifier_query_with_error(verifier, rule, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_query_with_error(
+    verifier: Option<&mut Verifier>,
+    rule: *const c_char,
+    error: *mut *mut BiscuitError,
+) -> Option<Box<QueryResult>> {
+    if verifier.is_none() {
+        update_error(error, Error::InvalidArgument);
+    }
+    let verifier = verifier?;
+
+    let rule = CStr::from_ptr(rule);
+    let s = rule.to_str();
+    if s.is_err() {
+        update_error(error, Error::InvalidArgument);
+        return None;
+    }
+
+    match verifier.0.query(s.unwrap()) {
+        Ok(facts) => Some(Box::new(QueryResult(facts))),
+        Err(e) => {
+            update_error(error, Error::Biscuit(e));
+            None
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn verifier_verify(
     verifier: Option<&mut Verifier>,
+) -> bool {
+    verifier_verify_with_error(verifier, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_verify_with_error(
+    verifier: Option<&mut Verifier>,
+    error: *mut *mut BiscuitError,
 ) -> bool {
     if verifier.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return false;
     }
     let verifier = verifier.unwrap();
@@ -834,7 +1655,7 @@ pub unsafe extern "C" fn verifier_verify(
     match verifier.0.verify() {
         Ok(()) => true,
         Err(e) => {
-            update_last_error(Error::Biscuit(e));
+            update_error(error, Error::Biscuit(e));
             false
         }
     }
@@ -843,9 +1664,17 @@ pub unsafe extern "C" fn verifier_verify(
 #[no_mangle]
 pub unsafe extern "C" fn verifier_print(
     verifier: Option<&mut Verifier>,
+) -> *mut c_char {
+    verifier_print_with_error(verifier, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verifier_print_with_error(
+    verifier: Option<&mut Verifier>,
+    error: *mut *mut BiscuitError,
 ) -> *mut c_char {
     if verifier.is_none() {
-        update_last_error(Error::InvalidArgument);
+        update_error(error, Error::InvalidArgument);
         return std::ptr::null_mut();
     }
     let verifier = verifier.unwrap();
@@ -853,7 +1682,7 @@ pub unsafe extern "C" fn verifier_print(
     match CString::new(verifier.0.print_world()) {
         Ok(s) => s.into_raw(),
         Err(_) => {
-            update_last_error(Error::InvalidArgument);
+            update_error(error, Error::InvalidArgument);
             return std::ptr::null_mut();
         }
     }
@@ -865,6 +1694,65 @@ pub unsafe extern "C" fn verifier_free<'a>(
 ) {
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn query_result_count(
+    result: Option<&QueryResult>,
+) -> usize {
+    query_result_count_with_error(result, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_result_count_with_error(
+    result: Option<&QueryResult>,
+    error: *mut *mut BiscuitError,
+) -> usize {
+    if result.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return 0;
+    }
+    let result = result.unwrap();
+
+    result.0.len()
+}
+
+/// returns the fact at `index` in the query result, printed as datalog
+///
+/// the returned string must be freed with `string_free`
+#[no_mangle]
+pub unsafe extern "C" fn query_result_fact(
+    result: Option<&QueryResult>,
+    index: usize,
+) -> *mut c_char {
+    query_result_fact_with_error(result, index, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_result_fact_with_error(
+    result: Option<&QueryResult>,
+    index: usize,
+    error: *mut *mut BiscuitError,
+) -> *mut c_char {
+    if result.is_none() {
+        update_error(error, Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+    let result = result.unwrap();
+
+    match result.0.get(index).map(|fact| CString::new(fact.to_string())) {
+        Some(Ok(s)) => s.into_raw(),
+        _ => {
+            update_error(error, Error::InvalidArgument);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn query_result_free(
+    _result: Option<Box<QueryResult>>,
+) {
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn string_free(
   ptr: *mut c_char,
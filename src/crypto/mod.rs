@@ -7,17 +7,21 @@
 //!
 //! The implementation is based on [curve25519_dalek](https://github.com/dalek-cryptography/curve25519-dalek),
 //! a Rust implementation of the Ristretto group over Ed25519.
+//!
+//! Signature nonces are derived from the private key and the message, mixed
+//! with the RNG's output when one is provided, so that a weak RNG cannot leak
+//! the private key.
 #![allow(non_snake_case)]
 use super::error;
 use curve25519_dalek::{
     constants::RISTRETTO_BASEPOINT_POINT,
     ristretto::{CompressedRistretto, RistrettoPoint},
     scalar::Scalar,
-    traits::Identity,
+    traits::{Identity, VartimeMultiscalarMul},
 };
 use zeroize::Zeroize;
 use rand_core::{RngCore, CryptoRng};
-use sha2::{Digest, Sha512};
+use sha2::{Digest, Sha256, Sha512};
 use std::{ops::{Deref, Drop}, convert::TryInto};
 
 pub struct KeyPair {
@@ -41,17 +45,11 @@ impl KeyPair {
         KeyPair { private, public }
     }
 
-    #[allow(dead_code)]
-    fn sign<T: RngCore + CryptoRng>(&self, rng: &mut T, message: &[u8]) -> (Scalar, Scalar) {
-        let mut r = Scalar::random(rng);
-        let A = r * RISTRETTO_BASEPOINT_POINT;
-        let d = hash_points(&[A]);
-        let e = hash_message(self.public, message);
-        let z = r * d - e * self.private;
-
-        r.zeroize();
-
-        (d, z)
+    /// signs a message with this key pair alone, outside of a token's
+    /// aggregated signature
+    pub(crate) fn sign<T: RngCore + CryptoRng>(&self, rng: &mut T, message: &[u8]) -> (Scalar, Scalar) {
+        let (A, z) = sign_message(self, &hedged_randomness(rng), message);
+        (hash_points(&[A]), z)
     }
 
     pub fn private(&self) -> PrivateKey {
@@ -69,8 +67,7 @@ impl Drop for KeyPair {
     }
 }
 
-#[allow(dead_code)]
-fn verify(public: &RistrettoPoint, message: &[u8], signature: &(Scalar, Scalar)) -> bool {
+pub(crate) fn verify(public: &RistrettoPoint, message: &[u8], signature: &(Scalar, Scalar)) -> bool {
     let (d, z) = signature;
     let e = hash_message(*public, message);
     let d_inv = d.invert();
@@ -79,6 +76,22 @@ fn verify(public: &RistrettoPoint, message: &[u8], signature: &(Scalar, Scalar))
     hash_points(&[A]) == *d
 }
 
+pub(crate) fn signature_to_bytes(signature: &(Scalar, Scalar)) -> Vec<u8> {
+    let mut v = signature.0.to_bytes().to_vec();
+    v.extend_from_slice(&signature.1.to_bytes());
+    v
+}
+
+pub(crate) fn signature_from_bytes(bytes: &[u8]) -> Option<(Scalar, Scalar)> {
+    if bytes.len() != 64 {
+        return None;
+    }
+
+    let d: [u8; 32] = bytes[..32].try_into().ok()?;
+    let z: [u8; 32] = bytes[32..].try_into().ok()?;
+    Some((Scalar::from_canonical_bytes(d)?, Scalar::from_canonical_bytes(z)?))
+}
+
 pub struct PrivateKey(pub(crate) Scalar);
 
 impl PrivateKey {
@@ -111,6 +124,14 @@ impl PublicKey {
             .decompress()
             .map(PublicKey)
     }
+
+    /// short printable identifier for this key
+    ///
+    /// hex encoding of the first 16 bytes of the SHA-256 hash of the key
+    pub fn fingerprint(&self) -> String {
+        let hash = Sha256::digest(&self.to_bytes()[..]);
+        hex::encode(&hash[..16])
+    }
 }
 
 #[allow(dead_code)]
@@ -168,13 +189,20 @@ pub struct TokenSignature {
 
 impl TokenSignature {
     pub fn new<T: RngCore + CryptoRng>(rng: &mut T, keypair: &KeyPair, message: &[u8]) -> Self {
-        let mut r = Scalar::random(rng);
-        let A = r * RISTRETTO_BASEPOINT_POINT;
-        let d = hash_points(&[A]);
-        let e = hash_message(keypair.public, message);
-        let z = r * d - e * keypair.private;
+        Self::new_with_randomness(keypair, message, &hedged_randomness(rng))
+    }
+
+    /// creates a signature with a nonce derived only from the private key
+    /// and the message
+    ///
+    /// signing the same message with the same key always produces the same
+    /// signature
+    pub fn new_deterministic(keypair: &KeyPair, message: &[u8]) -> Self {
+        Self::new_with_randomness(keypair, message, &[0u8; 32])
+    }
 
-        r.zeroize();
+    fn new_with_randomness(keypair: &KeyPair, message: &[u8], randomness: &[u8; 32]) -> Self {
+        let (A, z) = sign_message(keypair, randomness, message);
 
         TokenSignature {
             parameters: vec![A],
@@ -183,13 +211,17 @@ impl TokenSignature {
     }
 
     pub fn sign<T: RngCore + CryptoRng>(&self, rng: &mut T, keypair: &KeyPair, message: &[u8]) -> Self {
-        let mut r = Scalar::random(rng);
-        let A = r * RISTRETTO_BASEPOINT_POINT;
-        let d = hash_points(&[A]);
-        let e = hash_message(keypair.public, message);
-        let z = r * d - e * keypair.private;
+        self.sign_with_randomness(keypair, message, &hedged_randomness(rng))
+    }
+
+    /// adds a signature with a nonce derived only from the private key
+    /// and the message
+    pub fn sign_deterministic(&self, keypair: &KeyPair, message: &[u8]) -> Self {
+        self.sign_with_randomness(keypair, message, &[0u8; 32])
+    }
 
-        r.zeroize();
+    fn sign_with_randomness(&self, keypair: &KeyPair, message: &[u8], randomness: &[u8; 32]) -> Self {
+        let (A, z) = sign_message(keypair, randomness, message);
 
         let mut t = TokenSignature {
             parameters: self.parameters.clone(),
@@ -247,6 +279,89 @@ impl TokenSignature {
     }
 }
 
+/// verifies many token signatures at once
+///
+/// each element contains the public keys, messages and signature of one
+/// token. The verification equations are multiplied by random coefficients
+/// and summed, so that a single multiscalar multiplication checks all of
+/// them. If this fails, at least one of the signatures is invalid, but
+/// finding which one requires verifying them one by one
+pub fn verify_batch<T: RngCore + CryptoRng, M: Deref<Target = [u8]>>(
+    rng: &mut T,
+    batch: &[(&[PublicKey], &[M], &TokenSignature)],
+) -> Result<(), error::Signature> {
+    let mut scalars = Vec::new();
+    let mut points = Vec::new();
+    let mut basepoint_scalar = Scalar::zero();
+
+    for (public_keys, messages, signature) in batch.iter() {
+        if !(public_keys.len() == messages.len() && public_keys.len() == signature.parameters.len()) {
+            return Err(error::Signature::InvalidFormat);
+        }
+
+        let c = Scalar::random(rng);
+        basepoint_scalar += c * signature.z;
+
+        for (pubkey, message) in public_keys.iter().zip(messages.iter()) {
+            scalars.push(c * hash_message(pubkey.0, message));
+            points.push(pubkey.0);
+        }
+
+        for A in signature.parameters.iter() {
+            scalars.push(-(c * hash_points(&[*A])));
+            points.push(*A);
+        }
+    }
+
+    scalars.push(basepoint_scalar);
+    points.push(RISTRETTO_BASEPOINT_POINT);
+
+    if RistrettoPoint::vartime_multiscalar_mul(scalars, points) == RistrettoPoint::identity() {
+        Ok(())
+    } else {
+        Err(error::Signature::InvalidSignature)
+    }
+}
+
+/// returns the commitment `A` and the response `z` of a signature
+fn sign_message(keypair: &KeyPair, randomness: &[u8; 32], message: &[u8]) -> (RistrettoPoint, Scalar) {
+    let mut r = derive_nonce(&keypair.private, randomness, message);
+    let A = r * RISTRETTO_BASEPOINT_POINT;
+    let d = hash_points(&[A]);
+    let e = hash_message(keypair.public, message);
+    let z = r * d - e * keypair.private;
+
+    r.zeroize();
+
+    (A, z)
+}
+
+fn hedged_randomness<T: RngCore + CryptoRng>(rng: &mut T) -> [u8; 32] {
+    let mut randomness = [0u8; 32];
+    rng.fill_bytes(&mut randomness);
+    randomness
+}
+
+/// derives a signature nonce from the private key, the message and
+/// optional randomness (all zeros for deterministic signatures)
+///
+/// the nonce stays unpredictable if the randomness is weak, as long as the
+/// private key is secret, and a good RNG protects against fault attacks
+/// on deterministic signatures
+fn derive_nonce(private: &Scalar, randomness: &[u8; 32], message: &[u8]) -> Scalar {
+    let mut key = private.to_bytes();
+    let h = Sha512::new()
+        .chain(NONCE_LABEL)
+        .chain(&key[..])
+        .chain(randomness)
+        .chain(message);
+    key.zeroize();
+
+    Scalar::from_hash(h)
+}
+
+const NONCE_LABEL: &[u8] = b"biscuit signature nonce\0";
+
 //FIXME: is the output value in the right set?
 fn hash_points(points: &[RistrettoPoint]) -> Scalar {
     let mut h = Sha512::new();
@@ -356,4 +471,98 @@ mod tests {
             "cannot verify third token"
         );
     }
+
+    #[test]
+    fn batch_verification() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+
+        let keypair1 = KeyPair::new(&mut rng);
+        let keypair2 = KeyPair::new(&mut rng);
+        let token1 = Token::new(&mut rng, &keypair1, &b"hello"[..]);
+        let token2 = token1.append(&mut rng, &keypair2, &b"world"[..]);
+        let token3 = Token::new(&mut rng, &keypair2, &b"!!!"[..]);
+
+        let batch = [&token1, &token2, &token3]
+            .iter()
+            .map(|t| (&t.keys[..], &t.messages[..], &t.signature))
+            .collect::<Vec<_>>();
+        assert_eq!(verify_batch(&mut rng, &batch), Ok(()));
+
+        let empty: [(&[PublicKey], &[Vec<u8>], &TokenSignature); 0] = [];
+        assert_eq!(verify_batch(&mut rng, &empty), Ok(()));
+
+        let mut invalid = token1.append(&mut rng, &keypair2, &b"world"[..]);
+        invalid.messages[1] = Vec::from(&b"you"[..]);
+        let batch = [&token1, &invalid, &token3]
+            .iter()
+            .map(|t| (&t.keys[..], &t.messages[..], &t.signature))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            verify_batch(&mut rng, &batch),
+            Err(error::Signature::InvalidSignature)
+        );
+
+        let batch = [(&token1.keys[..], &token2.messages[..], &token1.signature)];
+        assert_eq!(
+            verify_batch(&mut rng, &batch),
+            Err(error::Signature::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn deterministic_signature_vectors() {
+        let keypair1 = KeyPair::from(PrivateKey::from_bytes(&[1u8; 32]).unwrap());
+        let keypair2 = KeyPair::from(PrivateKey::from_bytes(&[2u8; 32]).unwrap());
+
+        let signature1 = TokenSignature::new_deterministic(&keypair1, &b"hello"[..]);
+        let signature2 = signature1.sign_deterministic(&keypair2, &b"world"[..]);
+
+        let parameters = signature2
+            .parameters
+            .iter()
+            .map(|A| hex::encode(A.compress().to_bytes()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parameters,
+            vec![
+                "94c48bf8ccf2dbfe79f9c71119714029c6f0b1b1d906805b8c19f032793bce19",
+                "9674393b7862c357d72110decd080f34d9d721748c7bb79a3173ea5e9944102d",
+            ]
+        );
+        assert_eq!(
+            hex::encode(signature1.z.to_bytes()),
+            "ab3c4187877cf39045279e652bcce1aa36f2076993ea16bb05da1d292e1ddd00"
+        );
+        assert_eq!(
+            hex::encode(signature2.z.to_bytes()),
+            "4441a9ee94ef39eb34762b8e489fbd6b1b333cbaf58b78f90aee2fb04504bc03"
+        );
+
+        assert_eq!(
+            signature2.verify(&[keypair1.public(), keypair2.public()], &[&b"hello"[..], &b"world"[..]]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn hedged_signature() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let keypair = KeyPair::from(PrivateKey::from_bytes(&[1u8; 32]).unwrap());
+
+        let deterministic = TokenSignature::new_deterministic(&keypair, &b"hello"[..]);
+        let signature1 = TokenSignature::new(&mut rng, &keypair, &b"hello"[..]);
+        let signature2 = TokenSignature::new(&mut rng, &keypair, &b"hello"[..]);
+        assert_ne!(signature1.parameters, deterministic.parameters);
+        assert_ne!(signature1.parameters, signature2.parameters);
+
+        // a broken RNG still produces a different nonce for each message
+        let mut broken = StdRng::seed_from_u64(1);
+        let signature3 = TokenSignature::new(&mut broken, &keypair, &b"hello"[..]);
+        let mut broken = StdRng::seed_from_u64(1);
+        let signature4 = TokenSignature::new(&mut broken, &keypair, &b"world"[..]);
+        assert_ne!(signature3.parameters, signature4.parameters);
+
+        assert_eq!(signature1.verify(&[keypair.public()], &[&b"hello"[..]]), Ok(()));
+        assert_eq!(signature4.verify(&[keypair.public()], &[&b"world"[..]]), Ok(()));
+    }
 }
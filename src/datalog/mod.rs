@@ -1,14 +1,20 @@
 //! Logic language implementation for caveats
-use std::collections::{HashMap, HashSet};
+use crate::error;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::AsRef;
 use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use regex::Regex;
 use chrono::{DateTime, NaiveDateTime, Utc};
 
+pub mod path;
+pub mod plan;
+pub mod regex_pattern;
+pub use plan::{fact_counts, PlanStep, RulePlan};
+pub use regex_pattern::RegexPattern;
+
 pub type Symbol = u64;
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
 pub enum ID {
     Symbol(Symbol),
     Variable(u32),
@@ -16,6 +22,8 @@ pub enum ID {
     Str(String),
     Date(u64),
     Bytes(Vec<u8>),
+    Bool(bool),
+    Set(BTreeSet<ID>),
 }
 
 impl From<&ID> for ID {
@@ -27,6 +35,8 @@ impl From<&ID> for ID {
             ID::Str(ref s) => ID::Str(s.clone()),
             ID::Date(ref d) => ID::Date(*d),
             ID::Bytes(ref b) => ID::Bytes(b.clone()),
+            ID::Bool(ref b) => ID::Bool(*b),
+            ID::Set(ref s) => ID::Set(s.clone()),
         }
     }
 }
@@ -97,6 +107,7 @@ pub enum ConstraintKind {
     Date(DateConstraint),
     Symbol(SymbolConstraint),
     Bytes(BytesConstraint),
+    Set(SetConstraint),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -117,7 +128,11 @@ pub enum StrConstraint {
     Equal(String),
     In(HashSet<String>),
     NotIn(HashSet<String>),
-    Regex(String),
+    Regex(RegexPattern),
+    /// the string is this path or a path under it, on segment boundaries
+    PathPrefix(String),
+    /// the string is a path matching this glob pattern
+    Glob(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -139,6 +154,14 @@ pub enum BytesConstraint {
     NotIn(HashSet<Vec<u8>>),
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetConstraint {
+    /// the set must contain all of those elements
+    Contains(BTreeSet<ID>),
+    /// the set must have at least one element in common with this one
+    Intersection(BTreeSet<ID>),
+}
+
 impl Constraint {
     pub fn check(&self, name: u32, id: &ID) -> bool {
         if name != self.id {
@@ -160,14 +183,9 @@ impl Constraint {
                 StrConstraint::Prefix(pref) => s.as_str().starts_with(pref.as_str()),
                 StrConstraint::Suffix(suff) => s.as_str().ends_with(suff.as_str()),
                 StrConstraint::Equal(s2) => s == s2,
-                StrConstraint::Regex(r) => {
-                  if let Some(re) = Regex::new(r).ok() {
-                    re.is_match(s)
-                  } else {
-                    // an invalid regex will never match
-                    false
-                  }
-                },
+                StrConstraint::Regex(r) => r.is_match(s),
+                StrConstraint::PathPrefix(prefix) => path::has_prefix(s, prefix),
+                StrConstraint::Glob(pattern) => path::glob_match(pattern, s),
                 StrConstraint::In(h) => h.contains(s),
                 StrConstraint::NotIn(h) => !h.contains(s),
             },
@@ -184,6 +202,10 @@ impl Constraint {
                 BytesConstraint::In(h) => h.contains(s),
                 BytesConstraint::NotIn(h) => !h.contains(s),
             },
+            (ID::Set(s), ConstraintKind::Set(c)) => match c {
+                SetConstraint::Contains(h) => h.is_subset(s),
+                SetConstraint::Intersection(h) => !h.is_disjoint(s),
+            },
             _ => false,
         }
     }
@@ -201,8 +223,79 @@ impl fmt::Display for Fact {
     }
 }
 
+/// returns the variables of `used`, from the head and the constraints of a
+/// rule, that are not bound by the variables of its body
+///
+/// the result is sorted and has no duplicates. This is shared by the datalog
+/// and builder rules, which identify variables by index and by name
+pub fn unbound_variables<T: Ord>(
+    used: impl Iterator<Item = T>,
+    body: impl Iterator<Item = T>,
+) -> Vec<T> {
+    let body = body.collect::<BTreeSet<_>>();
+    used.filter(|v| !body.contains(v))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
 impl Rule {
+    /// returns the variables that appear in the head or the constraints
+    /// but are not bound by any predicate of the body
+    ///
+    /// a rule is only safe to evaluate if this list is empty
+    pub fn unbound_variables(&self) -> Vec<u32> {
+        fn variables(pred: &Predicate) -> Vec<u32> {
+            pred.ids
+                .iter()
+                .filter_map(|id| match id {
+                    ID::Variable(i) => Some(*i),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        unbound_variables(
+            variables(&self.head).into_iter().chain(self.constraints.iter().map(|c| c.id)),
+            self.body.iter().flat_map(variables),
+        )
+    }
+
     pub fn apply(&self, facts: &HashSet<Fact>, new_facts: &mut Vec<Fact>) {
+        let plan = self.plan(facts);
+        self.apply_plan(&plan, facts, new_facts);
+    }
+
+    /// creates an execution plan for this rule from the current facts
+    pub fn plan(&self, facts: &HashSet<Fact>) -> RulePlan {
+        RulePlan::new(self, &fact_counts(facts))
+    }
+
+    /// applies the rule, joining the body predicates in the plan's order
+    pub fn apply_plan(&self, plan: &RulePlan, facts: &HashSet<Fact>, new_facts: &mut Vec<Fact>) {
+        self.apply_with(self.variables(), &plan.body(self), facts, facts, new_facts);
+    }
+
+    /// applies the rule, only producing facts for which at least one of the
+    /// body predicates was matched by a fact from `delta`
+    ///
+    /// `facts` must contain the facts from `delta`
+    pub fn apply_delta(
+        &self,
+        plan: &RulePlan,
+        facts: &HashSet<Fact>,
+        delta: &HashSet<Fact>,
+        new_facts: &mut Vec<Fact>,
+    ) {
+        for index in 0..self.body.len() {
+            // the predicate matched by the delta is joined first,
+            // the other ones follow the plan
+            let body = plan.body_starting_with(self, index);
+            self.apply_with(self.variables(), &body, delta, facts, new_facts);
+        }
+    }
+
+    fn variables(&self) -> MatchedVariables {
         let variables_set = self
             .body
             .iter()
@@ -220,28 +313,34 @@ impl Rule {
             })
             .collect::<HashSet<_>>();
 
-        let variables = MatchedVariables::new(variables_set);
+        MatchedVariables::new(variables_set)
+    }
 
+    fn apply_with(
+        &self,
+        variables: MatchedVariables,
+        body: &[Predicate],
+        first_facts: &HashSet<Fact>,
+        facts: &HashSet<Fact>,
+        new_facts: &mut Vec<Fact>,
+    ) {
         new_facts.extend(
-            CombineIt::new(variables, &self.body, &self.constraints, facts).map(|h| {
-                let mut p = self.head.clone();
-                for index in 0..p.ids.len() {
-                    let value = match &p.ids[index] {
-                        ID::Variable(i) => match h.get(i) {
-                          Some(val) => val,
-                          None => {
-                            println!("error: variables that appear in the head should appear in the body and constraints as well");
-                            continue;
-                          }
-                        },
-                        _ => continue,
-                    };
-
-                    p.ids[index] = value.clone();
-                }
+            CombineIt::with_first_facts(variables, body, &self.constraints, first_facts, facts)
+                .filter_map(|h| {
+                    let mut p = self.head.clone();
+                    for index in 0..p.ids.len() {
+                        let value = match &p.ids[index] {
+                            // unsafe rules are rejected when they are added, but rules
+                            // created directly in the datalog engine can still reach this
+                            ID::Variable(i) => h.get(i)?,
+                            _ => continue,
+                        };
+
+                        p.ids[index] = value.clone();
+                    }
 
-                Fact { predicate: p }
-            }),
+                    Some(Fact { predicate: p })
+                }),
         );
     }
 }
@@ -262,6 +361,18 @@ impl<'a> CombineIt<'a> {
         predicates: &'a [Predicate],
         constraints: &'a [Constraint],
         facts: &'a HashSet<Fact>,
+    ) -> Self {
+        CombineIt::with_first_facts(variables, predicates, constraints, facts, facts)
+    }
+
+    /// the first predicate is only matched against `first_facts`,
+    /// the following ones against `facts`
+    pub fn with_first_facts(
+        variables: MatchedVariables,
+        predicates: &'a [Predicate],
+        constraints: &'a [Constraint],
+        first_facts: &'a HashSet<Fact>,
+        facts: &'a HashSet<Fact>,
     ) -> Self {
         let p = predicates[0].clone();
         CombineIt {
@@ -270,7 +381,7 @@ impl<'a> CombineIt<'a> {
             constraints,
             all_facts: facts,
             current_facts: Box::new(
-                facts
+                first_facts
                     .iter()
                     .filter(move |fact| match_preds(&fact.predicate, &p)),
             ),
@@ -446,6 +557,14 @@ pub fn date(t: &SystemTime) -> ID {
     ID::Date(dur.as_secs())
 }
 
+pub fn boolean(b: bool) -> ID {
+    ID::Bool(b)
+}
+
+pub fn set(s: BTreeSet<ID>) -> ID {
+    ID::Set(s)
+}
+
 pub fn var(syms: &mut SymbolTable, name: &str) -> ID {
     let id = syms.insert(name);
     ID::Variable(id as u32)
@@ -465,10 +584,18 @@ pub fn match_preds(pred1: &Predicate, pred2: &Predicate) -> bool {
                 (ID::Integer(i), ID::Integer(j)) => i == j,
                 (ID::Str(i), ID::Str(j)) => i == j,
                 (ID::Date(i), ID::Date(j)) => i == j,
+                (ID::Bool(i), ID::Bool(j)) => i == j,
+                (ID::Set(i), ID::Set(j)) => i == j,
                 _ => false,
             })
 }
 
+/// maximum number of iterations of `World::run` and `World::run_incremental`
+///
+/// each iteration derives facts from those of the previous one, so this is
+/// the length of the longest chain of rule applications
+pub const MAX_ITERATIONS: usize = 100;
+
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct World {
     pub facts: HashSet<Fact>,
@@ -488,28 +615,147 @@ impl World {
         self.rules.push(rule);
     }
 
-    pub fn run(&mut self) {
+    /// applies the rules until no new facts are generated
+    ///
+    /// this stops with an error after `MAX_ITERATIONS` iterations, leaving
+    /// the facts generated so far in the world
+    pub fn run(&mut self) -> Result<(), error::Logic> {
+        let mut counts = fact_counts(&self.facts);
+
         let mut index = 0;
         loop {
-            let mut new_facts: Vec<Fact> = Vec::new();
-            for rule in self.rules.iter() {
-                rule.apply(&self.facts, &mut new_facts);
-                //println!("new_facts after applying {:?}:\n{:#?}", rule, new_facts);
-            }
+            // plans are created again at each iteration, since derived
+            // predicates can start without any fact and grow
+            let plans = self.plans_with_counts(&counts);
+            let new_facts = self.apply_rules(&plans, None);
 
             let len = self.facts.len();
-            self.facts.extend(new_facts.drain(..));
+            self.insert_facts(new_facts, &mut counts);
             if self.facts.len() == len {
-                break;
+                return Ok(());
             }
 
             index += 1;
-            if index == 100 {
-                panic!();
+            if index == MAX_ITERATIONS {
+                return Err(error::Logic::TooManyIterations(MAX_ITERATIONS));
             }
         }
     }
 
+    /// derives the consequences of `new_facts` in a world that already
+    /// reached its fixpoint (semi-naive evaluation)
+    ///
+    /// `new_facts` can already be in the world, but the other facts must be
+    /// the result of a previous call to `run` with the current rules, otherwise
+    /// some facts would not be generated
+    ///
+    /// like `run`, this stops with an error after `MAX_ITERATIONS` iterations
+    pub fn run_incremental(&mut self, new_facts: HashSet<Fact>) -> Result<(), error::Logic> {
+        self.facts.extend(new_facts.iter().cloned());
+        let mut delta = new_facts;
+        let mut counts = fact_counts(&self.facts);
+
+        let mut index = 0;
+        while !delta.is_empty() {
+            let plans = self.plans_with_counts(&counts);
+            delta = self
+                .apply_rules(&plans, Some(&delta))
+                .into_iter()
+                .filter(|fact| !self.facts.contains(fact))
+                .collect();
+            self.insert_facts(delta.iter().cloned(), &mut counts);
+
+            index += 1;
+            if index == MAX_ITERATIONS && !delta.is_empty() {
+                return Err(error::Logic::TooManyIterations(MAX_ITERATIONS));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// creates an execution plan for each rule, from the current facts
+    pub fn plans(&self) -> Vec<RulePlan> {
+        self.plans_with_counts(&fact_counts(&self.facts))
+    }
+
+    fn plans_with_counts(&self, counts: &HashMap<Symbol, usize>) -> Vec<RulePlan> {
+        self.rules
+            .iter()
+            .map(|rule| RulePlan::new(rule, counts))
+            .collect()
+    }
+
+    /// adds facts to the world, keeping the counts from `fact_counts` up to date
+    fn insert_facts(&mut self, facts: impl IntoIterator<Item = Fact>, counts: &mut HashMap<Symbol, usize>) {
+        for fact in facts {
+            let name = fact.predicate.name;
+            if self.facts.insert(fact) {
+                *counts.entry(name).or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// applies a rule once on the current facts, and only on consequences
+    /// of `delta` if it is present
+    fn apply_rule(
+        &self,
+        rule: &Rule,
+        plan: &RulePlan,
+        delta: Option<&HashSet<Fact>>,
+        new_facts: &mut Vec<Fact>,
+    ) {
+        match delta {
+            None => rule.apply_plan(plan, &self.facts, new_facts),
+            Some(delta) => rule.apply_delta(plan, &self.facts, delta, new_facts),
+        }
+    }
+
+    /// applies every rule once on the current facts
+    #[cfg(not(feature = "parallel"))]
+    fn apply_rules(&self, plans: &[RulePlan], delta: Option<&HashSet<Fact>>) -> Vec<Fact> {
+        self.apply_rules_sequential(plans, delta)
+    }
+
+    fn apply_rules_sequential(&self, plans: &[RulePlan], delta: Option<&HashSet<Fact>>) -> Vec<Fact> {
+        let mut new_facts: Vec<Fact> = Vec::new();
+        for (rule, plan) in self.rules.iter().zip(plans) {
+            self.apply_rule(rule, plan, delta, &mut new_facts);
+            //println!("new_facts after applying {:?}:\n{:#?}", rule, new_facts);
+        }
+
+        new_facts
+    }
+
+    /// applies every rule once on the current facts
+    ///
+    /// rules only read the facts produced by the previous iteration, so they
+    /// can be evaluated concurrently. The results are concatenated in the
+    /// order of the rules, to get the same output as the sequential version.
+    /// In a thread pool with a single thread, the sequential version is used
+    /// directly
+    #[cfg(feature = "parallel")]
+    fn apply_rules(&self, plans: &[RulePlan], delta: Option<&HashSet<Fact>>) -> Vec<Fact> {
+        use rayon::prelude::*;
+
+        if rayon::current_num_threads() == 1 {
+            return self.apply_rules_sequential(plans, delta);
+        }
+
+        let results: Vec<Vec<Fact>> = self
+            .rules
+            .par_iter()
+            .zip(plans.par_iter())
+            .map(|(rule, plan)| {
+                let mut new_facts = Vec::new();
+                self.apply_rule(rule, plan, delta, &mut new_facts);
+                new_facts
+            })
+            .collect();
+
+        results.into_iter().flatten().collect()
+    }
+
     pub fn query(&self, pred: Predicate) -> Vec<&Fact> {
         self.facts
             .iter()
@@ -525,6 +771,8 @@ impl World {
                             (ID::Integer(i), ID::Integer(ref j)) => i == j,
                             (ID::Str(i), ID::Str(ref j)) => i == j,
                             (ID::Date(i), ID::Date(ref j)) => i == j,
+                            (ID::Bool(i), ID::Bool(ref j)) => i == j,
+                            (ID::Set(i), ID::Set(ref j)) => i == j,
                             _ => false,
                         })
             })
@@ -532,8 +780,14 @@ impl World {
     }
 
     pub fn query_rule(&self, rule: Rule) -> Vec<Fact> {
+        self.query_rule_with_counts(rule, &fact_counts(&self.facts))
+    }
+
+    /// queries the world with the counts returned by `fact_counts` for its
+    /// current facts, to avoid counting them again for each query
+    pub fn query_rule_with_counts(&self, rule: Rule, counts: &HashMap<Symbol, usize>) -> Vec<Fact> {
         let mut new_facts: Vec<Fact> = Vec::new();
-        rule.apply(&self.facts, &mut new_facts);
+        rule.apply_plan(&RulePlan::new(&rule, counts), &self.facts, &mut new_facts);
         new_facts
     }
 }
@@ -592,21 +846,32 @@ impl SymbolTable {
         self.print_predicate(&f.predicate)
     }
 
+    pub fn print_id(&self, id: &ID) -> String {
+        match id {
+            ID::Variable(i) => format!("${}", self.print_symbol(*i as u64)),
+            ID::Integer(i) => i.to_string(),
+            ID::Str(s) => format!("\"{}\"", s),
+            ID::Symbol(index) => format!("#{}", self.print_symbol(*index as u64)),
+            ID::Date(d) => {
+                let t = UNIX_EPOCH + Duration::from_secs(*d);
+                format!("{:?}", t)
+            },
+            ID::Bytes(s) => format!("hex:{}", hex::encode(s)),
+            ID::Bool(b) => b.to_string(),
+            ID::Set(s) => self.print_set(s),
+        }
+    }
+
+    pub fn print_set(&self, s: &BTreeSet<ID>) -> String {
+        let elements = s.iter().map(|id| self.print_id(id)).collect::<Vec<_>>();
+        format!("[{}]", elements.join(", "))
+    }
+
     pub fn print_predicate(&self, p: &Predicate) -> String {
         let strings = p
             .ids
             .iter()
-            .map(|id| match id {
-                ID::Variable(i) => format!("${}", self.print_symbol(*i as u64)),
-                ID::Integer(i) => i.to_string(),
-                ID::Str(s) => format!("\"{}\"", s),
-                ID::Symbol(index) => format!("#{}", self.print_symbol(*index as u64)),
-                ID::Date(d) => {
-                    let t = UNIX_EPOCH + Duration::from_secs(*d);
-                    format!("{:?}", t)
-                },
-                ID::Bytes(s) => format!("hex:{}", hex::encode(s)),
-            })
+            .map(|id| self.print_id(id))
             .collect::<Vec<_>>();
         format!(
             "{}({})",
@@ -630,7 +895,9 @@ impl SymbolTable {
             ConstraintKind::Str(StrConstraint::Prefix(i)) => format!("${} matches {}*", self.print_symbol(c.id as u64), i),
             ConstraintKind::Str(StrConstraint::Suffix(i)) => format!("${} matches *{}", self.print_symbol(c.id as u64), i),
             ConstraintKind::Str(StrConstraint::Equal(i)) => format!("${} == {}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Str(StrConstraint::Regex(i)) => format!("${} matches /{}/", self.print_symbol(c.id as u64), i),
+            ConstraintKind::Str(StrConstraint::Regex(i)) => format!("${} matches /{}/", self.print_symbol(c.id as u64), i.as_str()),
+            ConstraintKind::Str(StrConstraint::PathPrefix(i)) => format!("${} under {:?}", self.print_symbol(c.id as u64), i),
+            ConstraintKind::Str(StrConstraint::Glob(i)) => format!("${} glob {:?}", self.print_symbol(c.id as u64), i),
             ConstraintKind::Str(StrConstraint::In(i)) => format!("${} in {:?}", self.print_symbol(c.id as u64), i),
             ConstraintKind::Str(StrConstraint::NotIn(i)) => format!("${} not in {:?}", self.print_symbol(c.id as u64), i),
             ConstraintKind::Date(DateConstraint::Before(i)) => {
@@ -654,9 +921,17 @@ impl SymbolTable {
                 format!("${} not in {:?}", self.print_symbol(c.id as u64), i.iter()
                         .map(|s| format!("hex:{}", hex::encode(s))).collect::<HashSet<_>>())
             },
+            ConstraintKind::Set(SetConstraint::Contains(s)) => {
+                format!("${} contains {}", self.print_symbol(c.id as u64), self.print_set(s))
+            },
+            ConstraintKind::Set(SetConstraint::Intersection(s)) => {
+                format!("${} intersection {}", self.print_symbol(c.id as u64), self.print_set(s))
+            },
         }
     }
 
+    /// debug output of a rule. For text that the parser reads back, convert
+    /// it with `builder::Rule::convert_from` and use its `Display` implementation
     pub fn print_rule(&self, r: &Rule) -> String {
         let res = self.print_predicate(&r.head);
         let preds: Vec<_> = r.body.iter().map(|p| self.print_predicate(p)).collect();
@@ -680,6 +955,29 @@ impl SymbolTable {
         )
     }
 
+    /// prints the rule with its body in the plan's order, and the
+    /// selectivity estimates used for each step
+    pub fn print_plan(&self, r: &Rule, p: &RulePlan) -> String {
+        let steps = p
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                format!(
+                    "  {}: {} (body[{}], constants: {}, bound: {}, facts: {})",
+                    i,
+                    self.print_predicate(&r.body[step.index]),
+                    step.index,
+                    step.constants,
+                    step.bound,
+                    step.facts
+                )
+            })
+            .collect::<Vec<_>>();
+
+        format!("{}\n{}", self.print_rule(r), steps.join("\n"))
+    }
+
     pub fn print_caveat(&self, c: &Caveat) -> String {
         let queries = c
             .queries
@@ -744,7 +1042,7 @@ mod tests {
         println!("adding r2: {}", syms.print_rule(&r2));
         w.add_rule(r2);
 
-        w.run();
+        w.run().unwrap();
 
         println!("parents:");
         let res = w.query(pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "child")]));
@@ -761,7 +1059,7 @@ mod tests {
             w.query(pred(grandparent, &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")]))
         );
         w.add_fact(fact(parent, &[&c, &e]));
-        w.run();
+        w.run().unwrap();
         let mut res = w.query(pred(grandparent, &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")]));
         println!("grandparents after inserting parent(C, E): {:?}", res);
 
@@ -780,7 +1078,7 @@ mod tests {
           pred(parent, &[var(parent), var("B")])
         ]));
 
-        w.run();
+        w.run().unwrap();
         println!("siblings: {:#?}", w.query(pred("siblings", &[var("A"), var("B")])));
         */
     }
@@ -917,6 +1215,74 @@ mod tests {
         assert_eq!(res2, compared);
     }
 
+    #[test]
+    fn regex() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let route = syms.insert("route");
+        let matching = syms.insert("matching");
+
+        for i in 0..1000 {
+            w.add_fact(fact(route, &[&int(i), &string(&format!("app{}.example.com", i))]));
+        }
+
+        let query = |syms: &mut SymbolTable, pattern: &str| {
+            w.query_rule(constrained_rule(
+                matching,
+                &[var(syms, "route_id")],
+                &[pred(route, &[var(syms, "route_id"), var(syms, "domain_name")])],
+                &[Constraint {
+                    id: syms.insert("domain_name") as u32,
+                    kind: ConstraintKind::Str(StrConstraint::Regex(RegexPattern::new(pattern).unwrap())),
+                }],
+            ))
+        };
+
+        let res = query(&mut syms, r"^app1[0-9]\.example\.com$");
+        assert_eq!(res.len(), 10);
+
+        assert!(RegexPattern::new(r"^app1[0-9]$").is_ok());
+        assert!(RegexPattern::new(r"^app1[0-9$").is_err());
+        assert!(RegexPattern::new(r"a{1000}{1000}").is_err());
+    }
+
+    #[test]
+    fn path_constraints() {
+        assert!(path::has_prefix("/a/file1", "/a/file1"));
+        assert!(path::has_prefix("/a/file1/b", "/a/file1"));
+        assert!(path::has_prefix("/a/file1/b", "/a/file1/"));
+        assert!(!path::has_prefix("/a/file1.txt", "/a/file1"));
+        assert!(!path::has_prefix("/a/file10", "/a/file1/"));
+        assert!(!path::has_prefix("/a/file1/../secret", "/a/file1"));
+        assert!(!path::has_prefix("/a/./file1", "/a"));
+        assert!(path::has_prefix("/anything", "/"));
+
+        let glob = "/buckets/*/objects/**";
+        assert!(path::glob_match(glob, "/buckets/b1/objects"));
+        assert!(path::glob_match(glob, "/buckets/b1/objects/o1"));
+        assert!(path::glob_match(glob, "/buckets/b1/objects/dir/o1"));
+        assert!(!path::glob_match(glob, "/buckets/b1/b2/objects/o1"));
+        assert!(!path::glob_match(glob, "/buckets/b1/metadata/o1"));
+        assert!(!path::glob_match(glob, "/buckets/b1/objects/../../b2/objects"));
+
+        assert!(path::glob_match("/files/*.txt", "/files/a.txt"));
+        assert!(path::glob_match("/files/file?.txt", "/files/file1.txt"));
+        assert!(!path::glob_match("/files/file?.txt", "/files/file10.txt"));
+        assert!(!path::glob_match("/files/*.txt", "/files/dir/a.txt"));
+        assert!(path::glob_match("/files/**/*.txt", "/files/dir/a.txt"));
+        assert!(path::glob_match("/files/**/*.txt", "/files/a.txt"));
+        assert!(path::glob_match("/**/a*b*c", "/x/y/aXbYc"));
+        assert!(!path::glob_match("/**/a*b*c", "/x/y/aXbYcZ"));
+
+        // many wildcards do not lead to exponential matching time
+        let pattern = "/**".repeat(50) + "/x";
+        let path = "/a".repeat(200);
+        assert!(!path::glob_match(&pattern, &path));
+        let segment = format!("/{}b", "*a".repeat(50));
+        assert!(!path::glob_match(&segment, &format!("/{}", "a".repeat(1000))));
+    }
+
     #[test]
     fn date_constraint() {
         let mut w = World::new();
@@ -1080,6 +1446,65 @@ mod tests {
         assert_eq!(res2, compared);
     }
 
+    #[test]
+    fn set_id() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let abc = syms.add("abc");
+        let def = syms.add("def");
+        let read = syms.add("read");
+        let write = syms.add("write");
+        let x = syms.insert("x");
+        let contains = syms.insert("contains");
+        let intersection = syms.insert("intersection");
+
+        w.add_fact(fact(x, &[&abc, &set([read.clone(), write.clone()].iter().cloned().collect()), &boolean(true)]));
+        w.add_fact(fact(x, &[&def, &set([read.clone()].iter().cloned().collect()), &boolean(false)]));
+
+        let res = w.query_rule(constrained_rule(
+            contains,
+            &[var(&mut syms, "sym")],
+            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "ops"), var(&mut syms, "b")])],
+            &[Constraint {
+                id: syms.insert("ops") as u32,
+                kind: ConstraintKind::Set(SetConstraint::Contains(
+                    [read.clone(), write.clone()].iter().cloned().collect(),
+                )),
+            }],
+        ));
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(contains, &[&abc])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+        let res = w.query_rule(constrained_rule(
+            intersection,
+            &[var(&mut syms, "sym")],
+            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "ops"), boolean(false)])],
+            &[Constraint {
+                id: syms.insert("ops") as u32,
+                kind: ConstraintKind::Set(SetConstraint::Intersection(
+                    [read.clone(), abc.clone()].iter().cloned().collect(),
+                )),
+            }],
+        ));
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(intersection, &[&def])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+    }
+
     #[test]
     fn resource() {
         let mut w = World::new();
@@ -1131,4 +1556,177 @@ mod tests {
 
         assert!(res.is_empty());
     }
+
+    #[test]
+    fn incremental() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let e = syms.add("E");
+        let parent = syms.insert("parent");
+        let ancestor = syms.insert("ancestor");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+
+        w.add_rule(rule(
+            ancestor,
+            &[var(&mut syms, "ancestor"), var(&mut syms, "child")],
+            &[pred(parent, &[var(&mut syms, "ancestor"), var(&mut syms, "child")])],
+        ));
+        w.add_rule(rule(
+            ancestor,
+            &[var(&mut syms, "ancestor"), var(&mut syms, "descendant")],
+            &[
+                pred(ancestor, &[var(&mut syms, "ancestor"), var(&mut syms, "child")]),
+                pred(parent, &[var(&mut syms, "child"), var(&mut syms, "descendant")]),
+            ],
+        ));
+
+        w.run().unwrap();
+
+        let new_facts = [fact(parent, &[&c, &d]), fact(parent, &[&d, &e])]
+            .iter()
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        let mut full = w.clone();
+        full.facts.extend(new_facts.iter().cloned());
+        full.run().unwrap();
+
+        w.run_incremental(new_facts).unwrap();
+
+        assert_eq!(w.facts, full.facts);
+        let res = w.query(pred(ancestor, &[&a, &e]));
+        assert_eq!(res.len(), 1);
+    }
+
+    #[test]
+    fn plan() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let admin = syms.add("admin");
+        let right = syms.insert("right");
+        let role = syms.insert("role");
+        let allowed = syms.insert("allowed");
+
+        for i in 0..100 {
+            w.add_fact(fact(right, &[&string(&format!("user{}", i)), &string(&format!("file{}", i))]));
+        }
+        w.add_fact(fact(role, &[&string("user1"), &admin]));
+        w.add_fact(fact(role, &[&string("user2"), &sym(&mut syms, "guest")]));
+
+        // the most general predicate is written first
+        let r = rule(
+            allowed,
+            &[var(&mut syms, "file")],
+            &[
+                pred(right, &[var(&mut syms, "user"), var(&mut syms, "file")]),
+                pred(role, &[var(&mut syms, "user"), admin.clone()]),
+            ],
+        );
+
+        let plan = r.plan(&w.facts);
+        println!("plan:\n{}", syms.print_plan(&r, &plan));
+        assert_eq!(
+            plan.steps,
+            vec![
+                PlanStep { index: 1, constants: 1, bound: 0, facts: 2 },
+                PlanStep { index: 0, constants: 0, bound: 1, facts: 100 },
+            ]
+        );
+        assert_eq!(
+            syms.print_plan(&r, &plan),
+            "*allowed($file) <- right($user, $file), role($user, #admin)\n  \
+             0: role($user, #admin) (body[1], constants: 1, bound: 0, facts: 2)\n  \
+             1: right($user, $file) (body[0], constants: 0, bound: 1, facts: 100)"
+        );
+
+        w.add_rule(r);
+        w.run().unwrap();
+
+        assert_eq!(w.facts.len(), 103);
+        assert!(w.facts.contains(&fact(allowed, &[&string("file1")])));
+    }
+
+    #[test]
+    fn too_many_iterations() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let next = syms.insert("next");
+        let reached = syms.insert("reached");
+
+        // each iteration reaches one more element of the chain
+        for i in 0..150 {
+            w.add_fact(fact(next, &[&int(i), &int(i + 1)]));
+        }
+        w.add_rule(rule(
+            reached,
+            &[var(&mut syms, "b")],
+            &[
+                pred(reached, &[var(&mut syms, "a")]),
+                pred(next, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+            ],
+        ));
+
+        let mut short = w.clone();
+        short.add_fact(fact(reached, &[&int(100)]));
+        short.run().unwrap();
+        assert!(short.facts.contains(&fact(reached, &[&int(150)])));
+
+        let mut long = w.clone();
+        long.add_fact(fact(reached, &[&int(0)]));
+        assert_eq!(long.run(), Err(error::Logic::TooManyIterations(MAX_ITERATIONS)));
+
+        let mut incremental = w.clone();
+        incremental.run().unwrap();
+        let new_facts = std::iter::once(fact(reached, &[&int(0)])).collect();
+        assert_eq!(
+            incremental.run_incremental(new_facts),
+            Err(error::Logic::TooManyIterations(MAX_ITERATIONS))
+        );
+    }
+
+    #[test]
+    fn plans_follow_derived_facts() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let base = syms.insert("base");
+        let derived = syms.insert("derived");
+        let selected = syms.insert("selected");
+        let out = syms.insert("out");
+
+        for i in 0..20 {
+            w.add_fact(fact(base, &[&int(i)]));
+        }
+        w.add_fact(fact(selected, &[&int(4)]));
+        w.add_rule(rule(derived, &[var(&mut syms, "x")], &[pred(base, &[var(&mut syms, "x")])]));
+        w.add_rule(rule(
+            out,
+            &[var(&mut syms, "x")],
+            &[
+                pred(derived, &[var(&mut syms, "x")]),
+                pred(selected, &[var(&mut syms, "x")]),
+            ],
+        ));
+
+        // without facts, the derived predicate is joined first
+        assert_eq!(w.plans()[1].steps[0].index, 0);
+
+        w.run().unwrap();
+        assert_eq!(w.query(pred(out, &[&int(4)])).len(), 1);
+        // the selective predicate is joined first once derived facts exist
+        assert_eq!(w.plans()[1].steps[0].index, 1);
+
+        let counts = fact_counts(&w.facts);
+        let query = rule(out, &[var(&mut syms, "x")], &[pred(out, &[var(&mut syms, "x")])]);
+        assert_eq!(w.query_rule_with_counts(query.clone(), &counts), w.query_rule(query));
+    }
 }
@@ -0,0 +1,97 @@
+//! path-aware matching for string constraints
+//!
+//! resources are often file-like paths, where a plain string prefix would let
+//! `/a/file1` match `/a/file1.txt`. Those functions compare paths segment by
+//! segment instead. Paths containing `.` or `..` segments never match, since
+//! they could designate a location outside of the allowed prefix.
+
+/// checks that the path is the prefix or a path under it
+///
+/// a trailing `/` in the prefix is ignored: `/a/` and `/a` both match `/a`
+/// and `/a/b`, but not `/ab`
+pub fn has_prefix(path: &str, prefix: &str) -> bool {
+    if has_dot_segments(path) {
+        return false;
+    }
+
+    let prefix = prefix.strip_suffix('/').unwrap_or(prefix);
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// matches a path against a glob pattern
+///
+/// in the pattern, `**` as a whole segment matches any number of segments,
+/// while `*` matches any sequence of characters and `?` any character
+/// inside a segment
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    if has_dot_segments(path) {
+        return false;
+    }
+
+    let pattern = pattern.split('/').collect::<Vec<_>>();
+    let path = path.split('/').collect::<Vec<_>>();
+
+    // reachable[j] is true if the pattern segments seen so far can match
+    // the first j segments of the path. Patterns come from tokens, so this
+    // avoids the exponential backtracking of a recursive implementation
+    let mut reachable = vec![false; path.len() + 1];
+    reachable[0] = true;
+
+    for p in pattern.iter() {
+        let mut next = vec![false; path.len() + 1];
+
+        if *p == "**" {
+            let mut any = false;
+            for j in 0..=path.len() {
+                any |= reachable[j];
+                next[j] = any;
+            }
+        } else {
+            for j in 0..path.len() {
+                if reachable[j] && segment_match(p, path[j]) {
+                    next[j + 1] = true;
+                }
+            }
+        }
+
+        reachable = next;
+    }
+
+    reachable[path.len()]
+}
+
+fn segment_match(pattern: &str, segment: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let segment = segment.chars().collect::<Vec<_>>();
+
+    let (mut i, mut j) = (0, 0);
+    // position of the last `*` in the pattern, and of the segment character
+    // where it started matching
+    let mut star: Option<(usize, usize)> = None;
+
+    while j < segment.len() {
+        if i < pattern.len() && (pattern[i] == '?' || pattern[i] == segment[j]) {
+            i += 1;
+            j += 1;
+        } else if i < pattern.len() && pattern[i] == '*' {
+            star = Some((i, j));
+            i += 1;
+        } else if let Some((star_i, star_j)) = star {
+            // the last `*` consumes one more character
+            i = star_i + 1;
+            j = star_j + 1;
+            star = Some((star_i, star_j + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[i..].iter().all(|c| *c == '*')
+}
+
+fn has_dot_segments(path: &str) -> bool {
+    path.split('/').any(|s| s == "." || s == "..")
+}
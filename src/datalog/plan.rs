@@ -0,0 +1,112 @@
+//! execution plans for rules
+//!
+//! the body predicates of a rule are joined one after the other, each one
+//! being matched with the variables fixed by the previous ones. Starting
+//! with the most selective predicates keeps the number of intermediate
+//! matches low, so the body is reordered before applying the rule.
+use super::{Fact, Predicate, Rule, Symbol, ID};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+/// one step of a rule plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+    /// index of the predicate in the rule's body
+    pub index: usize,
+    /// number of constant arguments in the predicate
+    pub constants: usize,
+    /// number of variables already bound by the previous steps
+    pub bound: usize,
+    /// number of facts with the predicate's name when the plan was created
+    pub facts: usize,
+}
+
+/// order in which the body predicates of a rule are joined
+///
+/// a plan is created from the facts present in the world. `World::run` creates
+/// new plans at each iteration, as the number of derived facts changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulePlan {
+    pub steps: Vec<PlanStep>,
+}
+
+impl RulePlan {
+    /// greedily selects the next predicate to join: predicates without
+    /// any matching fact first (the rule cannot produce anything), then
+    /// the ones with the most constant or bound arguments, then the ones
+    /// with the least facts. Ties keep the written order
+    pub fn new(rule: &Rule, fact_counts: &HashMap<Symbol, usize>) -> Self {
+        let mut remaining = (0..rule.body.len()).collect::<Vec<_>>();
+        let mut bound_variables = HashSet::new();
+        let mut steps = Vec::with_capacity(rule.body.len());
+
+        while !remaining.is_empty() {
+            let (position, step) = remaining
+                .iter()
+                .enumerate()
+                .map(|(position, index)| {
+                    let pred = &rule.body[*index];
+                    (position, PlanStep {
+                        index: *index,
+                        constants: pred.ids.iter().filter(|id| !is_variable(id)).count(),
+                        bound: pred
+                            .ids
+                            .iter()
+                            .filter(|id| match id {
+                                ID::Variable(i) => bound_variables.contains(i),
+                                _ => false,
+                            })
+                            .count(),
+                        facts: fact_counts.get(&pred.name).cloned().unwrap_or(0),
+                    })
+                })
+                .min_by_key(|(_, step)| {
+                    (step.facts != 0, Reverse(step.constants + step.bound), step.facts, step.index)
+                })
+                .unwrap();
+
+            remaining.remove(position);
+            bound_variables.extend(rule.body[step.index].ids.iter().filter_map(|id| match id {
+                ID::Variable(i) => Some(*i),
+                _ => None,
+            }));
+            steps.push(step);
+        }
+
+        RulePlan { steps }
+    }
+
+    /// the rule's body in the plan's order
+    pub fn body(&self, rule: &Rule) -> Vec<Predicate> {
+        self.steps
+            .iter()
+            .map(|step| rule.body[step.index].clone())
+            .collect()
+    }
+
+    /// the rule's body in the plan's order, except that the predicate at
+    /// `index` in the body is moved at the start
+    pub fn body_starting_with(&self, rule: &Rule, index: usize) -> Vec<Predicate> {
+        std::iter::once(rule.body[index].clone())
+            .chain(
+                self.steps
+                    .iter()
+                    .filter(|step| step.index != index)
+                    .map(|step| rule.body[step.index].clone()),
+            )
+            .collect()
+    }
+}
+
+/// number of facts for each predicate name
+pub fn fact_counts(facts: &HashSet<Fact>) -> HashMap<Symbol, usize> {
+    let mut counts = HashMap::new();
+    for fact in facts.iter() {
+        *counts.entry(fact.predicate.name).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn is_variable(id: &ID) -> bool {
+    matches!(id, ID::Variable(_))
+}
@@ -0,0 +1,45 @@
+//! compiled regular expressions for string constraints
+//!
+//! compiling a regular expression costs much more than matching it, so a
+//! regex constraint holds its compiled expression: the pattern is compiled
+//! once, when the constraint is created, and an invalid pattern cannot be
+//! represented
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+
+/// maximum size of a compiled regular expression, in bytes
+pub const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// a regular expression, compared and printed as its pattern
+#[derive(Clone)]
+pub struct RegexPattern(Regex);
+
+impl RegexPattern {
+    /// compiles a pattern, rejecting it if the compiled expression is too large
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        RegexBuilder::new(pattern)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map(RegexPattern)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        self.0.is_match(s)
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl fmt::Debug for RegexPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
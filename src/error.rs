@@ -13,16 +13,26 @@ pub enum Token {
     InvalidAuthorityIndex(u32),
     #[error("the block index does not match its position")]
     InvalidBlockIndex(InvalidBlockIndex),
+    /// no longer returned: blocks reuse the symbols already in the table
     #[error("multiple blocks declare the same symbols")]
     SymbolTableOverlap,
     #[error(r#"the symbol table is missing either "authority" or "ambient""#)]
     MissingSymbols,
-    #[error("tried to append a block to a sealed token")]
+    #[error("tried to append a block to a sealed token, or to compare it")]
     Sealed,
     #[error("caveat validation failed")]
     FailedLogic(Logic),
     #[error("Datalog parsing error")]
     ParseError,
+    #[error("a rule uses variables in its head or constraints that do not appear in its body")]
+    UnsafeRule(UnsafeRule),
+    #[error("the token holder did not prove possession of its key")]
+    Possession(Possession),
+    #[error("the parameters of a fact, rule or caveat do not match the provided values")]
+    Parameters(Parameters),
+    /// pretty print of the fact, rule or caveat
+    #[error("a set contains variables or other sets, or a set constraint is empty")]
+    InvalidSet(String),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -31,12 +41,22 @@ pub struct InvalidBlockIndex {
     pub found: u32,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnsafeRule {
+    /// pretty print of the rule
+    pub rule: String,
+    /// variables of the head or constraints that are not bound in the body
+    pub variables: Vec<String>,
+}
+
 #[derive(Error, Clone, Debug, PartialEq)]
 pub enum Format {
     #[error("failed verifying the signature")]
     Signature(Signature),
     #[error("failed verifying the signature of a sealed token")]
     SealedSignature,
+    #[error("the sealed token uses an unknown secret: {0}")]
+    UnknownSealingKey(u32),
     #[error("the token does not provide intermediate public keys")]
     EmptyKeys,
     #[error("the root public key was not recognized")]
@@ -49,6 +69,50 @@ pub enum Format {
     BlockDeserializationError(String),
     #[error("could not serialize the block")]
     BlockSerializationError(String),
+    #[error("a rule of the block uses variables that are not bound in its body")]
+    UnsafeRule(String),
+    #[error("the serialized token is too large: {0} bytes")]
+    TooLarge(usize),
+    #[error("the token has too many blocks: {0}")]
+    TooManyBlocks(usize),
+    #[error("the token declares too many symbols: {0}")]
+    TooManySymbols(usize),
+    #[error("a block has too many facts: {0}")]
+    TooManyFacts(usize),
+    #[error("a block has too many rules: {0}")]
+    TooManyRules(usize),
+    #[error("a block has too many caveats: {0}")]
+    TooManyCaveats(usize),
+    #[error("a string is too long: {0} bytes")]
+    StringTooLong(usize),
+    #[error("a regular expression is too large: {0} bytes")]
+    RegexTooLarge(usize),
+    #[error("a regular expression constraint is invalid")]
+    InvalidRegex(String),
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum Possession {
+    #[error("the token is not bound to a holder key")]
+    MissingHolderKey,
+    #[error("the token is bound to multiple holder keys")]
+    MultipleHolderKeys,
+    #[error("the holder key is not a valid public key")]
+    InvalidHolderKey,
+    #[error("the key pair does not match the holder key")]
+    KeyMismatch,
+    #[error("the challenge signature did not match the holder key")]
+    InvalidSignature,
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum Parameters {
+    #[error("no value was provided for those parameters")]
+    Missing(Vec<String>),
+    #[error("those values do not match any parameter")]
+    Unused(Vec<String>),
+    #[error("a parameter can only be bound to a value without variables or parameters, with a type matching its constraint")]
+    InvalidValue(String),
 }
 
 #[derive(Error, Clone, Debug, PartialEq)]
@@ -71,6 +135,9 @@ pub enum Logic {
     InvalidBlockRule(u32, String),
     #[error("list of caveats that failed validation")]
     FailedCaveats(Vec<FailedCaveat>),
+    /// rules kept generating new facts after this number of iterations
+    #[error("rules were still generating facts after {0} iterations")]
+    TooManyIterations(usize),
 }
 
 #[derive(Error, Clone, Debug, PartialEq)]
@@ -79,6 +146,19 @@ pub enum FailedCaveat {
     Block(FailedBlockCaveat),
     #[error("a caveat provided by the verifier failed")]
     Verifier(FailedVerifierCaveat),
+    /// an expiration caveat of a block failed, while the verifier provided
+    /// the current time
+    ///
+    /// those failures used to be reported as `FailedCaveat::Block`, code
+    /// matching on that variant for expiration caveats must handle this one
+    #[error("the token has expired")]
+    Expired(FailedBlockCaveat),
+    /// a not before caveat of a block failed, while the verifier provided the
+    /// current time
+    ///
+    /// like `Expired`, those failures used to be reported as `FailedCaveat::Block`
+    #[error("the token is not valid yet")]
+    NotYetValid(FailedBlockCaveat),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -2,10 +2,12 @@
 use crate::crypto::TokenSignature;
 use curve25519_dalek::{ristretto::CompressedRistretto, scalar::Scalar};
 
+use super::limits::DeserializationLimits;
 use super::schema;
 use crate::datalog::*;
 use crate::error;
-use crate::token::Block;
+use crate::token::{Block, SymbolsMode, SYMBOLS_VERSION_EXTENDED, SYMBOLS_VERSION_INITIAL};
+use std::collections::BTreeSet;
 
 pub fn token_sig_to_proto_sig(input: &TokenSignature) -> schema::Signature {
     schema::Signature {
@@ -66,28 +68,57 @@ pub fn token_block_to_proto_block(input: &Block) -> schema::Block {
         rules: input.rules.iter().map(token_rule_to_proto_rule).collect(),
         caveats: input.caveats.iter().map(token_caveat_to_proto_caveat).collect(),
         context: input.context.clone(),
+        version: if input.version == SYMBOLS_VERSION_INITIAL {
+            None
+        } else {
+            Some(input.version)
+        },
     }
 }
 
 pub fn proto_block_to_token_block(input: &schema::Block) -> Result<Block, error::Format> {
+    proto_block_to_token_block_with_limits(input, &DeserializationLimits::default())
+}
+
+pub fn proto_block_to_token_block_with_limits(
+    input: &schema::Block,
+    limits: &DeserializationLimits,
+) -> Result<Block, error::Format> {
+    limits.check_proto_block(input)?;
+
+    // the symbols of a block from a later version cannot be read correctly
+    let version = input.version.unwrap_or(SYMBOLS_VERSION_INITIAL);
+    if version > SYMBOLS_VERSION_EXTENDED {
+        return Err(error::Format::BlockDeserializationError(format!(
+            "unsupported block version: {}",
+            version
+        )));
+    }
+
     let mut facts = vec![];
     for fact in input.facts.iter() {
         facts.push(proto_fact_to_token_fact(fact)?);
     }
 
     let mut rules = vec![];
-    for rule in input.rules.iter() {
-        rules.push(proto_rule_to_token_rule(rule)?);
+    for (i, rule) in input.rules.iter().enumerate() {
+        let rule = proto_rule_to_token_rule(rule)?;
+        check_rule_variables(&rule, &format!("rule {}", i))?;
+        rules.push(rule);
     }
 
     let mut caveats = vec![];
-    for caveat in input.caveats.iter() {
-        caveats.push(proto_caveat_to_token_caveat(caveat)?);
+    for (i, caveat) in input.caveats.iter().enumerate() {
+        let caveat = proto_caveat_to_token_caveat(caveat)?;
+        for (j, query) in caveat.queries.iter().enumerate() {
+            check_rule_variables(query, &format!("caveat {} query {}", i, j))?;
+        }
+        caveats.push(caveat);
     }
 
     let context = input.context.clone();
 
-    Ok(Block {
+    let block = Block {
         index: input.index,
         symbols: SymbolTable {
             symbols: input.symbols.clone(),
@@ -96,7 +127,25 @@ pub fn proto_block_to_token_block(input: &schema::Block) -> Result<Block, error:
         rules,
         caveats,
         context,
-    })
+        version,
+        symbols_mode: SymbolsMode::Declared,
+    };
+
+    limits.check_block(&block)?;
+    Ok(block)
+}
+
+fn check_rule_variables(rule: &Rule, position: &str) -> Result<(), error::Format> {
+    let unbound = rule.unbound_variables();
+
+    if unbound.is_empty() {
+        Ok(())
+    } else {
+        Err(error::Format::UnsafeRule(format!(
+            "{} has unbound variables: {:?}",
+            position, unbound
+        )))
+    }
 }
 
 pub fn token_fact_to_proto_fact(input: &Fact) -> schema::Fact {
@@ -197,6 +246,8 @@ pub fn token_id_to_proto_id(input: &ID) -> schema::Id {
             str: None,
             date: None,
             bytes: None,
+            bool: None,
+            set: None,
         },
         ID::Variable(v) => schema::Id {
             kind: Kind::Variable as i32,
@@ -206,6 +257,8 @@ pub fn token_id_to_proto_id(input: &ID) -> schema::Id {
             str: None,
             date: None,
             bytes: None,
+            bool: None,
+            set: None,
         },
         ID::Integer(i) => schema::Id {
             kind: Kind::Integer as i32,
@@ -215,6 +268,8 @@ pub fn token_id_to_proto_id(input: &ID) -> schema::Id {
             str: None,
             date: None,
             bytes: None,
+            bool: None,
+            set: None,
         },
         ID::Str(s) => schema::Id {
             kind: Kind::Str as i32,
@@ -224,6 +279,8 @@ pub fn token_id_to_proto_id(input: &ID) -> schema::Id {
             str: Some(s.clone()),
             date: None,
             bytes: None,
+            bool: None,
+            set: None,
         },
         ID::Date(d) => schema::Id {
             kind: Kind::Date as i32,
@@ -233,6 +290,8 @@ pub fn token_id_to_proto_id(input: &ID) -> schema::Id {
             str: None,
             date: Some(*d),
             bytes: None,
+            bool: None,
+            set: None,
         },
         ID::Bytes(s) => schema::Id {
             kind: Kind::Bytes as i32,
@@ -242,6 +301,32 @@ pub fn token_id_to_proto_id(input: &ID) -> schema::Id {
             str: None,
             date: None,
             bytes: Some(s.clone()),
+            bool: None,
+            set: None,
+        },
+        ID::Bool(b) => schema::Id {
+            kind: Kind::Bool as i32,
+            symbol: None,
+            variable: None,
+            integer: None,
+            str: None,
+            date: None,
+            bytes: None,
+            bool: Some(*b),
+            set: None,
+        },
+        ID::Set(s) => schema::Id {
+            kind: Kind::Set as i32,
+            symbol: None,
+            variable: None,
+            integer: None,
+            str: None,
+            date: None,
+            bytes: None,
+            bool: None,
+            set: Some(schema::IdSet {
+                set: s.iter().map(token_id_to_proto_id).collect(),
+            }),
         },
     }
 }
@@ -288,6 +373,16 @@ pub fn proto_id_to_token_id(input: &schema::Id) -> Result<ID, error::Format> {
                 return Ok(ID::Bytes(s.clone()));
             }
         }
+        Kind::Bool => {
+            if let Some(b) = input.bool {
+                return Ok(ID::Bool(b));
+            }
+        }
+        Kind::Set => {
+            if let Some(ref s) = input.set {
+                return proto_set_to_token_set(&s.set).map(ID::Set);
+            }
+        }
     }
 
     Err(error::Format::DeserializationError(
@@ -295,6 +390,25 @@ pub fn proto_id_to_token_id(input: &schema::Id) -> Result<ID, error::Format> {
     ))
 }
 
+fn proto_set_to_token_set(input: &[schema::Id]) -> Result<BTreeSet<ID>, error::Format> {
+    let mut set = BTreeSet::new();
+
+    for id in input.iter() {
+        match proto_id_to_token_id(id)? {
+            ID::Variable(_) | ID::Set(_) => {
+                return Err(error::Format::DeserializationError(
+                    "deserialization error: sets cannot contain variables or other sets".to_string(),
+                ));
+            }
+            id => {
+                set.insert(id);
+            }
+        }
+    }
+
+    Ok(set)
+}
+
 pub fn token_constraint_to_proto_constraint(input: &Constraint) -> schema::Constraint {
     use schema::constraint::Kind;
 
@@ -307,6 +421,7 @@ pub fn token_constraint_to_proto_constraint(input: &Constraint) -> schema::Const
             date: None,
             symbol: None,
             bytes: None,
+            set: None,
         },
         ConstraintKind::Str(ref c) => schema::Constraint {
             id: input.id,
@@ -316,6 +431,7 @@ pub fn token_constraint_to_proto_constraint(input: &Constraint) -> schema::Const
             date: None,
             symbol: None,
             bytes: None,
+            set: None,
         },
         ConstraintKind::Date(ref c) => schema::Constraint {
             id: input.id,
@@ -325,6 +441,7 @@ pub fn token_constraint_to_proto_constraint(input: &Constraint) -> schema::Const
             date: Some(token_date_constraint_to_proto_date_constraint(c)),
             symbol: None,
             bytes: None,
+            set: None,
         },
         ConstraintKind::Symbol(ref c) => schema::Constraint {
             id: input.id,
@@ -334,6 +451,7 @@ pub fn token_constraint_to_proto_constraint(input: &Constraint) -> schema::Const
             date: None,
             symbol: Some(token_symbol_constraint_to_proto_symbol_constraint(c)),
             bytes: None,
+            set: None,
         },
         ConstraintKind::Bytes(ref c) => schema::Constraint {
             id: input.id,
@@ -343,7 +461,18 @@ pub fn token_constraint_to_proto_constraint(input: &Constraint) -> schema::Const
             date: None,
             symbol: None,
             bytes: Some(token_bytes_constraint_to_proto_bytes_constraint(c)),
-        }
+            set: None,
+        },
+        ConstraintKind::Set(ref c) => schema::Constraint {
+            id: input.id,
+            kind: Kind::Set as i32,
+            int: None,
+            str: None,
+            date: None,
+            symbol: None,
+            bytes: None,
+            set: Some(token_set_constraint_to_proto_set_constraint(c)),
+        },
     }
 }
 
@@ -401,6 +530,14 @@ pub fn proto_constraint_to_token_constraint(
                 });
             }
         }
+        Kind::Set => {
+            if let Some(ref i) = input.set {
+                return proto_set_constraint_to_token_set_constraint(i).map(|c| Constraint {
+                    id: input.id,
+                    kind: ConstraintKind::Set(c),
+                });
+            }
+        }
     }
 
     Err(error::Format::DeserializationError(
@@ -559,6 +696,8 @@ pub fn token_str_constraint_to_proto_str_constraint(
             regex: None,
             in_set: vec![],
             not_in_set: vec![],
+            path_prefix: None,
+            glob: None,
         },
         StrConstraint::Suffix(s) => schema::StringConstraint {
             kind: Kind::Suffix as i32,
@@ -568,6 +707,8 @@ pub fn token_str_constraint_to_proto_str_constraint(
             regex: None,
             in_set: vec![],
             not_in_set: vec![],
+            path_prefix: None,
+            glob: None,
         },
         StrConstraint::Equal(s) => schema::StringConstraint {
             kind: Kind::Equal as i32,
@@ -577,6 +718,8 @@ pub fn token_str_constraint_to_proto_str_constraint(
             regex: None,
             in_set: vec![],
             not_in_set: vec![],
+            path_prefix: None,
+            glob: None,
         },
         StrConstraint::Regex(r) => schema::StringConstraint {
             kind: Kind::Regex as i32,
@@ -585,7 +728,9 @@ pub fn token_str_constraint_to_proto_str_constraint(
             equal: None,
             in_set: vec![],
             not_in_set: vec![],
-            regex: Some(r.clone()),
+            regex: Some(r.as_str().to_string()),
+            path_prefix: None,
+            glob: None,
         },
         StrConstraint::In(s) => schema::StringConstraint {
             kind: Kind::In as i32,
@@ -595,6 +740,8 @@ pub fn token_str_constraint_to_proto_str_constraint(
             regex: None,
             in_set: s.iter().cloned().collect(),
             not_in_set: vec![],
+            path_prefix: None,
+            glob: None,
         },
         StrConstraint::NotIn(s) => schema::StringConstraint {
             kind: Kind::NotIn as i32,
@@ -604,6 +751,30 @@ pub fn token_str_constraint_to_proto_str_constraint(
             regex: None,
             in_set: vec![],
             not_in_set: s.iter().cloned().collect(),
+            path_prefix: None,
+            glob: None,
+        },
+        StrConstraint::PathPrefix(s) => schema::StringConstraint {
+            kind: Kind::PathPrefix as i32,
+            prefix: None,
+            suffix: None,
+            equal: None,
+            regex: None,
+            in_set: vec![],
+            not_in_set: vec![],
+            path_prefix: Some(s.clone()),
+            glob: None,
+        },
+        StrConstraint::Glob(s) => schema::StringConstraint {
+            kind: Kind::Glob as i32,
+            prefix: None,
+            suffix: None,
+            equal: None,
+            regex: None,
+            in_set: vec![],
+            not_in_set: vec![],
+            path_prefix: None,
+            glob: Some(s.clone()),
         },
     }
 }
@@ -639,7 +810,19 @@ pub fn proto_str_constraint_to_token_str_constraint(
         }
         Kind::Regex => {
             if let Some(ref r) = input.regex {
-                return Ok(StrConstraint::Regex(r.clone()));
+                return RegexPattern::new(r)
+                    .map(StrConstraint::Regex)
+                    .map_err(|e| error::Format::InvalidRegex(e.to_string()));
+            }
+        }
+        Kind::PathPrefix => {
+            if let Some(ref s) = input.path_prefix {
+                return Ok(StrConstraint::PathPrefix(s.clone()));
+            }
+        }
+        Kind::Glob => {
+            if let Some(ref s) = input.glob {
+                return Ok(StrConstraint::Glob(s.clone()));
             }
         }
         Kind::In => {
@@ -827,3 +1010,53 @@ pub fn proto_bytes_constraint_to_token_bytes_constraint(
         "deserialization error: invalid string constraint".to_string(),
     ))
 }
+
+pub fn token_set_constraint_to_proto_set_constraint(
+    input: &SetConstraint,
+) -> schema::SetConstraint {
+    use schema::set_constraint::Kind;
+
+    match input {
+        SetConstraint::Contains(s) => schema::SetConstraint {
+            kind: Kind::Contains as i32,
+            contains: s.iter().map(token_id_to_proto_id).collect(),
+            intersection: vec![],
+        },
+        SetConstraint::Intersection(s) => schema::SetConstraint {
+            kind: Kind::Intersection as i32,
+            contains: vec![],
+            intersection: s.iter().map(token_id_to_proto_id).collect(),
+        },
+    }
+}
+
+pub fn proto_set_constraint_to_token_set_constraint(
+    input: &schema::SetConstraint,
+) -> Result<SetConstraint, error::Format> {
+    use schema::set_constraint::Kind;
+
+    let kind = if let Some(i) = Kind::from_i32(input.kind) {
+        i
+    } else {
+        return Err(error::Format::DeserializationError(
+            "deserialization error: invalid set constraint kind".to_string(),
+        ));
+    };
+
+    match kind {
+        Kind::Contains => {
+            if !input.contains.is_empty() {
+                return proto_set_to_token_set(&input.contains).map(SetConstraint::Contains);
+            }
+        }
+        Kind::Intersection => {
+            if !input.intersection.is_empty() {
+                return proto_set_to_token_set(&input.intersection).map(SetConstraint::Intersection);
+            }
+        }
+    }
+
+    Err(error::Format::DeserializationError(
+        "deserialization error: invalid set constraint".to_string(),
+    ))
+}
@@ -0,0 +1,222 @@
+//! resource limits for token deserialization
+//!
+//! tokens usually come from untrusted sources like cookies or HTTP headers.
+//! Those limits are checked while decoding, so that a hostile token cannot
+//! make the verifier allocate or compute an unreasonable amount before it
+//! is rejected.
+use super::schema;
+use crate::datalog::{
+    BytesConstraint, Constraint, ConstraintKind, Predicate, Rule, SetConstraint, StrConstraint,
+    ID,
+};
+use crate::error;
+use crate::token::Block;
+
+/// limits applied when deserializing a token
+///
+/// the default values are large enough for any reasonable token
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeserializationLimits {
+    /// maximum size of the serialized token, in bytes
+    pub max_size: usize,
+    /// maximum number of blocks, including the authority block
+    pub max_blocks: usize,
+    /// maximum number of symbols declared by all the blocks
+    pub max_symbols: usize,
+    /// maximum number of facts in a block
+    pub max_facts: usize,
+    /// maximum number of rules in a block
+    pub max_rules: usize,
+    /// maximum number of caveats in a block
+    pub max_caveats: usize,
+    /// maximum length of strings, byte arrays, symbols and block contexts
+    pub max_string_length: usize,
+    /// maximum length of the pattern of a regular expression constraint
+    pub max_regex_size: usize,
+}
+
+impl Default for DeserializationLimits {
+    fn default() -> Self {
+        DeserializationLimits {
+            max_size: 1024 * 1024,
+            max_blocks: 1024,
+            max_symbols: 10_000,
+            max_facts: 10_000,
+            max_rules: 1_000,
+            max_caveats: 1_000,
+            max_string_length: 64 * 1024,
+            max_regex_size: 1024,
+        }
+    }
+}
+
+impl DeserializationLimits {
+    /// no limits, for tokens coming from a trusted source
+    pub fn unlimited() -> Self {
+        DeserializationLimits {
+            max_size: usize::MAX,
+            max_blocks: usize::MAX,
+            max_symbols: usize::MAX,
+            max_facts: usize::MAX,
+            max_rules: usize::MAX,
+            max_caveats: usize::MAX,
+            max_string_length: usize::MAX,
+            max_regex_size: usize::MAX,
+        }
+    }
+
+    pub(crate) fn check_size(&self, size: usize) -> Result<(), error::Format> {
+        if size > self.max_size {
+            Err(error::Format::TooLarge(size))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `count` includes the authority block
+    pub(crate) fn check_blocks(&self, count: usize) -> Result<(), error::Format> {
+        if count > self.max_blocks {
+            Err(error::Format::TooManyBlocks(count))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn check_symbols(&self, count: usize) -> Result<(), error::Format> {
+        if count > self.max_symbols {
+            Err(error::Format::TooManySymbols(count))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// checks the element counts of a block, before converting it
+    pub(crate) fn check_proto_block(&self, block: &schema::Block) -> Result<(), error::Format> {
+        self.check_symbols(block.symbols.len())?;
+
+        if block.facts.len() > self.max_facts {
+            return Err(error::Format::TooManyFacts(block.facts.len()));
+        }
+        if block.rules.len() > self.max_rules {
+            return Err(error::Format::TooManyRules(block.rules.len()));
+        }
+        if block.caveats.len() > self.max_caveats {
+            return Err(error::Format::TooManyCaveats(block.caveats.len()));
+        }
+
+        // regexes are compiled during the conversion, so their size is
+        // checked before
+        let rules = block
+            .rules
+            .iter()
+            .chain(block.caveats.iter().flat_map(|c| c.queries.iter()));
+        for constraint in rules.flat_map(|r| r.constraints.iter()) {
+            if let Some(r) = constraint.str.as_ref().and_then(|s| s.regex.as_ref()) {
+                if r.len() > self.max_regex_size {
+                    return Err(error::Format::RegexTooLarge(r.len()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// checks the strings and regular expressions of a converted block
+    pub(crate) fn check_block(&self, block: &Block) -> Result<(), error::Format> {
+        for symbol in block.symbols.symbols.iter() {
+            self.check_string(symbol.len())?;
+        }
+        if let Some(context) = block.context.as_ref() {
+            self.check_string(context.len())?;
+        }
+
+        for fact in block.facts.iter() {
+            self.check_predicate(&fact.predicate)?;
+        }
+        for rule in block.rules.iter() {
+            self.check_rule(rule)?;
+        }
+        for caveat in block.caveats.iter() {
+            for query in caveat.queries.iter() {
+                self.check_rule(query)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_rule(&self, rule: &Rule) -> Result<(), error::Format> {
+        self.check_predicate(&rule.head)?;
+        for predicate in rule.body.iter() {
+            self.check_predicate(predicate)?;
+        }
+        for constraint in rule.constraints.iter() {
+            self.check_constraint(constraint)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_predicate(&self, predicate: &Predicate) -> Result<(), error::Format> {
+        for id in predicate.ids.iter() {
+            self.check_id(id)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_id(&self, id: &ID) -> Result<(), error::Format> {
+        match id {
+            ID::Str(s) => self.check_string(s.len()),
+            ID::Bytes(b) => self.check_string(b.len()),
+            ID::Set(set) => {
+                for id in set.iter() {
+                    self.check_id(id)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_constraint(&self, constraint: &Constraint) -> Result<(), error::Format> {
+        match &constraint.kind {
+            ConstraintKind::Str(StrConstraint::Prefix(s))
+            | ConstraintKind::Str(StrConstraint::Suffix(s))
+            | ConstraintKind::Str(StrConstraint::Equal(s))
+            | ConstraintKind::Str(StrConstraint::PathPrefix(s))
+            | ConstraintKind::Str(StrConstraint::Glob(s)) => self.check_string(s.len()),
+            ConstraintKind::Str(StrConstraint::In(h)) | ConstraintKind::Str(StrConstraint::NotIn(h)) => {
+                for s in h.iter() {
+                    self.check_string(s.len())?;
+                }
+                Ok(())
+            }
+            // checked on the serialized block, before compilation
+            ConstraintKind::Str(StrConstraint::Regex(_)) => Ok(()),
+            ConstraintKind::Bytes(BytesConstraint::Equal(b)) => self.check_string(b.len()),
+            ConstraintKind::Bytes(BytesConstraint::In(h)) | ConstraintKind::Bytes(BytesConstraint::NotIn(h)) => {
+                for b in h.iter() {
+                    self.check_string(b.len())?;
+                }
+                Ok(())
+            }
+            ConstraintKind::Set(SetConstraint::Contains(set))
+            | ConstraintKind::Set(SetConstraint::Intersection(set)) => {
+                for id in set.iter() {
+                    self.check_id(id)?;
+                }
+                Ok(())
+            }
+            ConstraintKind::Int(_) | ConstraintKind::Date(_) | ConstraintKind::Symbol(_) => Ok(()),
+        }
+    }
+
+    fn check_string(&self, length: usize) -> Result<(), error::Format> {
+        if length > self.max_string_length {
+            Err(error::Format::StringTooLong(length))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -4,14 +4,14 @@
 //!
 //! - serialization of Biscuit blocks to Protobuf then `Vec<u8>`
 //! - serialization of a wrapper structure containing serialized blocks and the signature
-use super::crypto::{KeyPair, TokenSignature};
+use super::crypto::{self, KeyPair, TokenSignature};
 use crate::crypto::PublicKey;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use prost::Message;
 use rand_core::{CryptoRng, RngCore};
 
 use super::error;
-use super::token::Block;
+use super::token::{Block, SYMBOLS_VERSION_EXTENDED, SYMBOLS_VERSION_INITIAL};
 
 /// Structures generated from the Protobuf schema
 pub mod schema;/* {
@@ -19,8 +19,10 @@ pub mod schema;/* {
 }*/
 
 pub mod convert;
+pub mod limits;
 
 use self::convert::*;
+use self::limits::DeserializationLimits;
 
 /// Intermediate structure for token serialization
 ///
@@ -32,14 +34,26 @@ pub struct SerializedBiscuit {
     pub blocks: Vec<Vec<u8>>,
     pub keys: Vec<PublicKey>,
     pub signature: TokenSignature,
+    /// symbols version of the authority block. Since `SYMBOLS_VERSION_EXTENDED`
+    /// it is part of the signed data, see `signed_blocks`
+    pub version: u32,
 }
 
 impl SerializedBiscuit {
     pub fn from_slice(slice: &[u8]) -> Result<Self, error::Format> {
+        SerializedBiscuit::from_slice_with_limits(slice, &DeserializationLimits::default())
+    }
+
+    /// deserializes the token, rejecting it if it exceeds the limits
+    pub fn from_slice_with_limits(slice: &[u8], limits: &DeserializationLimits) -> Result<Self, error::Format> {
+        limits.check_size(slice.len())?;
+
         let data = schema::Biscuit::decode(slice).map_err(|e| {
             error::Format::DeserializationError(format!("deserialization error: {:?}", e))
         })?;
 
+        limits.check_blocks(1 + data.blocks.len())?;
+
         let mut keys = vec![];
 
         for key in data.keys {
@@ -61,11 +75,20 @@ impl SerializedBiscuit {
 
         let signature = proto_sig_to_token_sig(data.signature)?;
 
+        let version = data.version.unwrap_or(SYMBOLS_VERSION_INITIAL);
+        if version > SYMBOLS_VERSION_EXTENDED {
+            return Err(error::Format::DeserializationError(format!(
+                "deserialization error: unsupported version {}",
+                version
+            )));
+        }
+
         let deser = SerializedBiscuit {
             authority: data.authority,
             blocks: data.blocks,
             keys,
             signature,
+            version,
         };
 
         match deser.verify() {
@@ -85,6 +108,11 @@ impl SerializedBiscuit {
                 .map(|k| Vec::from(&k.0.compress().to_bytes()[..]))
                 .collect(),
             signature: token_sig_to_proto_sig(&self.signature),
+            version: if self.version == SYMBOLS_VERSION_INITIAL {
+                None
+            } else {
+                Some(self.version)
+            },
         }
     }
 
@@ -109,6 +137,13 @@ impl SerializedBiscuit {
         keypair: &KeyPair,
         authority: &Block,
     ) -> Result<Self, error::Format> {
+        if authority.version > SYMBOLS_VERSION_EXTENDED {
+            return Err(error::Format::SerializationError(format!(
+                "serialization error: unsupported version {}",
+                authority.version
+            )));
+        }
+
         let mut v = Vec::new();
         token_block_to_proto_block(authority)
             .encode(&mut v)
@@ -116,13 +151,14 @@ impl SerializedBiscuit {
                 error::Format::SerializationError(format!("serialization error: {:?}", e))
             })?;
 
-        let signature = TokenSignature::new(rng, keypair, &v);
+        let signature = TokenSignature::new(rng, keypair, &signed_data(authority.version, &v));
 
         Ok(SerializedBiscuit {
             authority: v,
             blocks: vec![],
             keys: vec![keypair.public()],
             signature,
+            version: authority.version,
         })
     }
 
@@ -140,17 +176,14 @@ impl SerializedBiscuit {
                 error::Format::SerializationError(format!("serialization error: {:?}", e))
             })?;
 
-        let mut blocks = Vec::new();
-        blocks.push(self.authority.clone());
-        blocks.extend(self.blocks.iter().cloned());
-
-        let signature = self.signature.sign(rng, keypair, &v);
+        let signature = self.signature.sign(rng, keypair, &signed_data(self.version, &v));
 
         let mut t = SerializedBiscuit {
             authority: self.authority.clone(),
             blocks: self.blocks.clone(),
             keys: self.keys.clone(),
             signature,
+            version: self.version,
         };
 
         t.blocks.push(v);
@@ -165,15 +198,53 @@ impl SerializedBiscuit {
             return Err(error::Format::EmptyKeys);
         }
 
-        let mut blocks = Vec::new();
-        blocks.push(self.authority.clone());
-        blocks.extend(self.blocks.iter().cloned());
-
         self.signature
-            .verify(&self.keys, &blocks)
+            .verify(&self.keys, &self.signed_blocks())
             .map_err(error::Format::Signature)
     }
 
+    /// checks the signatures of many deserialized tokens at once
+    ///
+    /// returns the result of the verification for each token. The tokens
+    /// are first verified as a batch, and only verified one by one to find
+    /// the invalid ones if the batch verification failed
+    pub fn verify_batch<T: RngCore + CryptoRng>(
+        rng: &mut T,
+        tokens: &[SerializedBiscuit],
+    ) -> Vec<Result<(), error::Format>> {
+        let blocks = tokens.iter().map(|t| t.signed_blocks()).collect::<Vec<_>>();
+
+        let batch = tokens
+            .iter()
+            .zip(blocks.iter())
+            .filter(|(t, _)| !t.keys.is_empty())
+            .map(|(t, b)| (&t.keys[..], &b[..], &t.signature))
+            .collect::<Vec<_>>();
+
+        if crypto::verify_batch(rng, &batch).is_ok() {
+            tokens
+                .iter()
+                .map(|t| {
+                    if t.keys.is_empty() {
+                        Err(error::Format::EmptyKeys)
+                    } else {
+                        Ok(())
+                    }
+                })
+                .collect()
+        } else {
+            tokens.iter().map(|t| t.verify()).collect()
+        }
+    }
+
+    /// the data signed for the authority and each block
+    fn signed_blocks(&self) -> Vec<Vec<u8>> {
+        std::iter::once(&self.authority)
+            .chain(self.blocks.iter())
+            .map(|b| signed_data(self.version, b))
+            .collect()
+    }
+
     pub fn check_root_key(&self, root: PublicKey) -> Result<(), error::Format> {
         if self.keys.is_empty() {
             return Err(error::Format::EmptyKeys);
@@ -185,3 +256,19 @@ impl SerializedBiscuit {
         Ok(())
     }
 }
+
+/// the data signed for a serialized block
+///
+/// tokens with the extended symbol table prefix each block with the version.
+/// Readers that ignore the version would read the symbols with the initial
+/// table, and their signature check fails instead. Tokens without a version
+/// keep the signature format from before it was introduced
+fn signed_data(version: u32, block: &[u8]) -> Vec<u8> {
+    if version == SYMBOLS_VERSION_INITIAL {
+        block.to_vec()
+    } else {
+        let mut data = version.to_le_bytes().to_vec();
+        data.extend_from_slice(block);
+        data
+    }
+}
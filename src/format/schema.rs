@@ -8,6 +8,8 @@ pub struct Biscuit {
     pub keys: ::std::vec::Vec<std::vec::Vec<u8>>,
     #[prost(message, required, tag="4")]
     pub signature: Signature,
+    #[prost(uint32, optional, tag="5")]
+    pub version: ::std::option::Option<u32>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct SealedBiscuit {
@@ -17,6 +19,26 @@ pub struct SealedBiscuit {
     pub blocks: ::std::vec::Vec<std::vec::Vec<u8>>,
     #[prost(bytes, required, tag="3")]
     pub signature: std::vec::Vec<u8>,
+    #[prost(uint32, optional, tag="4")]
+    pub key_id: ::std::option::Option<u32>,
+    #[prost(uint32, optional, tag="5")]
+    pub version: ::std::option::Option<u32>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EncryptedBiscuit {
+    #[prost(bytes, required, tag="1")]
+    pub nonce: std::vec::Vec<u8>,
+    #[prost(bytes, required, tag="2")]
+    pub ciphertext: std::vec::Vec<u8>,
+    #[prost(uint32, optional, tag="3")]
+    pub key_id: ::std::option::Option<u32>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SealedContent {
+    #[prost(bytes, required, tag="1")]
+    pub authority: std::vec::Vec<u8>,
+    #[prost(bytes, repeated, tag="2")]
+    pub blocks: ::std::vec::Vec<std::vec::Vec<u8>>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Signature {
@@ -39,6 +61,8 @@ pub struct Block {
     pub caveats: ::std::vec::Vec<Caveat>,
     #[prost(string, optional, tag="6")]
     pub context: ::std::option::Option<std::string::String>,
+    #[prost(uint32, optional, tag="7")]
+    pub version: ::std::option::Option<u32>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Fact {
@@ -82,6 +106,10 @@ pub struct Id {
     pub date: ::std::option::Option<u64>,
     #[prost(bytes, optional, tag="7")]
     pub bytes: ::std::option::Option<std::vec::Vec<u8>>,
+    #[prost(bool, optional, tag="8")]
+    pub bool: ::std::option::Option<bool>,
+    #[prost(message, optional, tag="9")]
+    pub set: ::std::option::Option<IdSet>,
 }
 pub mod id {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
@@ -93,9 +121,16 @@ pub mod id {
         Str = 3,
         Date = 4,
         Bytes = 5,
+        Bool = 6,
+        Set = 7,
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IdSet {
+    #[prost(message, repeated, tag="1")]
+    pub set: ::std::vec::Vec<Id>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Constraint {
     #[prost(uint32, required, tag="1")]
     pub id: u32,
@@ -111,6 +146,8 @@ pub struct Constraint {
     pub symbol: ::std::option::Option<SymbolConstraint>,
     #[prost(message, optional, tag="7")]
     pub bytes: ::std::option::Option<BytesConstraint>,
+    #[prost(message, optional, tag="8")]
+    pub set: ::std::option::Option<SetConstraint>,
 }
 pub mod constraint {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
@@ -121,6 +158,7 @@ pub mod constraint {
         Date = 2,
         Symbol = 3,
         Bytes = 4,
+        Set = 5,
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -171,6 +209,10 @@ pub struct StringConstraint {
     pub not_in_set: ::std::vec::Vec<std::string::String>,
     #[prost(string, optional, tag="7")]
     pub regex: ::std::option::Option<std::string::String>,
+    #[prost(string, optional, tag="8")]
+    pub path_prefix: ::std::option::Option<std::string::String>,
+    #[prost(string, optional, tag="9")]
+    pub glob: ::std::option::Option<std::string::String>,
 }
 pub mod string_constraint {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
@@ -182,6 +224,8 @@ pub mod string_constraint {
         In = 3,
         NotIn = 4,
         Regex = 5,
+        PathPrefix = 6,
+        Glob = 7,
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -238,3 +282,20 @@ pub mod bytes_constraint {
         NotIn = 2,
     }
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetConstraint {
+    #[prost(enumeration="set_constraint::Kind", required, tag="1")]
+    pub kind: i32,
+    #[prost(message, repeated, tag="2")]
+    pub contains: ::std::vec::Vec<Id>,
+    #[prost(message, repeated, tag="3")]
+    pub intersection: ::std::vec::Vec<Id>,
+}
+pub mod set_constraint {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Kind {
+        Contains = 0,
+        Intersection = 1,
+    }
+}
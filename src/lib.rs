@@ -53,8 +53,8 @@
 //!     biscuit.to_vec()?
 //!   };
 //!
-//!   // this token is only 266 bytes, holding the authority data and the signature
-//!   assert_eq!(token1.len(), 266);
+//!   // this token is only 257 bytes, holding the authority data and the signature
+//!   assert_eq!(token1.len(), 257);
 //!
 //!   // now let's add some restrictions to this token
 //!   // we want to limit access to `/a/file1.txt` and to read operations
@@ -91,8 +91,8 @@
 //!     biscuit.to_vec()?
 //!   };
 //!
-//!   // this new token fits in 404 bytes
-//!   assert_eq!(token2.len(), 404);
+//!   // this new token fits in 395 bytes
+//!   assert_eq!(token2.len(), 395);
 //!
 //!   /************** VERIFICATION ****************/
 //!
@@ -166,6 +166,7 @@
 //! - valid if current time is before January 1st 2030, 00h00mn00s UTC
 //! - source IP is in set [1.2.3.4, 5.6.7.8]
 //! - resource matches prefix "/home/biscuit/data/"
+//! - resource is under the path "/home/biscuit/data", or matches the glob "/buckets/*/objects/**"
 //!
 //! But it can also combine into more complex patterns, like: right is read if
 //! user has read or user is member of organisation and organisation has read right
@@ -191,6 +192,15 @@
 //! - *caveat() <- !time($0) @ $0 < 2019-02-05T23:00:00Z // expiration date
 //! - *caveat() <- !application($0), !operation($1), !user($2), !right(#app, $0, $1), !owner($2, $0), !credit($2, $3) @ $3 > 0 // verifies that the user owns the applications, the application has the right on the operation, there's a credit information for the operation, and the credit is larger than 0
 //!
+//! Facts, rules and caveats can contain parameters like `{user}`, that are replaced
+//! with typed values through methods like `BlockBuilder::add_fact_with_params`. This
+//! avoids formatting untrusted strings into Datalog code. A parameter can also
+//! appear after the operator of a constraint, like `$0 > {min}`: the type of its
+//! value then selects the constraint, an integer comparison here.
+//!
+//! The `biscuit-quote` crate provides the `fact!`, `rule!`, `caveat!` and `block!`
+//! macros, that parse the Datalog at compile time and fill parameters from Rust values.
+//!
 //! ## Symbols and symbol tables
 //!
 //! To reduce the size of tokens, the language supports a data type called "symbol".
@@ -204,7 +214,12 @@
 //! `#4 <- #0("file.txt"), #1(#2)` that would be printed as `*caveat1() <- !resource("file.txt"), !operation(#read)`
 //!
 //! biscuit implementations come with a default symbol table to avoid transmitting
-//! frequent values with every token.
+//! frequent values with every token. It contains `authority`, `ambient`, `resource`,
+//! `operation`, `right`, `current_time`, `revocation_id`, `read`, `write`, `time`
+//! and `expiration`. Tokens created before the last four were added do not have a
+//! version in their authority block, and are read with the initial table. When a
+//! block is added, symbols already present in the table are reused instead of being
+//! declared again.
 extern crate bytes;
 extern crate curve25519_dalek;
 extern crate hmac;
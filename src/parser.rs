@@ -1,17 +1,18 @@
 use crate::{datalog, error, token::builder};
 use nom::{
     branch::alt,
-    bytes::complete::{escaped_transform, tag, take_while1},
+    bytes::complete::{escaped_transform, tag, take_while, take_while1},
     character::{
-        complete::{char, digit1, space0},
+        complete::{char, digit1, multispace0, one_of, space0, space1},
         is_alphanumeric,
     },
     combinator::{map, map_opt, map_res, opt, recognize, value},
-    multi::separated_list1,
-    sequence::{delimited, pair, preceded},
+    multi::{many0, many1, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated},
     IResult,
 };
 use std::{
+    collections::BTreeSet,
     convert::{TryFrom, TryInto},
     str::FromStr,
     time::{Duration, SystemTime},
@@ -54,6 +55,52 @@ pub fn rule(i: &str) -> IResult<&str, builder::Rule> {
     Ok((i, builder::Rule(head, predicates, constraints)))
 }
 
+enum Statement {
+    Fact(builder::Fact),
+    Rule(builder::Rule),
+    Caveat(builder::Caveat),
+}
+
+fn statement(i: &str) -> IResult<&str, Statement> {
+    alt((
+        map(preceded(pair(tag("caveat"), space1), caveat), Statement::Caveat),
+        map(rule, Statement::Rule),
+        map(fact, Statement::Fact),
+    ))(i)
+}
+
+/// parses facts, rules and caveats separated by `;`, caveats being
+/// prefixed by the `caveat` keyword:
+///
+/// ```text
+/// right(#authority, "file1", #read);
+/// *can_read($0) <- right(#authority, $0, #read);
+/// caveat *check($0) <- resource(#ambient, $0), can_read($0);
+/// ```
+///
+/// the block builder has the index 0, it must be set before appending
+/// the block to a token
+pub fn block(i: &str) -> IResult<&str, builder::BlockBuilder> {
+    let (i, statements) = terminated(
+        separated_list0(
+            preceded(multispace0, char(';')),
+            preceded(multispace0, statement),
+        ),
+        pair(opt(preceded(multispace0, char(';'))), multispace0),
+    )(i)?;
+
+    let mut block = builder::BlockBuilder::new(0);
+    for statement in statements {
+        match statement {
+            Statement::Fact(f) => block.facts.push(f),
+            Statement::Rule(r) => block.rules.push(r),
+            Statement::Caveat(c) => block.caveats.push(c),
+        }
+    }
+
+    Ok((i, block))
+}
+
 impl TryFrom<&str> for builder::Fact {
     type Error = error::Token;
 
@@ -131,7 +178,7 @@ fn predicate(i: &str) -> IResult<&str, builder::Predicate> {
     let (i, _) = space0(i)?;
     let (i, ids) = delimited(
         char('('),
-        separated_list1(preceded(space0, char(',')), atom),
+        separated_list0(preceded(space0, char(',')), atom),
         preceded(space0, char(')')),
     )(i)?;
 
@@ -162,6 +209,12 @@ enum Operator {
     In,
     NotIn,
     Matches,
+    StartsWith,
+    EndsWith,
+    Under,
+    Glob,
+    Contains,
+    Intersection,
 }
 
 fn operator(i: &str) -> IResult<&str, Operator> {
@@ -171,20 +224,55 @@ fn operator(i: &str) -> IResult<&str, Operator> {
         value(Operator::Lower, tag("<")),
         value(Operator::Larger, tag(">")),
         value(Operator::Equal, tag("==")),
+        value(Operator::Intersection, tag("intersection")),
         value(Operator::In, tag("in")),
         value(Operator::NotIn, tag("not in")),
         value(Operator::Matches, tag("matches")),
+        value(Operator::StartsWith, tag("starts_with")),
+        value(Operator::EndsWith, tag("ends_with")),
+        value(Operator::Under, tag("under")),
+        value(Operator::Glob, tag("glob")),
+        value(Operator::Contains, tag("contains")),
     ))(i)
 }
 
+impl Operator {
+    /// operators accepting a parameter like `{min}` instead of a value
+    fn parameter_operator(&self) -> Option<builder::ConstraintOperator> {
+        use builder::ConstraintOperator as Op;
+
+        match self {
+            Operator::Lower => Some(Op::Lower),
+            Operator::Larger => Some(Op::Larger),
+            Operator::LowerOrEqual => Some(Op::LowerOrEqual),
+            Operator::LargerOrEqual => Some(Op::LargerOrEqual),
+            Operator::Equal => Some(Op::Equal),
+            Operator::In => Some(Op::In),
+            Operator::NotIn => Some(Op::NotIn),
+            Operator::StartsWith => Some(Op::StartsWith),
+            Operator::EndsWith => Some(Op::EndsWith),
+            Operator::Under => Some(Op::Under),
+            Operator::Contains => Some(Op::Contains),
+            Operator::Intersection => Some(Op::Intersection),
+            Operator::Matches | Operator::Glob => None,
+        }
+    }
+}
+
 fn constraint_kind(i: &str) -> IResult<&str, builder::ConstraintKind> {
     let (i, op) = delimited(space0, operator, space0)(i)?;
 
+    if let Some(operator) = op.parameter_operator() {
+        if let Ok((i, name)) = parameter_name(i) {
+            return Ok((i, builder::ConstraintKind::Parameter(operator, name.to_string())));
+        }
+    }
+
     match op {
-        Operator::Lower => map(parse_integer, |i| {
+        Operator::Lower => map(parse_integer_or_duration, |i| {
             builder::ConstraintKind::Integer(datalog::IntConstraint::Lower(i))
         })(i),
-        Operator::Larger => map(parse_integer, |i| {
+        Operator::Larger => map(parse_integer_or_duration, |i| {
             builder::ConstraintKind::Integer(datalog::IntConstraint::Larger(i))
         })(i),
         Operator::LowerOrEqual => alt((
@@ -193,7 +281,7 @@ fn constraint_kind(i: &str) -> IResult<&str, builder::ConstraintKind> {
                     SystemTime::UNIX_EPOCH + Duration::from_secs(d),
                 ))
             }),
-            map(parse_integer, |i| {
+            map(parse_integer_or_duration, |i| {
                 builder::ConstraintKind::Integer(datalog::IntConstraint::LowerOrEqual(i))
             }),
         ))(i),
@@ -203,7 +291,7 @@ fn constraint_kind(i: &str) -> IResult<&str, builder::ConstraintKind> {
                     SystemTime::UNIX_EPOCH + Duration::from_secs(d),
                 ))
             }),
-            map(parse_integer, |i| {
+            map(parse_integer_or_duration, |i| {
                 builder::ConstraintKind::Integer(datalog::IntConstraint::LargerOrEqual(i))
             }),
         ))(i),
@@ -335,11 +423,40 @@ fn constraint_kind(i: &str) -> IResult<&str, builder::ConstraintKind> {
                 }
                 None
             }),
-            map(
+            map_opt(
                 regex, //delimited(char('/'), parse_string_internal, char('/')),
-                |s| builder::ConstraintKind::String(datalog::StrConstraint::Regex(s)),
+                |s| {
+                    // invalid regexes are rejected here instead of never matching
+                    let r = datalog::RegexPattern::new(&s).ok()?;
+                    Some(builder::ConstraintKind::String(datalog::StrConstraint::Regex(r)))
+                },
             ),
         ))(i),
+        Operator::StartsWith => map(parse_string, |s| {
+            builder::ConstraintKind::String(datalog::StrConstraint::Prefix(s))
+        })(i),
+        Operator::EndsWith => map(parse_string, |s| {
+            builder::ConstraintKind::String(datalog::StrConstraint::Suffix(s))
+        })(i),
+        Operator::Under => map(parse_string, |s| {
+            builder::ConstraintKind::String(datalog::StrConstraint::PathPrefix(s))
+        })(i),
+        Operator::Glob => map(parse_string, |s| {
+            builder::ConstraintKind::String(datalog::StrConstraint::Glob(s))
+        })(i),
+        Operator::Contains => alt((
+            map(parse_constraint_set, |h| {
+                builder::ConstraintKind::Set(builder::SetConstraint::Contains(h))
+            }),
+            map(set_element, |a| {
+                builder::ConstraintKind::Set(builder::SetConstraint::Contains(
+                    std::iter::once(a).collect(),
+                ))
+            }),
+        ))(i),
+        Operator::Intersection => map(parse_constraint_set, |h| {
+            builder::ConstraintKind::Set(builder::SetConstraint::Intersection(h))
+        })(i),
     }
 }
 
@@ -366,7 +483,11 @@ fn parse_string_internal(i: &str) -> IResult<&str, String> {
 }
 
 fn parse_string(i: &str) -> IResult<&str, String> {
-    delimited(char('"'), parse_string_internal, char('"'))(i)
+    delimited(
+        char('"'),
+        map(opt(parse_string_internal), Option::unwrap_or_default),
+        char('"'),
+    )(i)
 }
 
 fn string(i: &str) -> IResult<&str, builder::Atom> {
@@ -388,9 +509,40 @@ fn integer(i: &str) -> IResult<&str, builder::Atom> {
     parse_integer(i).map(|(i, n)| (i, builder::int(n)))
 }
 
+/// a duration used as a value is an integer number of seconds, like `ttl(5m)`
+fn duration(i: &str) -> IResult<&str, builder::Atom> {
+    map_opt(parse_duration, |d| i64::try_from(d.as_secs()).ok().map(builder::int))(i)
+}
+
+fn parse_integer_or_duration(i: &str) -> IResult<&str, i64> {
+    alt((
+        map_opt(parse_duration, |d| i64::try_from(d.as_secs()).ok()),
+        parse_integer,
+    ))(i)
+}
+
+/// parses a RFC3339 date, optionally followed by a duration to add or subtract,
+/// like `2019-12-02T13:49:53Z + 5m`
 fn parse_date(i: &str) -> IResult<&str, u64> {
+    map_opt(
+        pair(
+            parse_rfc3339,
+            opt(pair(
+                delimited(space0, alt((char('+'), char('-'))), space0),
+                parse_duration,
+            )),
+        ),
+        |(date, offset)| match offset {
+            None => Some(date),
+            Some(('+', d)) => date.checked_add(d.as_secs()),
+            Some((_, d)) => date.checked_sub(d.as_secs()),
+        },
+    )(i)
+}
+
+fn parse_rfc3339(i: &str) -> IResult<&str, u64> {
     map_res(
-        map_res(take_while1(|c: char| c != ',' && c != ' '), |s| {
+        map_res(take_while1(|c: char| c != ',' && c != ' ' && c != ')' && c != ']' && c != ';'), |s| {
             let r = chrono::DateTime::parse_from_rfc3339(s);
             r
         }),
@@ -401,6 +553,55 @@ fn parse_date(i: &str) -> IResult<&str, u64> {
     )(i)
 }
 
+/// parses a duration, either as a list of amounts and units (`s`, `m`, `h`,
+/// `d` and `w`) like `1h30m`, or in the ISO 8601 format like `P1DT12H`
+///
+/// months and years are not supported since their length varies
+fn parse_duration(i: &str) -> IResult<&str, Duration> {
+    alt((
+        map_opt(
+            preceded(
+                char('P'),
+                pair(
+                    many0(pair(parse_u64, one_of("WD"))),
+                    opt(preceded(char('T'), many1(pair(parse_u64, one_of("HMS"))))),
+                ),
+            ),
+            |(mut parts, time)| {
+                parts.extend(time.unwrap_or_default());
+                duration_from_parts(&parts)
+            },
+        ),
+        map_opt(many1(pair(parse_u64, one_of("smhdw"))), |parts| {
+            duration_from_parts(&parts)
+        }),
+    ))(i)
+}
+
+fn parse_u64(i: &str) -> IResult<&str, u64> {
+    map_res(digit1, |s: &str| s.parse())(i)
+}
+
+fn duration_from_parts(parts: &[(u64, char)]) -> Option<Duration> {
+    if parts.is_empty() {
+        return None;
+    }
+
+    let mut seconds = 0u64;
+    for (amount, unit) in parts.iter() {
+        let unit = match unit.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => 604_800,
+        };
+        seconds = seconds.checked_add(amount.checked_mul(unit)?)?;
+    }
+
+    Some(Duration::from_secs(seconds))
+}
+
 fn date(i: &str) -> IResult<&str, builder::Atom> {
     parse_date(i).map(|(i, t)| (i, builder::Atom::Date(t)))
 }
@@ -409,7 +610,7 @@ fn parse_bytes(i: &str) -> IResult<&str, Vec<u8>> {
     preceded(
         tag("hex:"),
         map_res(
-            take_while1(|c| {
+            take_while(|c| {
                 let c = c as u8;
                 (b'0' <= c && c <= b'9')
                     || (b'a' <= c && c <= b'f')
@@ -424,6 +625,45 @@ fn bytes(i: &str) -> IResult<&str, builder::Atom> {
     parse_bytes(i).map(|(i, s)| (i, builder::Atom::Bytes(s)))
 }
 
+fn boolean(i: &str) -> IResult<&str, builder::Atom> {
+    alt((
+        value(builder::boolean(true), tag("true")),
+        value(builder::boolean(false), tag("false")),
+    ))(i)
+}
+
+/// sets cannot contain variables or other sets
+fn set_element(i: &str) -> IResult<&str, builder::Atom> {
+    preceded(space0, alt((symbol, string, boolean, date, duration, integer, bytes)))(i)
+}
+
+fn parse_set(i: &str) -> IResult<&str, BTreeSet<builder::Atom>> {
+    map(
+        delimited(
+            char('['),
+            separated_list0(preceded(space0, char(',')), set_element),
+            preceded(space0, char(']')),
+        ),
+        |mut v| v.drain(..).collect(),
+    )(i)
+}
+
+/// set constraints cannot be empty
+fn parse_constraint_set(i: &str) -> IResult<&str, BTreeSet<builder::Atom>> {
+    map(
+        delimited(
+            char('['),
+            separated_list1(preceded(space0, char(',')), set_element),
+            preceded(space0, char(']')),
+        ),
+        |mut v| v.drain(..).collect(),
+    )(i)
+}
+
+fn set(i: &str) -> IResult<&str, builder::Atom> {
+    parse_set(i).map(|(i, s)| (i, builder::set(s)))
+}
+
 fn variable(i: &str) -> IResult<&str, builder::Atom> {
     map(
         preceded(char('$'), name),
@@ -431,8 +671,20 @@ fn variable(i: &str) -> IResult<&str, builder::Atom> {
     )(i)
 }
 
+/// a value provided separately when adding a fact, rule or caveat
+fn parameter(i: &str) -> IResult<&str, builder::Atom> {
+    map(parameter_name, |s| builder::Atom::Parameter(s.to_string()))(i)
+}
+
+fn parameter_name(i: &str) -> IResult<&str, &str> {
+    delimited(char('{'), name, char('}'))(i)
+}
+
 fn atom(i: &str) -> IResult<&str, builder::Atom> {
-    preceded(space0, alt((symbol, string, date, variable, integer, bytes)))(i)
+    preceded(
+        space0,
+        alt((symbol, string, boolean, set, date, variable, parameter, duration, integer, bytes)),
+    )(i)
 }
 
 fn regex(i: &str) -> IResult<&str, String> {
@@ -455,7 +707,8 @@ fn regex(i: &str) -> IResult<&str, String> {
 #[cfg(test)]
 mod tests {
     use crate::{datalog, token::builder};
-    use std::collections::HashSet;
+    use std::collections::{BTreeSet, HashSet};
+    use std::time::Duration;
 
     #[test]
     fn name() {
@@ -490,6 +743,72 @@ mod tests {
             super::date("2019-12-02T13:49:53Z"),
             Ok(("", builder::Atom::Date(1575294593)))
         );
+        assert_eq!(
+            super::date("2019-12-02T13:49:53Z + 5m"),
+            Ok(("", builder::Atom::Date(1575294593 + 300)))
+        );
+        assert_eq!(
+            super::date("2019-12-02T13:49:53Z - P1DT1H"),
+            Ok(("", builder::Atom::Date(1575294593 - 90000)))
+        );
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(super::parse_duration("30s"), Ok(("", Duration::from_secs(30))));
+        assert_eq!(super::parse_duration("5m"), Ok(("", Duration::from_secs(300))));
+        assert_eq!(super::parse_duration("1h30m"), Ok(("", Duration::from_secs(5400))));
+        assert_eq!(super::parse_duration("2w"), Ok(("", Duration::from_secs(1_209_600))));
+        assert_eq!(super::parse_duration("P1D"), Ok(("", Duration::from_secs(86400))));
+        assert_eq!(super::parse_duration("PT5M"), Ok(("", Duration::from_secs(300))));
+        assert_eq!(super::parse_duration("P1W2DT3H4M5S"), Ok(("", Duration::from_secs(788_645))));
+        assert!(super::parse_duration("P").is_err());
+        assert!(super::parse_duration("5").is_err());
+        assert!(super::parse_duration("99999999999999999999w").is_err());
+
+        // outside of a date, a duration is a number of seconds
+        assert_eq!(super::atom("5m"), Ok(("", builder::int(300))));
+        assert_eq!(super::atom("P1D"), Ok(("", builder::int(86400))));
+        assert_eq!(super::atom("5"), Ok(("", builder::int(5))));
+        assert_eq!(
+            super::fact("ttl(1h30m)"),
+            Ok(("", builder::fact("ttl", &[builder::int(5400)])))
+        );
+        assert_eq!(
+            super::constraint("$ttl <= 1h"),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "ttl".to_string(),
+                    kind: builder::ConstraintKind::Integer(datalog::IntConstraint::LowerOrEqual(3600)),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn boolean() {
+        assert_eq!(super::boolean("true"), Ok(("", builder::boolean(true))));
+        assert_eq!(super::boolean("false"), Ok(("", builder::boolean(false))));
+    }
+
+    #[test]
+    fn set() {
+        let h = [builder::s("read"), builder::s("write")]
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(super::set("[#read, #write]"), Ok(("", builder::set(h))));
+
+        let h = [builder::int(1), builder::int(2), builder::int(3)]
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(super::set("[ 1, 2,3 ]"), Ok(("", builder::set(h))));
+
+        assert_eq!(super::set("[]"), Ok(("", builder::set(BTreeSet::new()))));
+        assert!(super::set("[$0, 1]").is_err());
+        assert!(super::set("[[1], 2]").is_err());
     }
 
     #[test]
@@ -497,6 +816,61 @@ mod tests {
         assert_eq!(super::variable("$1"), Ok(("", builder::variable("1"))));
     }
 
+    #[test]
+    fn block() {
+        let (remaining, block) = super::block(
+            r#"
+            right(#authority, "file1", #read);
+            *can_read($0) <- right(#authority, $0, #read);
+            caveat *check($0) <- resource(#ambient, $0), can_read($0) || *check($0) <- operation(#ambient, #write);
+            caveat(#authority);
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(
+            block.facts.iter().map(|f| f.to_string()).collect::<Vec<_>>(),
+            vec!["right(#authority, \"file1\", #read)", "caveat(#authority)"]
+        );
+        assert_eq!(
+            block.rules.iter().map(|r| r.to_string()).collect::<Vec<_>>(),
+            vec!["*can_read($0) <- right(#authority, $0, #read)"]
+        );
+        assert_eq!(block.caveats.len(), 1);
+        assert_eq!(block.caveats[0].queries.len(), 2);
+
+        assert_eq!(super::block("").unwrap().1.facts.len(), 0);
+        assert_eq!(super::block("fact(1) fact(2)").unwrap().0, "fact(2)");
+    }
+
+    #[test]
+    fn parameter() {
+        assert_eq!(
+            super::parameter("{user_id}"),
+            Ok(("", builder::Atom::Parameter("user_id".to_string())))
+        );
+        assert!(super::parameter("{user id}").is_err());
+
+        let fact = super::fact("right(#authority, {resource}, #read)").unwrap().1;
+        assert_eq!(fact.to_string(), "right(#authority, {resource}, #read)");
+
+        assert_eq!(
+            super::constraint("$0 not in {forbidden}"),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "0".to_string(),
+                    kind: builder::ConstraintKind::Parameter(
+                        builder::ConstraintOperator::NotIn,
+                        "forbidden".to_string()
+                    ),
+                }
+            ))
+        );
+        assert!(super::constraint("$0 matches {pattern}").is_err());
+    }
+
     #[test]
     fn constraint() {
         assert_eq!(
@@ -513,6 +887,20 @@ mod tests {
             ))
         );
 
+        assert_eq!(
+            super::constraint("$0 <= 2030-12-31T12:59:59+00:00 + 1h30m"),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "0".to_string(),
+                    kind: builder::ConstraintKind::Date(builder::DateConstraint::Before(
+                        std::time::SystemTime::UNIX_EPOCH
+                            + std::time::Duration::from_secs(1924952399 + 5400)
+                    )),
+                }
+            ))
+        );
+
         assert_eq!(
             super::constraint("$0 >= 2030-12-31T12:59:59+00:00"),
             Ok((
@@ -655,7 +1043,35 @@ mod tests {
                 builder::Constraint {
                     id: "0".to_string(),
                     kind: builder::ConstraintKind::String(datalog::StrConstraint::Regex(
-                        "abc[0-9]+".to_string()
+                        datalog::RegexPattern::new("abc[0-9]+").unwrap()
+                    )),
+                }
+            ))
+        );
+
+        assert!(super::constraint("$0 matches /abc[0-9+/").is_err());
+
+        assert_eq!(
+            super::constraint("$0 under \"/folder1/file1\""),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "0".to_string(),
+                    kind: builder::ConstraintKind::String(datalog::StrConstraint::PathPrefix(
+                        "/folder1/file1".to_string()
+                    )),
+                }
+            ))
+        );
+
+        assert_eq!(
+            super::constraint("$0 glob \"/buckets/*/objects/**\""),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "0".to_string(),
+                    kind: builder::ConstraintKind::String(datalog::StrConstraint::Glob(
+                        "/buckets/*/objects/**".to_string()
                     )),
                 }
             ))
@@ -712,6 +1128,48 @@ mod tests {
                 }
             ))
         );
+
+        let h = [builder::s("read")].iter().cloned().collect::<BTreeSet<_>>();
+        assert_eq!(
+            super::constraint("$0 contains #read"),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "0".to_string(),
+                    kind: builder::ConstraintKind::Set(builder::SetConstraint::Contains(h)),
+                }
+            ))
+        );
+
+        let h = [builder::s("read"), builder::s("write")]
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            super::constraint("$0 contains [#read, #write]"),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "0".to_string(),
+                    kind: builder::ConstraintKind::Set(builder::SetConstraint::Contains(h.clone())),
+                }
+            ))
+        );
+
+        assert_eq!(
+            super::constraint("$0 intersection [#read, #write]"),
+            Ok((
+                "",
+                builder::Constraint {
+                    id: "0".to_string(),
+                    kind: builder::ConstraintKind::Set(builder::SetConstraint::Intersection(h)),
+                }
+            ))
+        );
+
+        // set constraints cannot be empty
+        assert!(super::constraint("$0 contains []").is_err());
+        assert!(super::constraint("$0 intersection [ ]").is_err());
     }
 
     #[test]
@@ -732,6 +1190,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fact_with_set() {
+        let h = [builder::s("read"), builder::s("write")]
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            super::fact("operations(#authority, [#read, #write], true)"),
+            Ok((
+                "",
+                builder::fact(
+                    "operations",
+                    &[
+                        builder::s("authority"),
+                        builder::set(h),
+                        builder::boolean(true),
+                    ]
+                )
+            ))
+        );
+    }
+
     #[test]
     fn rule() {
         assert_eq!(
@@ -778,4 +1258,246 @@ mod tests {
             ))
         );
     }
+
+    /// random builder values, covering every kind of ID and constraint
+    /// that the parser can read
+    mod generate {
+        use crate::{datalog, token::builder};
+        use rand::{prelude::StdRng, Rng};
+        use std::{collections::BTreeSet, time::{Duration, SystemTime}};
+
+        /// latest date that RFC 3339 can represent, 9999-12-31T23:59:59Z
+        const MAX_DATE: u64 = 253_402_300_799;
+
+        /// names that the datalog can write, other ones are printed as is
+        /// and do not parse back, see `print_unsupported_names`
+        pub fn name(rng: &mut StdRng) -> String {
+            const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+            (0..rng.gen_range(1, 8))
+                .map(|_| CHARS[rng.gen_range(0, CHARS.len())] as char)
+                .collect()
+        }
+
+        pub fn string(rng: &mut StdRng) -> String {
+            const CHARS: &[char] = &[
+                'a', 'b', 'Z', '0', ' ', ',', '(', ')', '[', ']', '"', '\\', '\n', '\t', '*',
+                '/', '#', '$', '@', '|', ';', 'é', '→',
+            ];
+            (0..rng.gen_range(0, 10))
+                .map(|_| CHARS[rng.gen_range(0, CHARS.len())])
+                .collect()
+        }
+
+        pub fn regex(rng: &mut StdRng) -> String {
+            const PATTERNS: &[&str] = &[
+                "^a+$", "/a/b/", r"\d+", "x\"y", r"[a-z]*/\w", "\n?", r"\\", r"^/\*\.txt$",
+            ];
+            PATTERNS[rng.gen_range(0, PATTERNS.len())].to_string()
+        }
+
+        pub fn bytes(rng: &mut StdRng) -> Vec<u8> {
+            (0..rng.gen_range(0, 6)).map(|_| rng.gen()).collect()
+        }
+
+        pub fn date(rng: &mut StdRng) -> u64 {
+            rng.gen_range(0, MAX_DATE + 1)
+        }
+
+        pub fn system_time(rng: &mut StdRng) -> SystemTime {
+            SystemTime::UNIX_EPOCH + Duration::from_secs(date(rng))
+        }
+
+        /// values allowed in sets
+        pub fn set_element(rng: &mut StdRng) -> builder::Atom {
+            match rng.gen_range(0, 6) {
+                0 => builder::Atom::Symbol(name(rng)),
+                1 => builder::Atom::Str(string(rng)),
+                2 => builder::Atom::Bool(rng.gen()),
+                3 => builder::Atom::Date(date(rng)),
+                4 => builder::Atom::Integer(rng.gen()),
+                _ => builder::Atom::Bytes(bytes(rng)),
+            }
+        }
+
+        pub fn set(rng: &mut StdRng) -> BTreeSet<builder::Atom> {
+            (0..rng.gen_range(0, 4)).map(|_| set_element(rng)).collect()
+        }
+
+        /// set constraints cannot be empty
+        fn constraint_set(rng: &mut StdRng) -> BTreeSet<builder::Atom> {
+            (0..rng.gen_range(1, 4)).map(|_| set_element(rng)).collect()
+        }
+
+        pub fn atom(rng: &mut StdRng) -> builder::Atom {
+            match rng.gen_range(0, 8) {
+                0 => builder::Atom::Variable(name(rng)),
+                1 => builder::Atom::Set(set(rng)),
+                2 => builder::Atom::Parameter(name(rng)),
+                _ => set_element(rng),
+            }
+        }
+
+        pub fn predicate(rng: &mut StdRng) -> builder::Predicate {
+            builder::Predicate {
+                name: name(rng),
+                ids: (0..rng.gen_range(0, 4)).map(|_| atom(rng)).collect(),
+            }
+        }
+
+        fn non_empty<T, F: FnMut(&mut StdRng) -> T>(rng: &mut StdRng, mut f: F) -> Vec<T> {
+            (0..rng.gen_range(1, 4)).map(|_| f(rng)).collect()
+        }
+
+        pub fn constraint_kind(rng: &mut StdRng) -> builder::ConstraintKind {
+            use builder::ConstraintKind as K;
+            use datalog::{BytesConstraint as B, IntConstraint as I, StrConstraint as S};
+
+            match rng.gen_range(0, 25) {
+                0 => K::Integer(I::Lower(rng.gen())),
+                1 => K::Integer(I::Larger(rng.gen())),
+                2 => K::Integer(I::LowerOrEqual(rng.gen())),
+                3 => K::Integer(I::LargerOrEqual(rng.gen())),
+                4 => K::Integer(I::Equal(rng.gen())),
+                5 => K::Integer(I::In(non_empty(rng, |r| r.gen()).into_iter().collect())),
+                6 => K::Integer(I::NotIn(non_empty(rng, |r| r.gen()).into_iter().collect())),
+                7 => K::String(S::Prefix(string(rng))),
+                8 => K::String(S::Suffix(string(rng))),
+                9 => K::String(S::Equal(string(rng))),
+                10 => K::String(S::In(non_empty(rng, string).into_iter().collect())),
+                11 => K::String(S::NotIn(non_empty(rng, string).into_iter().collect())),
+                12 => K::String(S::Regex(datalog::RegexPattern::new(&regex(rng)).unwrap())),
+                13 => K::String(S::PathPrefix(string(rng))),
+                14 => K::String(S::Glob(string(rng))),
+                15 => K::Date(builder::DateConstraint::Before(system_time(rng))),
+                16 => K::Date(builder::DateConstraint::After(system_time(rng))),
+                17 => K::Symbol(builder::SymbolConstraint::In(non_empty(rng, name).into_iter().collect())),
+                18 => K::Symbol(builder::SymbolConstraint::NotIn(non_empty(rng, name).into_iter().collect())),
+                19 => K::Bytes(B::Equal(bytes(rng))),
+                20 => K::Bytes(B::In(non_empty(rng, bytes).into_iter().collect())),
+                21 => K::Bytes(B::NotIn(non_empty(rng, bytes).into_iter().collect())),
+                22 => K::Set(builder::SetConstraint::Contains(constraint_set(rng))),
+                23 => {
+                    use builder::ConstraintOperator as Op;
+                    let operators = [
+                        Op::Lower, Op::Larger, Op::LowerOrEqual, Op::LargerOrEqual, Op::Equal, Op::In,
+                        Op::NotIn, Op::StartsWith, Op::EndsWith, Op::Under, Op::Contains, Op::Intersection,
+                    ];
+                    K::Parameter(operators[rng.gen_range(0, operators.len())], name(rng))
+                }
+                _ => K::Set(builder::SetConstraint::Intersection(constraint_set(rng))),
+            }
+        }
+
+        pub fn rule(rng: &mut StdRng) -> builder::Rule {
+            builder::Rule(
+                predicate(rng),
+                non_empty(rng, predicate),
+                (0..rng.gen_range(0, 4))
+                    .map(|_| builder::Constraint {
+                        id: name(rng),
+                        kind: constraint_kind(rng),
+                    })
+                    .collect(),
+            )
+        }
+
+        pub fn caveat(rng: &mut StdRng) -> builder::Caveat {
+            builder::Caveat {
+                queries: non_empty(rng, rule),
+            }
+        }
+    }
+
+    #[test]
+    fn print_parse_round_trip() {
+        use rand::{prelude::StdRng, SeedableRng};
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+
+        for _ in 0..2000 {
+            let fact = builder::Fact(generate::predicate(&mut rng));
+            let text = fact.to_string();
+            assert_eq!(super::fact(&text), Ok(("", fact)), "fact: {}", text);
+
+            let rule = generate::rule(&mut rng);
+            let text = rule.to_string();
+            assert_eq!(super::rule(&text), Ok(("", rule)), "rule: {}", text);
+
+            let caveat = generate::caveat(&mut rng);
+            let text = caveat.to_string();
+            assert_eq!(super::caveat(&text), Ok(("", caveat)), "caveat: {}", text);
+        }
+
+        for _ in 0..200 {
+            let mut block = builder::BlockBuilder::new(0);
+            for _ in 0..3 {
+                block.facts.push(builder::Fact(generate::predicate(&mut rng)));
+                block.rules.push(generate::rule(&mut rng));
+                block.caveats.push(generate::caveat(&mut rng));
+            }
+
+            let text = block.to_string();
+            let (remaining, parsed) = super::block(&text).unwrap();
+            assert_eq!(remaining, "", "block: {}", text);
+            assert_eq!(parsed.facts, block.facts);
+            assert_eq!(parsed.rules, block.rules);
+            assert_eq!(parsed.caveats, block.caveats);
+        }
+    }
+
+    #[test]
+    fn print_unsupported_names() {
+        // names are not escaped, the round trip only holds for the ones
+        // made of ASCII letters, digits and `_`
+        let fact = builder::fact("right", &[builder::s("read write")]);
+        assert_eq!(fact.to_string(), "right(#read write)");
+        assert!(super::fact(&fact.to_string()).is_err());
+
+        let fact = builder::fact("can read", &[builder::s("read")]);
+        assert_ne!(super::fact(&fact.to_string()).map(|(_, f)| f), Ok(fact));
+
+        // the datalog has no dates before the Unix epoch
+        let rule = builder::constrained_rule(
+            "r",
+            &[builder::var("0")],
+            &[builder::pred("a", &[builder::var("0")])],
+            &[builder::Constraint {
+                id: "0".to_string(),
+                kind: builder::ConstraintKind::Date(builder::DateConstraint::Before(
+                    std::time::SystemTime::UNIX_EPOCH - std::time::Duration::from_secs(86400),
+                )),
+            }],
+        );
+        let text = rule.to_string();
+        assert_eq!(text, "*r($0) <- a($0) @ $0 <= 1969-12-31T00:00:00+00:00");
+        assert!(!matches!(super::rule(&text), Ok(("", _))));
+    }
+
+    #[test]
+    fn print_constraints() {
+        let rule = super::rule(
+            r#"*r($0) <- a($0) @ $0 matches "pre*", $0 matches "*suf", $0 in ["b", "a"], $0 matches /a\/b/"#,
+        )
+        .unwrap()
+        .1;
+        assert_eq!(
+            rule.to_string(),
+            r#"*r($0) <- a($0) @ $0 starts_with "pre", $0 ends_with "suf", $0 in ["a", "b"], $0 matches /a\/b/"#
+        );
+
+        // this was ambiguous with `matches "*a*"`
+        let rule = builder::constrained_rule(
+            "r",
+            &[builder::var("0")],
+            &[builder::pred("a", &[builder::var("0")])],
+            &[builder::Constraint {
+                id: "0".to_string(),
+                kind: builder::ConstraintKind::String(datalog::StrConstraint::Prefix("*a".to_string())),
+            }],
+        );
+        assert_eq!(rule.to_string(), r#"*r($0) <- a($0) @ $0 starts_with "*a""#);
+        assert_eq!(super::rule(&rule.to_string()), Ok(("", rule)));
+
+        let fact = builder::fact("a", &[builder::string("quote \" and \\ \n"), builder::Atom::Date(0)]);
+        assert_eq!(fact.to_string(), r#"a("quote \" and \\ \n", 1970-01-01T00:00:00+00:00)"#);
+    }
 }
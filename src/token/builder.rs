@@ -1,16 +1,16 @@
-use super::{Biscuit, Block};
-use crate::crypto::KeyPair;
+use super::{Biscuit, Block, SymbolsMode, SYMBOLS_VERSION_EXTENDED, SYMBOLS_VERSION_INITIAL};
+use crate::crypto::{KeyPair, PublicKey};
 use crate::datalog::{
     self, SymbolTable, ID,
 };
 use crate::error;
 use rand_core::{CryptoRng, RngCore};
-use std::{fmt, convert::{TryInto, TryFrom}, time::{SystemTime, Duration, UNIX_EPOCH}, collections::HashSet};
+use std::{fmt, convert::{TryInto, TryFrom}, time::{SystemTime, Duration, UNIX_EPOCH}, collections::{BTreeSet, HashMap, HashSet}};
 
 // reexport those because the builder uses the same definitions
-pub use crate::datalog::{IntConstraint, StrConstraint, BytesConstraint};
+pub use crate::datalog::{IntConstraint, StrConstraint, BytesConstraint, RegexPattern};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct BlockBuilder {
     pub index: u32,
     pub facts: Vec<Fact>,
@@ -32,26 +32,84 @@ impl BlockBuilder {
 
     pub fn add_fact<F: TryInto<Fact>>(&mut self, fact: F) -> Result<(), error::Token> {
         let fact = fact.try_into().map_err(|_| error::Token::ParseError)?;
+        fact.validate_parameters()?;
+        fact.validate_sets()?;
         self.facts.push(fact);
         Ok(())
     }
 
     pub fn add_rule<R: TryInto<Rule>>(&mut self, rule: R) -> Result<(), error::Token> {
         let rule = rule.try_into().map_err(|_| error::Token::ParseError)?;
+        rule.validate_parameters()?;
+        rule.validate_sets()?;
+        rule.validate_variables()?;
         self.rules.push(rule);
         Ok(())
     }
 
     pub fn add_caveat<C: TryInto<Caveat>>(&mut self, caveat: C) -> Result<(), error::Token> {
         let caveat = caveat.try_into().map_err(|_| error::Token::ParseError)?;
+        caveat.validate_parameters()?;
+        caveat.validate_sets()?;
+        caveat.validate_variables()?;
         self.caveats.push(caveat);
         Ok(())
     }
 
+    /// adds a fact containing `{name}` parameters, replaced by the values
+    /// of `params`
+    ///
+    /// ```rust
+    /// # use biscuit_auth::token::builder::{BlockBuilder, string};
+    /// # use std::collections::HashMap;
+    /// let mut block = BlockBuilder::new(1);
+    /// let mut params = HashMap::new();
+    /// params.insert("user".to_string(), string("user\", #admin"));
+    /// block.add_fact_with_params("user(#authority, {user})", &params).unwrap();
+    /// ```
+    pub fn add_fact_with_params<F: TryInto<Fact>>(
+        &mut self,
+        fact: F,
+        params: &HashMap<String, Atom>,
+    ) -> Result<(), error::Token> {
+        let mut fact = fact.try_into().map_err(|_| error::Token::ParseError)?;
+        fact.apply_parameters(params)?;
+        self.add_fact(fact)
+    }
+
+    /// adds a rule containing `{name}` parameters, replaced by the values
+    /// of `params`
+    pub fn add_rule_with_params<R: TryInto<Rule>>(
+        &mut self,
+        rule: R,
+        params: &HashMap<String, Atom>,
+    ) -> Result<(), error::Token> {
+        let mut rule = rule.try_into().map_err(|_| error::Token::ParseError)?;
+        rule.apply_parameters(params)?;
+        self.add_rule(rule)
+    }
+
+    /// adds a caveat containing `{name}` parameters, replaced by the values
+    /// of `params`
+    pub fn add_caveat_with_params<C: TryInto<Caveat>>(
+        &mut self,
+        caveat: C,
+        params: &HashMap<String, Atom>,
+    ) -> Result<(), error::Token> {
+        let mut caveat = caveat.try_into().map_err(|_| error::Token::ParseError)?;
+        caveat.apply_parameters(params)?;
+        self.add_caveat(caveat)
+    }
+
     pub fn set_context(&mut self, context: String) {
         self.context = Some(context);
     }
 
+    /// converts the elements to a block
+    ///
+    /// the `add_*` methods reject elements with unbound parameters, this
+    /// panics if one was pushed directly to the fields instead. They also
+    /// reject the sets that could not be deserialized
     pub fn build(self, mut symbols: SymbolTable) -> Block {
         let symbols_start = symbols.symbols.len();
 
@@ -80,6 +138,8 @@ impl BlockBuilder {
             rules,
             caveats,
             context: self.context,
+            version: SYMBOLS_VERSION_INITIAL,
+            symbols_mode: SymbolsMode::Declared,
         }
     }
 
@@ -145,6 +205,38 @@ impl BlockBuilder {
         let _ = self.add_caveat(caveat);
     }
 
+    /// restricts the resource to a path and the paths under it
+    ///
+    /// unlike `resource_prefix`, the prefix `/a/file1` does not match `/a/file1.txt`
+    pub fn resource_path_prefix(&mut self, prefix: &str) {
+        let caveat = constrained_rule(
+            "path_prefix",
+            &[var("resource")],
+            &[pred("resource", &[s("ambient"), var("resource")])],
+            &[Constraint {
+                id: "resource".to_string(),
+                kind: ConstraintKind::String(datalog::StrConstraint::PathPrefix(prefix.to_string())),
+            }],
+        );
+
+        let _ = self.add_caveat(caveat);
+    }
+
+    /// restricts the resource to paths matching a glob pattern, like `/buckets/*/objects/**`
+    pub fn resource_glob(&mut self, pattern: &str) {
+        let caveat = constrained_rule(
+            "glob",
+            &[var("resource")],
+            &[pred("resource", &[s("ambient"), var("resource")])],
+            &[Constraint {
+                id: "resource".to_string(),
+                kind: ConstraintKind::String(datalog::StrConstraint::Glob(pattern.to_string())),
+            }],
+        );
+
+        let _ = self.add_caveat(caveat);
+    }
+
     pub fn expiration_date(&mut self, date: SystemTime) {
         let caveat = constrained_rule(
             "expiration",
@@ -159,11 +251,61 @@ impl BlockBuilder {
         let _ = self.add_caveat(caveat);
     }
 
+    /// the token will not be valid before this date
+    pub fn not_before(&mut self, date: SystemTime) {
+        let caveat = constrained_rule(
+            "not_before",
+            &[var("date")],
+            &[pred("time", &[s("ambient"), var("date")])],
+            &[Constraint {
+                id: "date".to_string(),
+                kind: ConstraintKind::Date(DateConstraint::After(date)),
+            }],
+        );
+
+        let _ = self.add_caveat(caveat);
+    }
+
+    /// restricts the token to be used for a duration after this block is created
+    ///
+    /// this is a shorthand for `expiration_date(now + duration)`: constraints
+    /// compare a variable with a constant, so the caveat cannot be relative to
+    /// the issuance date, and the expiration date is computed here. The block
+    /// also gets an `issued_at(date)` fact with the current time. The caveat
+    /// does not use it, it records when the block was created for the
+    /// verifier's own rules and logs
+    pub fn valid_for(&mut self, duration: Duration) {
+        let now = SystemTime::now();
+        let _ = self.add_fact(fact("issued_at", &[date(&now)]));
+        self.expiration_date(now + duration);
+    }
+
     pub fn revocation_id(&mut self, id: i64) {
         let _ = self.add_fact(fact("revocation_id", &[int(id)]));
     }
 }
 
+/// prints the block in the format read by `parser::block`
+///
+/// the output parses back to the same values, except for names that the
+/// datalog cannot write: symbols, predicates and variables are printed as
+/// is, so their names must only contain ASCII letters, digits and `_`. Dates
+/// before the Unix epoch do not parse back either
+impl fmt::Display for BlockBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for fact in self.facts.iter() {
+            writeln!(f, "{};", fact)?;
+        }
+        for rule in self.rules.iter() {
+            writeln!(f, "{};", rule)?;
+        }
+        for caveat in self.caveats.iter() {
+            writeln!(f, "caveat {};", caveat)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct BiscuitBuilder<'a> {
     root: &'a KeyPair,
@@ -193,6 +335,8 @@ impl<'a> BiscuitBuilder<'a> {
 
     pub fn add_authority_fact<F: TryInto<Fact>>(&mut self, fact: F) -> Result<(), error::Token> {
         let fact = fact.try_into().map_err(|_| error::Token::ParseError)?;
+        fact.validate_parameters()?;
+        fact.validate_sets()?;
 
         let f = fact.convert(&mut self.symbols);
         self.facts.push(f);
@@ -201,6 +345,9 @@ impl<'a> BiscuitBuilder<'a> {
 
     pub fn add_authority_rule<Ru: TryInto<Rule>>(&mut self, rule: Ru) -> Result<(), error::Token> {
         let rule = rule.try_into().map_err(|_| error::Token::ParseError)?;
+        rule.validate_parameters()?;
+        rule.validate_sets()?;
+        rule.validate_variables()?;
 
         let r = rule.convert(&mut self.symbols);
         self.rules.push(r);
@@ -209,11 +356,50 @@ impl<'a> BiscuitBuilder<'a> {
 
     pub fn add_authority_caveat<Ru: TryInto<Rule>>(&mut self, rule: Ru) -> Result<(), error::Token> {
         let caveat = rule.try_into().map_err(|_| error::Token::ParseError)?;
+        caveat.validate_parameters()?;
+        caveat.validate_sets()?;
+        caveat.validate_variables()?;
         let r = caveat.convert(&mut self.symbols);
         self.caveats.push(datalog::Caveat { queries: vec![r]});
         Ok(())
     }
 
+    /// adds an authority fact containing `{name}` parameters, replaced by
+    /// the values of `params`
+    pub fn add_authority_fact_with_params<F: TryInto<Fact>>(
+        &mut self,
+        fact: F,
+        params: &HashMap<String, Atom>,
+    ) -> Result<(), error::Token> {
+        let mut fact = fact.try_into().map_err(|_| error::Token::ParseError)?;
+        fact.apply_parameters(params)?;
+        self.add_authority_fact(fact)
+    }
+
+    /// adds an authority rule containing `{name}` parameters, replaced by
+    /// the values of `params`
+    pub fn add_authority_rule_with_params<Ru: TryInto<Rule>>(
+        &mut self,
+        rule: Ru,
+        params: &HashMap<String, Atom>,
+    ) -> Result<(), error::Token> {
+        let mut rule = rule.try_into().map_err(|_| error::Token::ParseError)?;
+        rule.apply_parameters(params)?;
+        self.add_authority_rule(rule)
+    }
+
+    /// adds an authority caveat containing `{name}` parameters, replaced by
+    /// the values of `params`
+    pub fn add_authority_caveat_with_params<Ru: TryInto<Rule>>(
+        &mut self,
+        rule: Ru,
+        params: &HashMap<String, Atom>,
+    ) -> Result<(), error::Token> {
+        let mut rule = rule.try_into().map_err(|_| error::Token::ParseError)?;
+        rule.apply_parameters(params)?;
+        self.add_authority_caveat(rule)
+    }
+
     pub fn add_right(&mut self, resource: &str, right: &str) {
         let _ = self.add_authority_fact(fact(
             "right",
@@ -221,6 +407,28 @@ impl<'a> BiscuitBuilder<'a> {
         ));
     }
 
+    /// the token will not be valid before this date
+    pub fn not_before(&mut self, date: SystemTime) {
+        let _ = self.add_authority_caveat(constrained_rule(
+            "not_before",
+            &[var("date")],
+            &[pred("time", &[s("ambient"), var("date")])],
+            &[Constraint {
+                id: "date".to_string(),
+                kind: ConstraintKind::Date(DateConstraint::After(date)),
+            }],
+        ));
+    }
+
+    /// binds the token to a holder key: the verifier can then require the
+    /// holder to sign a challenge with the matching private key
+    ///
+    /// the binding is only read from the authority block, an attenuation
+    /// block cannot bind the token or change its holder
+    pub fn bind_holder(&mut self, key: &PublicKey) {
+        let _ = self.add_authority_fact(fact("holder", &[bytes(&key.to_bytes())]));
+    }
+
     pub fn set_context(&mut self, context: String) {
         self.context = Some(context);
     }
@@ -235,13 +443,15 @@ impl<'a> BiscuitBuilder<'a> {
             rules: self.rules,
             caveats: self.caveats,
             context: self.context,
+            version: SYMBOLS_VERSION_EXTENDED,
+            symbols_mode: SymbolsMode::Declared,
         };
 
         Biscuit::new(rng, self.root, self.symbols, authority_block)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
 pub enum Atom {
     Symbol(String),
     Variable(String),
@@ -249,6 +459,10 @@ pub enum Atom {
     Str(String),
     Date(u64),
     Bytes(Vec<u8>),
+    Bool(bool),
+    Set(BTreeSet<Atom>),
+    /// placeholder for a value provided separately, written `{name}`
+    Parameter(String),
 }
 
 impl Atom {
@@ -260,6 +474,64 @@ impl Atom {
             Atom::Str(s) => ID::Str(s.clone()),
             Atom::Date(d) => ID::Date(*d),
             Atom::Bytes(s) => ID::Bytes(s.clone()),
+            Atom::Bool(b) => ID::Bool(*b),
+            Atom::Set(s) => ID::Set(s.iter().map(|a| a.convert(symbols)).collect()),
+            // the builders reject facts and rules with unbound parameters,
+            // even inside sets, with `validate_parameters`
+            Atom::Parameter(p) => panic!("unbound parameter {{{}}}", p),
+        }
+    }
+
+    /// names of the parameters of the atom, including the ones inside sets
+    fn parameters(&self) -> Vec<&str> {
+        match self {
+            Atom::Parameter(p) => vec![p.as_str()],
+            Atom::Set(s) => s.iter().flat_map(|a| a.parameters()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// sets cannot contain variables or other sets
+    fn is_set_element(&self) -> bool {
+        !matches!(self, Atom::Variable(_) | Atom::Set(_))
+    }
+
+    /// checks the elements if the atom is a set
+    fn has_valid_sets(&self) -> bool {
+        match self {
+            Atom::Set(s) => s.iter().all(|a| a.is_set_element()),
+            _ => true,
+        }
+    }
+
+    /// a parameter value must not contain variables or parameters
+    fn is_value(&self) -> bool {
+        match self {
+            Atom::Variable(_) | Atom::Parameter(_) => false,
+            Atom::Set(s) => s.iter().all(|a| a.is_value()),
+            _ => true,
+        }
+    }
+
+    /// replaces the parameters, including the ones inside sets
+    ///
+    /// every parameter must have a value in `params`
+    fn apply_parameters(&mut self, params: &HashMap<String, Atom>) {
+        match self {
+            Atom::Parameter(p) => {
+                let value = params[p.as_str()].clone();
+                *self = value;
+            }
+            Atom::Set(s) => {
+                *s = std::mem::take(s)
+                    .into_iter()
+                    .map(|mut a| {
+                        a.apply_parameters(params);
+                        a
+                    })
+                    .collect();
+            }
+            _ => {}
         }
     }
 
@@ -271,6 +543,8 @@ impl Atom {
         ID::Str(s) => Atom::Str(s.clone()),
         ID::Date(d) => Atom::Date(*d),
         ID::Bytes(s) => Atom::Bytes(s.clone()),
+        ID::Bool(b) => Atom::Bool(*b),
+        ID::Set(s) => Atom::Set(s.iter().map(|id| Atom::convert_from(id, symbols)).collect()),
       }
     }
 }
@@ -284,10 +558,61 @@ impl From<&Atom> for Atom {
             Atom::Str(ref s) => Atom::Str(s.clone()),
             Atom::Date(ref d) => Atom::Date(*d),
             Atom::Bytes(ref s) => Atom::Bytes(s.clone()),
+            Atom::Bool(ref b) => Atom::Bool(*b),
+            Atom::Set(ref s) => Atom::Set(s.clone()),
+            Atom::Parameter(ref p) => Atom::Parameter(p.clone()),
         }
     }
 }
 
+impl From<i64> for Atom {
+    fn from(i: i64) -> Self {
+        Atom::Integer(i)
+    }
+}
+
+impl From<bool> for Atom {
+    fn from(b: bool) -> Self {
+        Atom::Bool(b)
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(s: &str) -> Self {
+        Atom::Str(s.to_string())
+    }
+}
+
+impl From<String> for Atom {
+    fn from(s: String) -> Self {
+        Atom::Str(s)
+    }
+}
+
+impl From<SystemTime> for Atom {
+    fn from(t: SystemTime) -> Self {
+        date(&t)
+    }
+}
+
+impl From<&[u8]> for Atom {
+    fn from(b: &[u8]) -> Self {
+        Atom::Bytes(b.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Atom {
+    fn from(b: Vec<u8>) -> Self {
+        Atom::Bytes(b)
+    }
+}
+
+impl From<BTreeSet<Atom>> for Atom {
+    fn from(s: BTreeSet<Atom>) -> Self {
+        Atom::Set(s)
+    }
+}
+
 impl AsRef<Atom> for Atom {
     fn as_ref(&self) -> &Atom {
         self
@@ -299,15 +624,87 @@ impl fmt::Display for Atom {
         match self {
             Atom::Variable(i) => write!(f, "${}", i),
             Atom::Integer(i) => write!(f, "{}", i),
-            Atom::Str(s) => write!(f, "\"{}\"", s),
+            Atom::Str(s) => write!(f, "{}", print_string(s)),
             Atom::Symbol(s) => write!(f, "#{}", s),
-            Atom::Date(d) => {
-                let t = UNIX_EPOCH + Duration::from_secs(*d);
-                write!(f, "{:?}", t)
-            }
+            Atom::Date(d) => write!(f, "{}", print_date(*d)),
             Atom::Bytes(s) => write!(f, "hex:{}", hex::encode(s)),
+            Atom::Bool(b) => write!(f, "{}", b),
+            Atom::Set(s) => write!(f, "{}", print_set(s)),
+            Atom::Parameter(p) => write!(f, "{{{}}}", p),
+        }
+
+    }
+}
+
+fn print_set(s: &BTreeSet<Atom>) -> String {
+    print_list(s.iter().map(|a| a.to_string()))
+}
+
+fn print_list<I: Iterator<Item = String>>(elements: I) -> String {
+    format!("[{}]", elements.collect::<Vec<_>>().join(", "))
+}
+
+/// sorts the elements of a hash set, to print them in a stable order
+fn sorted<T: Ord>(s: &HashSet<T>) -> Vec<&T> {
+    let mut v = s.iter().collect::<Vec<_>>();
+    v.sort();
+    v
+}
+
+/// prints a string literal, escaping the characters that the parser
+/// would interpret
+fn print_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn print_regex(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('/');
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '/' => res.push_str("\\/"),
+            '\n' => res.push_str("\\n"),
+            c => res.push(c),
         }
+    }
+    res.push('/');
+    res
+}
 
+/// prints a date in RFC 3339 format, in UTC
+///
+/// dates that chrono cannot represent are printed as a number of seconds,
+/// which will not parse back as a date
+fn print_date(secs: u64) -> String {
+    use chrono::TimeZone;
+
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| chrono::Utc.timestamp_opt(secs, 0).single())
+        .map(|date| date.to_rfc3339())
+        .unwrap_or_else(|| secs.to_string())
+}
+
+/// prints a date in RFC 3339 format, in UTC
+///
+/// the datalog only has dates after the Unix epoch: earlier ones are printed
+/// in the same format, but will not parse back
+fn print_system_time(date: &SystemTime) -> String {
+    match date.duration_since(UNIX_EPOCH) {
+        Ok(d) => print_date(d.as_secs()),
+        Err(_) => chrono::DateTime::<chrono::Utc>::from(*date).to_rfc3339(),
     }
 }
 
@@ -342,6 +739,138 @@ impl Predicate {
             ids: ids.to_vec(),
         }
     }
+
+    fn parameters(&self) -> impl Iterator<Item = &str> {
+        self.ids.iter().flat_map(|id| id.parameters())
+    }
+}
+
+/// replaces the parameters of the predicates and constraints with their values
+///
+/// every parameter must have a value, and every value must be used. A
+/// constraint parameter gets its kind from the type of its value, and
+/// `InvalidValue` is returned if the value does not fit the operator, or if
+/// a parameter inside a set is bound to another set
+fn apply_parameters(
+    mut predicates: Vec<&mut Predicate>,
+    mut constraints: Vec<&mut Constraint>,
+    params: &HashMap<String, Atom>,
+) -> Result<(), error::Token> {
+    if let Some((name, _)) = params.iter().find(|(_, value)| !value.is_value()) {
+        return Err(error::Token::Parameters(error::Parameters::InvalidValue(
+            name.clone(),
+        )));
+    }
+
+    let used = predicates
+        .iter()
+        .flat_map(|p| p.parameters())
+        .chain(constraints.iter().flat_map(|c| c.parameters()))
+        .map(|p| p.to_string())
+        .collect::<BTreeSet<_>>();
+
+    let missing = used
+        .iter()
+        .filter(|p| !params.contains_key(*p))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(error::Token::Parameters(error::Parameters::Missing(missing)));
+    }
+
+    let mut unused = params
+        .keys()
+        .filter(|p| !used.contains(*p))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !unused.is_empty() {
+        unused.sort();
+        return Err(error::Token::Parameters(error::Parameters::Unused(unused)));
+    }
+
+    // sets cannot contain other sets
+    if let Some(name) = predicates
+        .iter()
+        .flat_map(|p| p.ids.iter().filter(|id| matches!(id, Atom::Set(_))))
+        .flat_map(|id| id.parameters())
+        .chain(
+            constraints
+                .iter()
+                .filter(|c| !matches!(c.kind, ConstraintKind::Parameter(..)))
+                .flat_map(|c| c.parameters()),
+        )
+        .find(|name| matches!(params[*name], Atom::Set(_)))
+    {
+        return Err(error::Token::Parameters(error::Parameters::InvalidValue(
+            name.to_string(),
+        )));
+    }
+
+    // constraint parameters are resolved before modifying anything, so that
+    // the elements are left unchanged on error
+    let mut kinds = Vec::new();
+    for constraint in constraints.iter() {
+        kinds.push(match &constraint.kind {
+            ConstraintKind::Parameter(operator, name) => Some(
+                ConstraintKind::from_parameter(operator, &params[name.as_str()]).ok_or_else(
+                    || error::Token::Parameters(error::Parameters::InvalidValue(name.clone())),
+                )?,
+            ),
+            _ => None,
+        });
+    }
+
+    for predicate in predicates.iter_mut() {
+        for id in predicate.ids.iter_mut() {
+            id.apply_parameters(params);
+        }
+    }
+
+    for (constraint, kind) in constraints.iter_mut().zip(kinds) {
+        match kind {
+            Some(kind) => constraint.kind = kind,
+            None => constraint.kind.apply_parameters(params),
+        }
+    }
+
+    Ok(())
+}
+
+/// returns an error listing the parameters that were not replaced
+fn validate_parameters<'a>(
+    predicates: impl Iterator<Item = &'a Predicate>,
+    constraints: impl Iterator<Item = &'a Constraint>,
+) -> Result<(), error::Token> {
+    let missing = predicates
+        .flat_map(|p| p.parameters())
+        .chain(constraints.flat_map(|c| c.parameters()))
+        .map(|p| p.to_string())
+        .collect::<BTreeSet<_>>();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(error::Token::Parameters(error::Parameters::Missing(
+            missing.into_iter().collect(),
+        )))
+    }
+}
+
+/// returns an error if a set of the predicates or constraints could not be
+/// deserialized: sets cannot contain variables or other sets, and set
+/// constraints cannot be empty
+fn validate_sets<'a>(
+    mut predicates: impl Iterator<Item = &'a Predicate>,
+    mut constraints: impl Iterator<Item = &'a Constraint>,
+    element: &dyn fmt::Display,
+) -> Result<(), error::Token> {
+    if predicates.all(|p| p.ids.iter().all(|id| id.has_valid_sets()))
+        && constraints.all(|c| c.has_valid_sets())
+    {
+        Ok(())
+    } else {
+        Err(error::Token::InvalidSet(element.to_string()))
+    }
 }
 
 impl AsRef<Predicate> for Predicate {
@@ -387,6 +916,21 @@ impl Fact {
     pub fn convert_from(f: &datalog::Fact, symbols: &SymbolTable) -> Self {
         Fact(Predicate::convert_from(&f.predicate, symbols))
     }
+
+    /// replaces the `{name}` parameters with the values of `params`
+    pub fn apply_parameters(&mut self, params: &HashMap<String, Atom>) -> Result<(), error::Token> {
+        apply_parameters(vec![&mut self.0], vec![], params)
+    }
+
+    /// checks that every parameter has been replaced by a value
+    pub fn validate_parameters(&self) -> Result<(), error::Token> {
+        validate_parameters(std::iter::once(&self.0), std::iter::empty())
+    }
+
+    /// checks that the sets of the fact can be deserialized
+    pub fn validate_sets(&self) -> Result<(), error::Token> {
+        validate_sets(std::iter::once(&self.0), std::iter::empty(), self)
+    }
 }
 
 impl fmt::Display for Fact {
@@ -417,6 +961,28 @@ impl Constraint {
             kind: ConstraintKind::convert_from(&c.kind, symbols),
         }
     }
+
+    fn parameters(&self) -> Vec<&str> {
+        match &self.kind {
+            ConstraintKind::Parameter(_, name) => vec![name.as_str()],
+            ConstraintKind::Set(SetConstraint::Contains(s))
+            | ConstraintKind::Set(SetConstraint::Intersection(s)) => {
+                s.iter().flat_map(|a| a.parameters()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// set constraints cannot be empty, or contain variables or other sets
+    fn has_valid_sets(&self) -> bool {
+        match &self.kind {
+            ConstraintKind::Set(SetConstraint::Contains(s))
+            | ConstraintKind::Set(SetConstraint::Intersection(s)) => {
+                !s.is_empty() && s.iter().all(|a| a.is_set_element())
+            }
+            _ => true,
+        }
+    }
 }
 
 impl AsRef<Constraint> for Constraint {
@@ -433,36 +999,51 @@ impl fmt::Display for Constraint {
             ConstraintKind::Integer(IntConstraint::LowerOrEqual(i)) => write!(f, "${} <= {}", self.id, i),
             ConstraintKind::Integer(IntConstraint::LargerOrEqual(i)) => write!(f, "${} >= {}", self.id, i),
             ConstraintKind::Integer(IntConstraint::Equal(i)) => write!(f, "${} == {}", self.id, i),
-            ConstraintKind::Integer(IntConstraint::In(i)) => write!(f, "${} in {:?}", self.id, i),
-            ConstraintKind::Integer(IntConstraint::NotIn(i)) => write!(f, "${} not in {:?}", self.id, i),
-            ConstraintKind::String(StrConstraint::Prefix(i)) => write!(f, "${} matches {}*", self.id, i),
-            ConstraintKind::String(StrConstraint::Suffix(i)) => write!(f, "${} matches *{}", self.id, i),
-            ConstraintKind::String(StrConstraint::Equal(i)) => write!(f, "${} == {}", self.id, i),
-            ConstraintKind::String(StrConstraint::Regex(i)) => write!(f, "${} matches /{}/", self.id, i),
-            ConstraintKind::String(StrConstraint::In(i)) => write!(f, "${} in {:?}", self.id, i),
-            ConstraintKind::String(StrConstraint::NotIn(i)) => write!(f, "${} not in {:?}", self.id, i),
+            ConstraintKind::Integer(IntConstraint::In(i)) => {
+                write!(f, "${} in {}", self.id, print_list(sorted(i).iter().map(|i| i.to_string())))
+            },
+            ConstraintKind::Integer(IntConstraint::NotIn(i)) => {
+                write!(f, "${} not in {}", self.id, print_list(sorted(i).iter().map(|i| i.to_string())))
+            },
+            ConstraintKind::String(StrConstraint::Prefix(i)) => write!(f, "${} starts_with {}", self.id, print_string(i)),
+            ConstraintKind::String(StrConstraint::Suffix(i)) => write!(f, "${} ends_with {}", self.id, print_string(i)),
+            ConstraintKind::String(StrConstraint::Equal(i)) => write!(f, "${} == {}", self.id, print_string(i)),
+            ConstraintKind::String(StrConstraint::Regex(i)) => write!(f, "${} matches {}", self.id, print_regex(i.as_str())),
+            ConstraintKind::String(StrConstraint::PathPrefix(i)) => write!(f, "${} under {}", self.id, print_string(i)),
+            ConstraintKind::String(StrConstraint::Glob(i)) => write!(f, "${} glob {}", self.id, print_string(i)),
+            ConstraintKind::String(StrConstraint::In(i)) => {
+                write!(f, "${} in {}", self.id, print_list(sorted(i).iter().map(|s| print_string(s))))
+            },
+            ConstraintKind::String(StrConstraint::NotIn(i)) => {
+                write!(f, "${} not in {}", self.id, print_list(sorted(i).iter().map(|s| print_string(s))))
+            },
             ConstraintKind::Date(DateConstraint::Before(date)) => {
-              //let date = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(*i as i64, 0), Utc);
-              let date: chrono::DateTime<chrono::Utc> = (*date).into();
-              write!(f, "${} <= {}", self.id, date.to_rfc3339())
+                write!(f, "${} <= {}", self.id, print_system_time(date))
             },
             ConstraintKind::Date(DateConstraint::After(date)) => {
-              //let date = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(*i as i64, 0), Utc);
-              let date: chrono::DateTime<chrono::Utc> = (*date).into();
-              write!(f, "${} >= {}", self.id, date.to_rfc3339())
+                write!(f, "${} >= {}", self.id, print_system_time(date))
+            },
+            ConstraintKind::Symbol(SymbolConstraint::In(i)) => {
+                write!(f, "${} in {}", self.id, print_list(sorted(i).iter().map(|s| format!("#{}", s))))
             },
-            ConstraintKind::Symbol(SymbolConstraint::In(i)) => write!(f, "${} in {:?}", self.id, i),
             ConstraintKind::Symbol(SymbolConstraint::NotIn(i)) => {
-                write!(f, "${} not in {:?}", self.id, i)
+                write!(f, "${} not in {}", self.id, print_list(sorted(i).iter().map(|s| format!("#{}", s))))
             },
-            ConstraintKind::Bytes(BytesConstraint::Equal(i)) => write!(f, "${} == {}", self.id, hex::encode(i)),
+            ConstraintKind::Bytes(BytesConstraint::Equal(i)) => write!(f, "${} == hex:{}", self.id, hex::encode(i)),
             ConstraintKind::Bytes(BytesConstraint::In(i)) => {
-                write!(f, "${} in {:?}", self.id, i.iter()
-                       .map(|s| format!("hex:{}", hex::encode(s))).collect::<HashSet<_>>())
+                write!(f, "${} in {}", self.id, print_list(sorted(i).iter().map(|s| format!("hex:{}", hex::encode(s)))))
             },
             ConstraintKind::Bytes(BytesConstraint::NotIn(i)) => {
-                write!(f, "${} not in {:?}", self.id, i.iter()
-                       .map(|s| format!("hex:{}", hex::encode(s))).collect::<HashSet<_>>())
+                write!(f, "${} not in {}", self.id, print_list(sorted(i).iter().map(|s| format!("hex:{}", hex::encode(s)))))
+            },
+            ConstraintKind::Set(SetConstraint::Contains(s)) => {
+                write!(f, "${} contains {}", self.id, print_set(s))
+            },
+            ConstraintKind::Set(SetConstraint::Intersection(s)) => {
+                write!(f, "${} intersection {}", self.id, print_set(s))
+            },
+            ConstraintKind::Parameter(operator, name) => {
+                write!(f, "${} {} {{{}}}", self.id, operator, name)
             },
         }
     }
@@ -476,6 +1057,10 @@ pub enum ConstraintKind {
     Date(DateConstraint),
     Symbol(SymbolConstraint),
     Bytes(datalog::BytesConstraint),
+    Set(SetConstraint),
+    /// comparison with a parameter, like `$0 > {min}`, that becomes one of
+    /// the other kinds depending on the type of its value
+    Parameter(ConstraintOperator, String),
 }
 
 impl ConstraintKind {
@@ -500,6 +1085,16 @@ impl ConstraintKind {
           let hset = h.iter().map(|s| symbols.insert(&s)).collect();
           datalog::ConstraintKind::Symbol(datalog::SymbolConstraint::NotIn(hset))
         },
+        ConstraintKind::Set(SetConstraint::Contains(h)) => {
+          let set = h.iter().map(|a| a.convert(symbols)).collect();
+          datalog::ConstraintKind::Set(datalog::SetConstraint::Contains(set))
+        },
+        ConstraintKind::Set(SetConstraint::Intersection(h)) => {
+          let set = h.iter().map(|a| a.convert(symbols)).collect();
+          datalog::ConstraintKind::Set(datalog::SetConstraint::Intersection(set))
+        },
+        // rejected by the builders with `validate_parameters`
+        ConstraintKind::Parameter(_, name) => panic!("unbound parameter {{{}}}", name),
       }
     }
 
@@ -524,8 +1119,117 @@ impl ConstraintKind {
           ConstraintKind::Symbol(SymbolConstraint::NotIn(hset))
         },
         datalog::ConstraintKind::Bytes(s) => ConstraintKind::Bytes(s.clone()),
+        datalog::ConstraintKind::Set(datalog::SetConstraint::Contains(h)) => {
+          let set = h.iter().map(|id| Atom::convert_from(id, symbols)).collect();
+          ConstraintKind::Set(SetConstraint::Contains(set))
+        },
+        datalog::ConstraintKind::Set(datalog::SetConstraint::Intersection(h)) => {
+          let set = h.iter().map(|id| Atom::convert_from(id, symbols)).collect();
+          ConstraintKind::Set(SetConstraint::Intersection(set))
+        },
       }
     }
+
+    /// builds the constraint comparing a variable to `value` with `operator`
+    ///
+    /// returns `None` if the operator does not apply to the type of the value
+    fn from_parameter(operator: &ConstraintOperator, value: &Atom) -> Option<Self> {
+        use ConstraintOperator as Op;
+
+        let date = |d: u64| UNIX_EPOCH + Duration::from_secs(d);
+        Some(match (operator, value) {
+            (Op::Lower, Atom::Integer(i)) => ConstraintKind::Integer(IntConstraint::Lower(*i)),
+            (Op::Larger, Atom::Integer(i)) => ConstraintKind::Integer(IntConstraint::Larger(*i)),
+            (Op::LowerOrEqual, Atom::Integer(i)) => ConstraintKind::Integer(IntConstraint::LowerOrEqual(*i)),
+            (Op::LargerOrEqual, Atom::Integer(i)) => ConstraintKind::Integer(IntConstraint::LargerOrEqual(*i)),
+            (Op::LowerOrEqual, Atom::Date(d)) => ConstraintKind::Date(DateConstraint::Before(date(*d))),
+            (Op::LargerOrEqual, Atom::Date(d)) => ConstraintKind::Date(DateConstraint::After(date(*d))),
+            (Op::Equal, Atom::Integer(i)) => ConstraintKind::Integer(IntConstraint::Equal(*i)),
+            (Op::Equal, Atom::Str(s)) => ConstraintKind::String(StrConstraint::Equal(s.clone())),
+            (Op::Equal, Atom::Bytes(b)) => ConstraintKind::Bytes(BytesConstraint::Equal(b.clone())),
+            (Op::StartsWith, Atom::Str(s)) => ConstraintKind::String(StrConstraint::Prefix(s.clone())),
+            (Op::EndsWith, Atom::Str(s)) => ConstraintKind::String(StrConstraint::Suffix(s.clone())),
+            (Op::Under, Atom::Str(s)) => ConstraintKind::String(StrConstraint::PathPrefix(s.clone())),
+            (Op::In, Atom::Set(s)) | (Op::NotIn, Atom::Set(s)) => {
+                let not = *operator == Op::NotIn;
+                // like in the datalog syntax, the set must not be empty and
+                // all its elements must have the same type
+                match s.iter().next()? {
+                    Atom::Integer(_) => {
+                        let h = s.iter().map(|a| match a { Atom::Integer(i) => Some(*i), _ => None }).collect::<Option<_>>()?;
+                        ConstraintKind::Integer(if not { IntConstraint::NotIn(h) } else { IntConstraint::In(h) })
+                    }
+                    Atom::Str(_) => {
+                        let h = s.iter().map(|a| match a { Atom::Str(s) => Some(s.clone()), _ => None }).collect::<Option<_>>()?;
+                        ConstraintKind::String(if not { StrConstraint::NotIn(h) } else { StrConstraint::In(h) })
+                    }
+                    Atom::Symbol(_) => {
+                        let h = s.iter().map(|a| match a { Atom::Symbol(s) => Some(s.clone()), _ => None }).collect::<Option<_>>()?;
+                        ConstraintKind::Symbol(if not { SymbolConstraint::NotIn(h) } else { SymbolConstraint::In(h) })
+                    }
+                    Atom::Bytes(_) => {
+                        let h = s.iter().map(|a| match a { Atom::Bytes(b) => Some(b.clone()), _ => None }).collect::<Option<_>>()?;
+                        ConstraintKind::Bytes(if not { BytesConstraint::NotIn(h) } else { BytesConstraint::In(h) })
+                    }
+                    _ => return None,
+                }
+            }
+            (Op::Contains, Atom::Set(s)) => ConstraintKind::Set(SetConstraint::Contains(s.clone())),
+            (Op::Contains, a) => ConstraintKind::Set(SetConstraint::Contains(std::iter::once(a.clone()).collect())),
+            (Op::Intersection, Atom::Set(s)) => ConstraintKind::Set(SetConstraint::Intersection(s.clone())),
+            _ => return None,
+        })
+    }
+
+    /// replaces the parameters inside set constraints
+    fn apply_parameters(&mut self, params: &HashMap<String, Atom>) {
+        if let ConstraintKind::Set(SetConstraint::Contains(s))
+        | ConstraintKind::Set(SetConstraint::Intersection(s)) = self
+        {
+            let mut set = Atom::Set(std::mem::take(s));
+            set.apply_parameters(params);
+            if let Atom::Set(set) = set {
+                *s = set;
+            }
+        }
+    }
+}
+
+/// operators that can compare a variable to a parameter in a constraint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintOperator {
+    Lower,
+    Larger,
+    LowerOrEqual,
+    LargerOrEqual,
+    Equal,
+    In,
+    NotIn,
+    StartsWith,
+    EndsWith,
+    Under,
+    Contains,
+    Intersection,
+}
+
+impl fmt::Display for ConstraintOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = match self {
+            ConstraintOperator::Lower => "<",
+            ConstraintOperator::Larger => ">",
+            ConstraintOperator::LowerOrEqual => "<=",
+            ConstraintOperator::LargerOrEqual => ">=",
+            ConstraintOperator::Equal => "==",
+            ConstraintOperator::In => "in",
+            ConstraintOperator::NotIn => "not in",
+            ConstraintOperator::StartsWith => "starts_with",
+            ConstraintOperator::EndsWith => "ends_with",
+            ConstraintOperator::Under => "under",
+            ConstraintOperator::Contains => "contains",
+            ConstraintOperator::Intersection => "intersection",
+        };
+        write!(f, "{}", operator)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -540,6 +1244,12 @@ pub enum SymbolConstraint {
     NotIn(HashSet<String>),
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetConstraint {
+    Contains(BTreeSet<Atom>),
+    Intersection(BTreeSet<Atom>),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Rule(
     pub Predicate,
@@ -548,6 +1258,54 @@ pub struct Rule(
 );
 
 impl Rule {
+    /// checks that every variable appearing in the head or the constraints
+    /// is bound by a predicate of the body
+    pub fn validate_variables(&self) -> Result<(), error::Token> {
+        fn variables(p: &Predicate) -> Vec<&str> {
+            p.ids
+                .iter()
+                .filter_map(|id| match id {
+                    Atom::Variable(v) => Some(v.as_str()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let unbound = datalog::unbound_variables(
+            variables(&self.0).into_iter().chain(self.2.iter().map(|c| c.id.as_str())),
+            self.1.iter().flat_map(variables),
+        );
+
+        if unbound.is_empty() {
+            Ok(())
+        } else {
+            let unbound = unbound.into_iter().map(|v| v.to_string()).collect();
+            Err(error::Token::UnsafeRule(error::UnsafeRule {
+                rule: self.to_string(),
+                variables: unbound,
+            }))
+        }
+    }
+
+    /// replaces the `{name}` parameters with the values of `params`
+    pub fn apply_parameters(&mut self, params: &HashMap<String, Atom>) -> Result<(), error::Token> {
+        apply_parameters(
+            std::iter::once(&mut self.0).chain(self.1.iter_mut()).collect(),
+            self.2.iter_mut().collect(),
+            params,
+        )
+    }
+
+    /// checks that every parameter has been replaced by a value
+    pub fn validate_parameters(&self) -> Result<(), error::Token> {
+        validate_parameters(std::iter::once(&self.0).chain(self.1.iter()), self.2.iter())
+    }
+
+    /// checks that the sets of the rule can be deserialized
+    pub fn validate_sets(&self) -> Result<(), error::Token> {
+        validate_sets(std::iter::once(&self.0).chain(self.1.iter()), self.2.iter(), self)
+    }
+
     pub fn convert(&self, symbols: &mut SymbolTable) -> datalog::Rule {
         let head = self.0.convert(symbols);
         let mut body = vec![];
@@ -612,6 +1370,51 @@ pub struct Caveat {
 }
 
 impl Caveat {
+    /// checks that every query of the caveat is a safe rule
+    pub fn validate_variables(&self) -> Result<(), error::Token> {
+        for q in self.queries.iter() {
+            q.validate_variables()?;
+        }
+
+        Ok(())
+    }
+
+    /// replaces the `{name}` parameters with the values of `params`
+    ///
+    /// the parameters can be spread over the queries of the caveat
+    pub fn apply_parameters(&mut self, params: &HashMap<String, Atom>) -> Result<(), error::Token> {
+        let mut predicates = vec![];
+        let mut constraints = vec![];
+        for q in self.queries.iter_mut() {
+            predicates.push(&mut q.0);
+            predicates.extend(q.1.iter_mut());
+            constraints.extend(q.2.iter_mut());
+        }
+
+        apply_parameters(predicates, constraints, params)
+    }
+
+    /// checks that every parameter has been replaced by a value
+    pub fn validate_parameters(&self) -> Result<(), error::Token> {
+        validate_parameters(
+            self.queries
+                .iter()
+                .flat_map(|q| std::iter::once(&q.0).chain(q.1.iter())),
+            self.queries.iter().flat_map(|q| q.2.iter()),
+        )
+    }
+
+    /// checks that the sets of the caveat can be deserialized
+    pub fn validate_sets(&self) -> Result<(), error::Token> {
+        validate_sets(
+            self.queries
+                .iter()
+                .flat_map(|q| std::iter::once(&q.0).chain(q.1.iter())),
+            self.queries.iter().flat_map(|q| q.2.iter()),
+            self,
+        )
+    }
+
     pub fn convert(&self, symbols: &mut SymbolTable) -> datalog::Caveat {
         let mut queries = vec![];
         for q in self.queries.iter() {
@@ -620,6 +1423,12 @@ impl Caveat {
 
         datalog::Caveat { queries }
     }
+
+    pub fn convert_from(c: &datalog::Caveat, symbols: &SymbolTable) -> Self {
+        Caveat {
+            queries: c.queries.iter().map(|q| Rule::convert_from(q, symbols)).collect(),
+        }
+    }
 }
 
 impl TryFrom<Rule> for Caveat {
@@ -740,3 +1549,21 @@ pub fn variable(s: &str) -> Atom {
 pub fn bytes(s: &[u8]) -> Atom {
     Atom::Bytes(s.to_vec())
 }
+
+/// creates a string atom from the fingerprint of a public key
+pub fn fingerprint(key: &PublicKey) -> Atom {
+    Atom::Str(key.fingerprint())
+}
+
+/// creates a boolean
+pub fn boolean(b: bool) -> Atom {
+    Atom::Bool(b)
+}
+
+/// creates a set
+///
+/// sets can contain any atom except variables and other sets, the builders
+/// return `Token::InvalidSet` otherwise
+pub fn set(s: BTreeSet<Atom>) -> Atom {
+    Atom::Set(s)
+}
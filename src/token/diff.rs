@@ -0,0 +1,102 @@
+//! comparison of tokens, to see what an attenuated token adds
+use super::builder::{BlockBuilder, Caveat, Fact, Rule};
+use super::{Biscuit, Block};
+use crate::crypto::PublicKey;
+use crate::datalog::SymbolTable;
+use crate::error;
+use std::fmt;
+
+/// differences between a token and another one
+///
+/// blocks are compared in order, and the tokens diverge from the first
+/// block that does not have the same content and public key in both
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenDiff {
+    /// number of leading blocks, including the authority block, that are
+    /// identical in both tokens
+    pub common_blocks: usize,
+    /// blocks of the first token after the common ones
+    pub removed: Vec<BlockBuilder>,
+    /// blocks of the other token after the common ones
+    pub added: Vec<BlockBuilder>,
+}
+
+impl TokenDiff {
+    /// the other token was created by appending blocks to the first one
+    pub fn is_extension(&self) -> bool {
+        self.common_blocks > 0 && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for TokenDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "common blocks: {}", self.common_blocks)?;
+        for block in self.removed.iter() {
+            writeln!(f, "removed block {}:", block.index)?;
+            write_block(f, block)?;
+        }
+        for block in self.added.iter() {
+            writeln!(f, "added block {}:", block.index)?;
+            write_block(f, block)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_block(f: &mut fmt::Formatter<'_>, block: &BlockBuilder) -> fmt::Result {
+    if let Some(context) = block.context.as_ref() {
+        writeln!(f, "    // context: {}", context)?;
+    }
+    for line in block.to_string().lines() {
+        writeln!(f, "    {}", line)?;
+    }
+    Ok(())
+}
+
+/// sealed tokens are refused: they do not carry the public keys of their
+/// blocks, so two sealed tokens with the same content but sealed with
+/// different secrets would look like an extension of each other
+pub(crate) fn diff(token: &Biscuit, other: &Biscuit) -> Result<TokenDiff, error::Token> {
+    let blocks = token_blocks(token)?;
+    let other_blocks = token_blocks(other)?;
+
+    let common_blocks = blocks
+        .iter()
+        .zip(other_blocks.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    Ok(TokenDiff {
+        common_blocks,
+        removed: blocks.into_iter().skip(common_blocks).map(|(b, _)| b).collect(),
+        added: other_blocks.into_iter().skip(common_blocks).map(|(b, _)| b).collect(),
+    })
+}
+
+/// the blocks of a token and their public keys
+///
+/// blocks are converted with the token's symbol table, so that they can be
+/// compared with the blocks of a token using different symbol indexes
+fn token_blocks(token: &Biscuit) -> Result<Vec<(BlockBuilder, PublicKey)>, error::Token> {
+    let keys = &token.container.as_ref().ok_or(error::Token::Sealed)?.keys;
+
+    Ok(std::iter::once(&token.authority)
+        .chain(token.blocks.iter())
+        .zip(keys.iter())
+        .map(|(block, key)| (block_builder(&token.symbols, block), *key))
+        .collect())
+}
+
+fn block_builder(symbols: &SymbolTable, block: &Block) -> BlockBuilder {
+    BlockBuilder {
+        index: block.index,
+        facts: block.facts.iter().map(|f| Fact::convert_from(f, symbols)).collect(),
+        rules: block.rules.iter().map(|r| Rule::convert_from(r, symbols)).collect(),
+        caveats: block
+            .caveats
+            .iter()
+            .map(|c| Caveat::convert_from(c, symbols))
+            .collect(),
+        context: block.context.clone(),
+    }
+}
@@ -0,0 +1,299 @@
+//! static analysis of the blocks of a token
+//!
+//! an attenuation block can only restrict what the token allows, but nothing
+//! prevents it from being meaningless: caveats that always succeed, or rules
+//! depending on facts that nobody provides. The analysis only sees the token:
+//! predicates that the verifier provides with its own facts and rules, other
+//! than `#ambient` facts, are reported as not produced by the token.
+use super::builder::{self, Caveat, Fact};
+use super::{Biscuit, Block};
+use crate::datalog::{
+    fact_counts, BytesConstraint, Constraint, ConstraintKind, DateConstraint, IntConstraint, Predicate, Rule,
+    StrConstraint, Symbol, SymbolConstraint, World, ID,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// a problem found in a block of the token
+///
+/// datalog elements are printed in the format read by the parser
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lint {
+    /// a rule or caveat uses a predicate that no fact or rule of the token
+    /// produces, and that is not an `#ambient` fact. The verifier can still
+    /// provide it with its own facts and rules
+    UnreachablePredicate {
+        block_id: usize,
+        predicate: String,
+        rule: String,
+    },
+    /// the constraints of every query of the caveat contradict each other,
+    /// so it can never match whatever facts the verifier provides
+    CaveatNeverSucceeds {
+        block_id: usize,
+        caveat_id: usize,
+        caveat: String,
+    },
+    /// the caveat matches without any ambient fact, so it restricts nothing
+    CaveatAlwaysSucceeds {
+        block_id: usize,
+        caveat_id: usize,
+        caveat: String,
+    },
+    /// a fact of an attenuation block that no rule or caveat uses
+    UnusedFact { block_id: usize, fact: String },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::UnreachablePredicate {
+                block_id,
+                predicate,
+                rule,
+            } => write!(
+                f,
+                "block {}: `{}` is not produced by the token, in `{}`",
+                block_id, predicate, rule
+            ),
+            Lint::CaveatNeverSucceeds {
+                block_id,
+                caveat_id,
+                caveat,
+            } => write!(
+                f,
+                "block {}: caveat {} can never succeed: `{}`",
+                block_id, caveat_id, caveat
+            ),
+            Lint::CaveatAlwaysSucceeds {
+                block_id,
+                caveat_id,
+                caveat,
+            } => write!(
+                f,
+                "block {}: caveat {} always succeeds: `{}`",
+                block_id, caveat_id, caveat
+            ),
+            Lint::UnusedFact { block_id, fact } => {
+                write!(f, "block {}: fact `{}` is never used", block_id, fact)
+            }
+        }
+    }
+}
+
+/// facts that are not reported as unused: the verifier reads them with
+/// `Verifier::revocation_check`, or they record information for its own
+/// rules and logs, like the issuance date added by `BlockBuilder::valid_for`
+const VERIFIER_FACTS: &[&str] = &["revocation_id", "issued_at"];
+
+pub(crate) fn lint(token: &Biscuit) -> Vec<Lint> {
+    let symbols = &token.symbols;
+    let blocks = std::iter::once(&token.authority)
+        .chain(token.blocks.iter())
+        .collect::<Vec<_>>();
+
+    let produced = blocks
+        .iter()
+        .flat_map(|b| {
+            b.facts
+                .iter()
+                .map(|f| &f.predicate)
+                .chain(b.rules.iter().map(|r| &r.head))
+        })
+        .collect::<Vec<_>>();
+    let used = blocks
+        .iter()
+        .flat_map(|b| block_queries(b).flat_map(|r| r.body.iter()))
+        .collect::<Vec<_>>();
+
+    let ambient = symbols.get("ambient");
+    let verifier_facts = VERIFIER_FACTS
+        .iter()
+        .filter_map(|name| symbols.get(name))
+        .collect::<HashSet<_>>();
+    let reachable = |p: &Predicate| match p.ids.first() {
+        // the verifier could provide it, as an ambient fact
+        Some(ID::Variable(_)) => true,
+        Some(ID::Symbol(s)) if Some(*s) == ambient => true,
+        _ => produced.iter().any(|other| compatible(p, other)),
+    };
+
+    // the token's own world, without any ambient fact. Since datalog here has
+    // no negation, a caveat matching it will match in any verifier
+    let world = token.generate_world(symbols).ok();
+    let counts = world.as_ref().map(|w| fact_counts(&w.facts)).unwrap_or_default();
+
+    let mut lints = Vec::new();
+    for (block_id, block) in blocks.iter().enumerate() {
+        let mut reported = HashSet::new();
+        for rule in block_queries(block) {
+            for predicate in rule.body.iter().filter(|p| !reachable(p)) {
+                let lint = Lint::UnreachablePredicate {
+                    block_id,
+                    predicate: builder::Predicate::convert_from(predicate, symbols).to_string(),
+                    rule: builder::Rule::convert_from(rule, symbols).to_string(),
+                };
+                if reported.insert(lint.to_string()) {
+                    lints.push(lint);
+                }
+            }
+        }
+
+        for (caveat_id, caveat) in block.caveats.iter().enumerate() {
+            let text = Caveat::convert_from(caveat, symbols).to_string();
+            // predicates that the token does not produce are reported as
+            // unreachable: the verifier could provide them
+            if !caveat.queries.iter().any(satisfiable) {
+                lints.push(Lint::CaveatNeverSucceeds {
+                    block_id,
+                    caveat_id,
+                    caveat: text,
+                });
+            } else if world.as_ref().map(|w| always_matches(w, &counts, &caveat.queries)) == Some(true) {
+                lints.push(Lint::CaveatAlwaysSucceeds {
+                    block_id,
+                    caveat_id,
+                    caveat: text,
+                });
+            }
+        }
+
+        // authority facts are the token's rights, they can be used by the
+        // verifier's caveats and policies
+        if block_id > 0 {
+            for fact in block.facts.iter() {
+                if !verifier_facts.contains(&fact.predicate.name)
+                    && !used.iter().any(|p| compatible(p, &fact.predicate))
+                {
+                    lints.push(Lint::UnusedFact {
+                        block_id,
+                        fact: Fact::convert_from(fact, symbols).to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    lints
+}
+
+/// the rules and caveat queries of a block
+fn block_queries(block: &Block) -> impl Iterator<Item = &Rule> {
+    block
+        .rules
+        .iter()
+        .chain(block.caveats.iter().flat_map(|c| c.queries.iter()))
+}
+
+fn always_matches(world: &World, counts: &HashMap<Symbol, usize>, queries: &[Rule]) -> bool {
+    queries
+        .iter()
+        .any(|q| !world.query_rule_with_counts(q.clone(), counts).is_empty())
+}
+
+/// two predicates could unify: same name and arity, and constants are equal
+/// where both sides have one
+fn compatible(a: &Predicate, b: &Predicate) -> bool {
+    a.name == b.name
+        && a.ids.len() == b.ids.len()
+        && a.ids.iter().zip(b.ids.iter()).all(|(x, y)| match (x, y) {
+            (ID::Variable(_), _) | (_, ID::Variable(_)) => true,
+            _ => x == y,
+        })
+}
+
+/// checks that the constraints on each variable of the rule can be met
+fn satisfiable(rule: &Rule) -> bool {
+    let mut by_variable: HashMap<u32, Vec<&Constraint>> = HashMap::new();
+    for constraint in rule.constraints.iter() {
+        by_variable
+            .entry(constraint.id)
+            .or_default()
+            .push(constraint);
+    }
+
+    by_variable.values().all(|c| variable_satisfiable(c))
+}
+
+fn variable_satisfiable(constraints: &[&Constraint]) -> bool {
+    // a value has a single type
+    let kinds = constraints
+        .iter()
+        .map(|c| std::mem::discriminant(&c.kind))
+        .collect::<HashSet<_>>();
+    if kinds.len() > 1 {
+        return false;
+    }
+
+    // if a constraint lists the possible values, one of them must pass all
+    // the other constraints
+    if let Some(candidates) = constraints.iter().find_map(|c| candidates(c)) {
+        return candidates
+            .iter()
+            .any(|id| constraints.iter().all(|c| c.check(c.id, id)));
+    }
+
+    match constraints[0].kind {
+        ConstraintKind::Int(_) => int_satisfiable(constraints),
+        ConstraintKind::Date(_) => date_satisfiable(constraints),
+        _ => true,
+    }
+}
+
+fn candidates(constraint: &Constraint) -> Option<Vec<ID>> {
+    match &constraint.kind {
+        ConstraintKind::Int(IntConstraint::Equal(i)) => Some(vec![ID::Integer(*i)]),
+        ConstraintKind::Int(IntConstraint::In(h)) => {
+            Some(h.iter().map(|i| ID::Integer(*i)).collect())
+        }
+        ConstraintKind::Str(StrConstraint::Equal(s)) => Some(vec![ID::Str(s.clone())]),
+        ConstraintKind::Str(StrConstraint::In(h)) => {
+            Some(h.iter().map(|s| ID::Str(s.clone())).collect())
+        }
+        ConstraintKind::Symbol(SymbolConstraint::In(h)) => {
+            Some(h.iter().map(|s| ID::Symbol(*s)).collect())
+        }
+        ConstraintKind::Bytes(BytesConstraint::Equal(b)) => Some(vec![ID::Bytes(b.clone())]),
+        ConstraintKind::Bytes(BytesConstraint::In(h)) => {
+            Some(h.iter().map(|b| ID::Bytes(b.clone())).collect())
+        }
+        _ => None,
+    }
+}
+
+fn int_satisfiable(constraints: &[&Constraint]) -> bool {
+    // bounds are inclusive, and computed on i128 to avoid overflows
+    let (mut low, mut high) = (i64::MIN as i128, i64::MAX as i128);
+    let mut excluded = HashSet::new();
+
+    for constraint in constraints.iter() {
+        match &constraint.kind {
+            ConstraintKind::Int(IntConstraint::Lower(i)) => high = high.min(*i as i128 - 1),
+            ConstraintKind::Int(IntConstraint::LowerOrEqual(i)) => high = high.min(*i as i128),
+            ConstraintKind::Int(IntConstraint::Larger(i)) => low = low.max(*i as i128 + 1),
+            ConstraintKind::Int(IntConstraint::LargerOrEqual(i)) => low = low.max(*i as i128),
+            ConstraintKind::Int(IntConstraint::NotIn(h)) => excluded.extend(h.iter().cloned()),
+            _ => {}
+        }
+    }
+
+    let excluded = excluded
+        .into_iter()
+        .filter(|i| low <= *i as i128 && *i as i128 <= high)
+        .count() as i128;
+    low <= high && high - low + 1 > excluded
+}
+
+fn date_satisfiable(constraints: &[&Constraint]) -> bool {
+    let (mut after, mut before) = (u64::MIN, u64::MAX);
+
+    for constraint in constraints.iter() {
+        match &constraint.kind {
+            ConstraintKind::Date(DateConstraint::Before(d)) => before = before.min(*d),
+            ConstraintKind::Date(DateConstraint::After(d)) => after = after.max(*d),
+            _ => {}
+        }
+    }
+
+    after <= before
+}
@@ -1,23 +1,65 @@
 //! main structures to interact with Biscuit tokens
-use super::crypto::{KeyPair, PublicKey};
-use super::datalog::{Fact, Rule, Caveat, SymbolTable, World, ID};
+use super::crypto::{self, KeyPair, PublicKey};
+use super::datalog::{
+    fact_counts, Caveat, ConstraintKind, DateConstraint, Fact, Predicate, Rule, SetConstraint, SymbolConstraint,
+    SymbolTable, World, ID,
+};
 use super::error;
 use super::format::SerializedBiscuit;
 use builder::{BiscuitBuilder, BlockBuilder};
 use prost::Message;
 use rand_core::{CryptoRng, RngCore};
-use std::collections::{HashMap, HashSet};
-
-use crate::format::{convert::proto_block_to_token_block, schema};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::format::{
+    convert::proto_block_to_token_block_with_limits,
+    limits::DeserializationLimits,
+    schema,
+};
+use diff::TokenDiff;
+use lint::Lint;
 use verifier::Verifier;
 
 pub mod builder;
+pub mod diff;
+pub mod lint;
 pub mod sealed;
 pub mod verifier;
 
+/// the authority block declares its symbols after those of
+/// `initial_symbol_table`. Tokens created before the default symbol table was
+/// extended have no version in their authority block
+pub const SYMBOLS_VERSION_INITIAL: u32 = 0;
+/// the authority block declares its symbols after those of
+/// `default_symbol_table`
+pub const SYMBOLS_VERSION_EXTENDED: u32 = 1;
+
+/// how the symbols of a block are numbered, before it is added to a token
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolsMode {
+    /// the block's table starts with a copy of the table it was created with,
+    /// as done by `Block::new`, and indexes refer to this table
+    Full,
+    /// the block's table only has the symbols it declares, numbered after the
+    /// token's table. This is the case of the blocks built by `BlockBuilder`
+    /// and of deserialized blocks
+    Declared,
+}
+
 /// some symbols are predefined and available in every implementation, to avoid
 /// transmitting them with every token
 pub fn default_symbol_table() -> SymbolTable {
+    let mut syms = initial_symbol_table();
+    syms.insert("read");
+    syms.insert("write");
+    syms.insert("time");
+    syms.insert("expiration");
+
+    syms
+}
+
+/// the default symbol table of tokens with the `SYMBOLS_VERSION_INITIAL` version
+pub fn initial_symbol_table() -> SymbolTable {
     let mut syms = SymbolTable::new();
     syms.insert("authority");
     syms.insert("ambient");
@@ -30,6 +72,19 @@ pub fn default_symbol_table() -> SymbolTable {
     syms
 }
 
+/// the table a token's symbols are declared after, depending on the version
+/// of its authority block
+///
+/// only the default table changes with the version, custom tables are used
+/// as they are
+fn base_symbol_table(symbols: SymbolTable, version: u32) -> SymbolTable {
+    if version == SYMBOLS_VERSION_INITIAL && symbols == default_symbol_table() {
+        initial_symbol_table()
+    } else {
+        symbols
+    }
+}
+
 /// This structure represents a valid Biscuit token
 ///
 /// It contains multiple `Block` elements, the associated symbol table,
@@ -79,26 +134,19 @@ impl Biscuit {
     /// the public part of the root keypair must be used for verification
     ///
     /// The block is an authority block: its index must be 0 and all of its facts must have the authority tag
+    ///
+    /// symbols of the block that are already in the table are not declared again
     pub fn new<T: RngCore + CryptoRng>(
         rng: &mut T,
         root: &KeyPair,
         mut symbols: SymbolTable,
         authority: Block,
     ) -> Result<Biscuit, error::Token> {
-        let h1 = symbols.symbols.iter().collect::<HashSet<_>>();
-        let h2 = authority.symbols.symbols.iter().collect::<HashSet<_>>();
-
-        if !h1.is_disjoint(&h2) {
-            return Err(error::Token::SymbolTableOverlap);
-        }
-
         if authority.index as usize != 0 {
             return Err(error::Token::InvalidAuthorityIndex(authority.index));
         }
 
-        symbols
-            .symbols
-            .extend(authority.symbols.symbols.iter().cloned());
+        let authority = merge_symbols(&mut symbols, authority);
 
         let blocks = vec![];
 
@@ -119,8 +167,25 @@ impl Biscuit {
     }
 
     /// deserializes a token and validates the signature using the root public key, with a custom symbol table
-    pub fn from_with_symbols(slice: &[u8], mut symbols: SymbolTable) -> Result<Self, error::Token> {
-        let container = SerializedBiscuit::from_slice(slice).map_err(error::Token::Format)?;
+    pub fn from_with_symbols(slice: &[u8], symbols: SymbolTable) -> Result<Self, error::Token> {
+        Biscuit::from_with_symbols_and_limits(slice, symbols, &DeserializationLimits::default())
+    }
+
+    /// deserializes a token and validates the signature using the root public key,
+    /// rejecting the token if it exceeds the limits
+    pub fn from_with_limits(slice: &[u8], limits: &DeserializationLimits) -> Result<Self, error::Token> {
+        Biscuit::from_with_symbols_and_limits(slice, default_symbol_table(), limits)
+    }
+
+    /// deserializes a token and validates the signature using the root public key, with a custom
+    /// symbol table, rejecting the token if it exceeds the limits
+    pub fn from_with_symbols_and_limits(
+        slice: &[u8],
+        symbols: SymbolTable,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
+        let container =
+            SerializedBiscuit::from_slice_with_limits(slice, limits).map_err(error::Token::Format)?;
 
         let authority: Block = schema::Block::decode(&container.authority[..])
             .map_err(|e| {
@@ -129,11 +194,18 @@ impl Biscuit {
                     e
                 )))
             })
-            .and_then(|b| proto_block_to_token_block(&b).map_err(error::Token::Format))?;
+            .and_then(|b| proto_block_to_token_block_with_limits(&b, limits).map_err(error::Token::Format))?;
 
         if authority.index != 0 {
             return Err(error::Token::InvalidAuthorityIndex(authority.index));
         }
+        // the container's version is the one covered by the signature
+        if authority.version != container.version {
+            return Err(error::Token::Format(error::Format::BlockDeserializationError(
+                "the authority block version does not match the token version".to_string(),
+            )));
+        }
+        let mut symbols = base_symbol_table(symbols, authority.version);
 
         let mut blocks = vec![];
 
@@ -146,7 +218,7 @@ impl Biscuit {
                         e
                     )))
                 })
-                .and_then(|b| proto_block_to_token_block(&b).map_err(error::Token::Format))?;
+                .and_then(|b| proto_block_to_token_block_with_limits(&b, limits).map_err(error::Token::Format))?;
 
             if deser.index != index {
                 return Err(error::Token::InvalidBlockIndex(error::InvalidBlockIndex {
@@ -159,6 +231,13 @@ impl Biscuit {
             index += 1;
         }
 
+        limits
+            .check_symbols(
+                authority.symbols.symbols.len()
+                    + blocks.iter().map(|b| b.symbols.symbols.len()).sum::<usize>(),
+            )
+            .map_err(error::Token::Format)?;
+
         symbols
             .symbols
             .extend(authority.symbols.symbols.iter().cloned());
@@ -179,15 +258,162 @@ impl Biscuit {
         })
     }
 
-    /// deserializes a sealed token and checks its signature with the secret, using a custom symbol table
+    /// deserializes a sealed token and checks its signature with the secret
     pub fn from_sealed(slice: &[u8], secret: &[u8]) -> Result<Self, error::Token> {
       Biscuit::from_sealed_with_symbols(slice, secret, default_symbol_table())
     }
 
+    /// deserializes a sealed token and checks its signature with the secret, using a custom symbol table
+    pub fn from_sealed_with_symbols(slice: &[u8], secret: &[u8], symbols: SymbolTable) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_with_symbols_and_limits(slice, secret, symbols, &DeserializationLimits::default())
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret,
+    /// rejecting the token if it exceeds the limits
+    pub fn from_sealed_with_limits(slice: &[u8], secret: &[u8], limits: &DeserializationLimits) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_with_symbols_and_limits(slice, secret, default_symbol_table(), limits)
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret, with a custom
+    /// symbol table, rejecting the token if it exceeds the limits
+    pub fn from_sealed_with_symbols_and_limits(
+        slice: &[u8],
+        secret: &[u8],
+        symbols: SymbolTable,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
+        let container = sealed::SealedBiscuit::from_slice_with_limits(slice, secret, limits)
+            .map_err(error::Token::Format)?;
+
+        Biscuit::from_sealed_container(container, symbols, limits)
+    }
+
     /// deserializes a sealed token and checks its signature with the secret
-    pub fn from_sealed_with_symbols(slice: &[u8], secret: &[u8], mut symbols: SymbolTable) -> Result<Self, error::Token> {
-        let container =
-            sealed::SealedBiscuit::from_slice(slice, secret).map_err(error::Token::Format)?;
+    /// matching its key id in the keyring
+    ///
+    /// during a secret rotation, the keyring contains both the old and new
+    /// secrets, so tokens sealed with the old one stay valid
+    pub fn from_sealed_with_keyring(slice: &[u8], keyring: &HashMap<u32, Vec<u8>>) -> Result<Self, error::Token> {
+      Biscuit::from_sealed_with_keyring_and_symbols(slice, keyring, default_symbol_table())
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret
+    /// matching its key id in the keyring, using a custom symbol table
+    pub fn from_sealed_with_keyring_and_symbols(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        symbols: SymbolTable,
+    ) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_with_keyring_symbols_and_limits(slice, keyring, symbols, &DeserializationLimits::default())
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret
+    /// matching its key id in the keyring, rejecting the token if it exceeds the limits
+    pub fn from_sealed_with_keyring_and_limits(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_with_keyring_symbols_and_limits(slice, keyring, default_symbol_table(), limits)
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret
+    /// matching its key id in the keyring, with a custom symbol table,
+    /// rejecting the token if it exceeds the limits
+    pub fn from_sealed_with_keyring_symbols_and_limits(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        symbols: SymbolTable,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
+        let container = sealed::SealedBiscuit::from_slice_with_keyring_and_limits(slice, keyring, limits)
+            .map_err(error::Token::Format)?;
+
+        Biscuit::from_sealed_container(container, symbols, limits)
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted`
+    pub fn from_sealed_encrypted(slice: &[u8], secret: &[u8]) -> Result<Self, error::Token> {
+      Biscuit::from_sealed_encrypted_with_symbols(slice, secret, default_symbol_table())
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted`, using a custom symbol table
+    pub fn from_sealed_encrypted_with_symbols(slice: &[u8], secret: &[u8], symbols: SymbolTable) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_encrypted_with_symbols_and_limits(slice, secret, symbols, &DeserializationLimits::default())
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted`, rejecting the
+    /// token if it exceeds the limits
+    pub fn from_sealed_encrypted_with_limits(slice: &[u8], secret: &[u8], limits: &DeserializationLimits) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_encrypted_with_symbols_and_limits(slice, secret, default_symbol_table(), limits)
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted`, with a custom
+    /// symbol table, rejecting the token if it exceeds the limits
+    pub fn from_sealed_encrypted_with_symbols_and_limits(
+        slice: &[u8],
+        secret: &[u8],
+        symbols: SymbolTable,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
+        let container = sealed::SealedBiscuit::from_encrypted_slice_with_limits(slice, secret, limits)
+            .map_err(error::Token::Format)?;
+
+        Biscuit::from_sealed_container(container, symbols, limits)
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted_with_key_id`, with
+    /// the secret matching its key id in the keyring
+    pub fn from_sealed_encrypted_with_keyring(slice: &[u8], keyring: &HashMap<u32, Vec<u8>>) -> Result<Self, error::Token> {
+      Biscuit::from_sealed_encrypted_with_keyring_and_symbols(slice, keyring, default_symbol_table())
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted_with_key_id`, with
+    /// the secret matching its key id in the keyring, using a custom symbol table
+    pub fn from_sealed_encrypted_with_keyring_and_symbols(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        symbols: SymbolTable,
+    ) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_encrypted_with_keyring_symbols_and_limits(
+            slice,
+            keyring,
+            symbols,
+            &DeserializationLimits::default(),
+        )
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted_with_key_id`, with
+    /// the secret matching its key id in the keyring, rejecting the token if it
+    /// exceeds the limits
+    pub fn from_sealed_encrypted_with_keyring_and_limits(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
+        Biscuit::from_sealed_encrypted_with_keyring_symbols_and_limits(slice, keyring, default_symbol_table(), limits)
+    }
+
+    /// decrypts a token sealed with `Biscuit::seal_encrypted_with_key_id`, with
+    /// the secret matching its key id in the keyring, with a custom symbol table,
+    /// rejecting the token if it exceeds the limits
+    pub fn from_sealed_encrypted_with_keyring_symbols_and_limits(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        symbols: SymbolTable,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
+        let container = sealed::SealedBiscuit::from_encrypted_slice_with_keyring_and_limits(slice, keyring, limits)
+            .map_err(error::Token::Format)?;
+
+        Biscuit::from_sealed_container(container, symbols, limits)
+    }
+
+    fn from_sealed_container(
+        container: sealed::SealedBiscuit,
+        symbols: SymbolTable,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Token> {
 
         let authority: Block = schema::Block::decode(&container.authority[..])
             .map_err(|e| {
@@ -196,11 +422,12 @@ impl Biscuit {
                     e
                 )))
             })
-            .and_then(|b| proto_block_to_token_block(&b).map_err(error::Token::Format))?;
+            .and_then(|b| proto_block_to_token_block_with_limits(&b, limits).map_err(error::Token::Format))?;
 
         if authority.index != 0 {
             return Err(error::Token::InvalidAuthorityIndex(authority.index));
         }
+        let mut symbols = base_symbol_table(symbols, authority.version);
 
         let mut blocks = vec![];
 
@@ -213,7 +440,7 @@ impl Biscuit {
                         e
                     )))
                 })
-                .and_then(|b| proto_block_to_token_block(&b).map_err(error::Token::Format))?;
+                .and_then(|b| proto_block_to_token_block_with_limits(&b, limits).map_err(error::Token::Format))?;
 
             if deser.index != index {
                 return Err(error::Token::InvalidBlockIndex(error::InvalidBlockIndex {
@@ -226,6 +453,13 @@ impl Biscuit {
             index += 1;
         }
 
+        limits
+            .check_symbols(
+                authority.symbols.symbols.len()
+                    + blocks.iter().map(|b| b.symbols.symbols.len()).sum::<usize>(),
+            )
+            .map_err(error::Token::Format)?;
+
         symbols
             .symbols
             .extend(authority.symbols.symbols.iter().cloned());
@@ -277,6 +511,39 @@ impl Biscuit {
         sealed.to_vec().map_err(error::Token::Format)
     }
 
+    /// serializes a sealed version of the token, with its content encrypted
+    ///
+    /// unlike `seal`, the facts and rules cannot be read without the secret
+    pub fn seal_encrypted<T: RngCore + CryptoRng>(&self, rng: &mut T, secret: &[u8]) -> Result<Vec<u8>, error::Token> {
+        let sealed =
+            sealed::SealedBiscuit::from_token(self, secret).map_err(error::Token::Format)?;
+        sealed.to_encrypted_vec(rng, secret).map_err(error::Token::Format)
+    }
+
+    /// serializes a sealed version of the token, with its content encrypted,
+    /// recording the id of the secret
+    ///
+    /// the token can then be decrypted with `Biscuit::from_sealed_encrypted_with_keyring`
+    pub fn seal_encrypted_with_key_id<T: RngCore + CryptoRng>(
+        &self,
+        rng: &mut T,
+        key_id: u32,
+        secret: &[u8],
+    ) -> Result<Vec<u8>, error::Token> {
+        let sealed = sealed::SealedBiscuit::from_token_with_key_id(self, key_id, secret)
+            .map_err(error::Token::Format)?;
+        sealed.to_encrypted_vec(rng, secret).map_err(error::Token::Format)
+    }
+
+    /// serializes a sealed version of the token, recording the id of the secret
+    ///
+    /// the token can then be deserialized with `Biscuit::from_sealed_with_keyring`
+    pub fn seal_with_key_id(&self, key_id: u32, secret: &[u8]) -> Result<Vec<u8>, error::Token> {
+        let sealed = sealed::SealedBiscuit::from_token_with_key_id(self, key_id, secret)
+            .map_err(error::Token::Format)?;
+        sealed.to_vec().map_err(error::Token::Format)
+    }
+
     /// returns the internal representation of the token
     pub fn container(&self) -> Option<&SerializedBiscuit> {
         self.container.as_ref()
@@ -303,6 +570,7 @@ impl Biscuit {
         }
     }
 
+    /// creates the world of the token's facts and rules, run to its fixpoint
     pub(crate) fn generate_world(&self, symbols: &SymbolTable) -> Result<World, error::Logic> {
         let mut world = World::new();
 
@@ -352,7 +620,7 @@ impl Biscuit {
             }
         }
 
-        world.run();
+        world.run()?;
 
         Ok(world)
     }
@@ -404,8 +672,9 @@ impl Biscuit {
             world.rules.push(rule);
         }
 
-        world.run();
+        world.run()?;
         //println!("world:\n{}", symbols.print_world(&world));
+        let counts = fact_counts(&world.facts);
 
         // we only keep the verifier rules
         //world.rules = ambient_rules;
@@ -417,7 +686,7 @@ impl Biscuit {
             let mut successful = false;
 
             for query in caveat.queries.iter() {
-                let res = world.query_rule(query.clone());
+                let res = world.query_rule_with_counts(query.clone(), &counts);
                 if !res.is_empty() {
                     successful = true;
                     break;
@@ -425,11 +694,7 @@ impl Biscuit {
             }
 
             if !successful {
-                errors.push(error::FailedCaveat::Block(error::FailedBlockCaveat {
-                    block_id: 0,
-                    caveat_id: i as u32,
-                    rule: symbols.print_caveat(caveat),
-                }));
+                errors.push(failed_block_caveat(symbols, &world, 0, i as u32, caveat));
             }
         }
 
@@ -438,7 +703,7 @@ impl Biscuit {
             let mut successful = false;
 
             for query in caveat.queries.iter() {
-                let res = world.query_rule(query.clone());
+                let res = world.query_rule_with_counts(query.clone(), &counts);
                 if !res.is_empty() {
                     successful = true;
                     break;
@@ -458,7 +723,7 @@ impl Biscuit {
                 let mut successful = false;
 
                 for query in caveat.queries.iter() {
-                    let res = world.query_rule(query.clone());
+                    let res = world.query_rule_with_counts(query.clone(), &counts);
                     if !res.is_empty() {
                         successful = true;
                         break;
@@ -466,18 +731,14 @@ impl Biscuit {
                 }
 
                 if !successful {
-                    errors.push(error::FailedCaveat::Block(error::FailedBlockCaveat {
-                        block_id: i as u32,
-                        caveat_id: j as u32,
-                        rule: symbols.print_caveat(caveat),
-                    }));
+                    errors.push(failed_block_caveat(symbols, &world, i as u32, j as u32, caveat));
                 }
             }
         }
 
         let mut query_results = HashMap::new();
         for (name, rule) in queries.iter() {
-            let res = world.query_rule(rule.clone());
+            let res = world.query_rule_with_counts(rule.clone(), &counts);
             query_results.insert(name.clone(), res);
         }
 
@@ -522,13 +783,6 @@ impl Biscuit {
 
         let block = block_builder.build(self.symbols.clone());
 
-        let h1 = self.symbols.symbols.iter().collect::<HashSet<_>>();
-        let h2 = block.symbols.symbols.iter().collect::<HashSet<_>>();
-
-        if !h1.is_disjoint(&h2) {
-            return Err(error::Token::SymbolTableOverlap);
-        }
-
         if block.index as usize != 1 + self.blocks.len() {
             return Err(error::Token::InvalidBlockIndex(error::InvalidBlockIndex {
                 expected: 1 + self.blocks.len() as u32,
@@ -539,6 +793,7 @@ impl Biscuit {
         let authority = self.authority.clone();
         let mut blocks = self.blocks.clone();
         let mut symbols = self.symbols.clone();
+        let block = merge_symbols(&mut symbols, block);
 
         let container = match self.container.as_ref() {
             None => return Err(error::Token::Sealed),
@@ -547,9 +802,6 @@ impl Biscuit {
                 .map_err(error::Token::Format)?,
         };
 
-        symbols
-            .symbols
-            .extend(block.symbols.symbols.iter().cloned());
         blocks.push(block);
 
         Ok(Biscuit {
@@ -560,6 +812,65 @@ impl Biscuit {
         })
     }
 
+    /// signs a verifier provided challenge with the holder's key pair
+    ///
+    /// the token must have been bound to the key pair's public key with
+    /// `BiscuitBuilder::bind_holder`. The challenge should contain a nonce
+    /// chosen by the verifier along with the request data, to prevent replays
+    pub fn sign_challenge<T: RngCore + CryptoRng>(
+        &self,
+        rng: &mut T,
+        keypair: &KeyPair,
+        challenge: &[u8],
+    ) -> Result<Vec<u8>, error::Token> {
+        let holder = self.holder_key()?;
+        if holder != keypair.public() {
+            return Err(error::Token::Possession(error::Possession::KeyMismatch));
+        }
+
+        let signature = keypair.sign(rng, &challenge_message(challenge));
+        Ok(crypto::signature_to_bytes(&signature))
+    }
+
+    /// public key the token was bound to with `BiscuitBuilder::bind_holder`
+    ///
+    /// since anybody can append a block, holder facts are only read from the
+    /// authority block: otherwise the holder of an unbound token could bind
+    /// it to its own key
+    pub fn holder_key(&self) -> Result<PublicKey, error::Token> {
+        let holder = match self.symbols.get("holder") {
+            Some(holder) => holder,
+            None => return Err(error::Token::Possession(error::Possession::MissingHolderKey)),
+        };
+
+        let mut keys = HashSet::new();
+        for fact in self.authority.facts.iter() {
+            if fact.predicate.name != holder {
+                continue;
+            }
+
+            match fact.predicate.ids.as_slice() {
+                [ID::Bytes(key)] => {
+                    keys.insert(key.clone());
+                }
+                _ => return Err(error::Token::Possession(error::Possession::InvalidHolderKey)),
+            }
+        }
+
+        let mut keys = keys.into_iter();
+        match (keys.next(), keys.next()) {
+            (None, _) => Err(error::Token::Possession(error::Possession::MissingHolderKey)),
+            (Some(_), Some(_)) => Err(error::Token::Possession(error::Possession::MultipleHolderKeys)),
+            (Some(key), None) => PublicKey::from_bytes(&key)
+                .ok_or(error::Token::Possession(error::Possession::InvalidHolderKey)),
+        }
+    }
+
+    /// number of blocks, including the authority block
+    pub fn block_count(&self) -> usize {
+        1 + self.blocks.len()
+    }
+
     pub fn context(&self) -> Vec<Option<String>> {
       let mut res = vec![];
       res.push(self.authority.context.clone());
@@ -571,13 +882,58 @@ impl Biscuit {
       res
     }
 
+    /// compares this token with another one, usually an attenuation of it
+    ///
+    /// the added blocks print in the datalog format read by `parser::block`.
+    /// Sealed tokens cannot be compared and return `error::Token::Sealed`:
+    /// they do not keep the public keys that tell tokens with the same
+    /// blocks apart
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{crypto::KeyPair, token::Biscuit};
+    /// let mut rng = rand::thread_rng();
+    /// let root = KeyPair::new(&mut rng);
+    /// let token1 = Biscuit::builder(&root).build(&mut rng).unwrap();
+    ///
+    /// let mut builder = token1.create_block();
+    /// builder.check_operation("read");
+    /// let keypair2 = KeyPair::new(&mut rng);
+    /// let token2 = token1.append(&mut rng, &keypair2, builder).unwrap();
+    ///
+    /// let diff = token1.diff(&token2).unwrap();
+    /// assert!(diff.is_extension());
+    /// assert_eq!(diff.added.len(), 1);
+    /// assert_eq!(
+    ///     diff.added[0].to_string(),
+    ///     "caveat *operation_check(#operation_check) <- operation(#ambient, #read);\n"
+    /// );
+    /// ```
+    pub fn diff(&self, other: &Biscuit) -> Result<TokenDiff, error::Token> {
+        diff::diff(self, other)
+    }
+
+    /// looks for blocks that do not restrict anything or refer to data
+    /// that nothing provides
+    ///
+    /// this reports predicates used by rules and caveats that are not
+    /// `#ambient` facts and that no fact or rule of the token produces,
+    /// caveats whose constraints can never be met, caveats that succeed
+    /// without any ambient fact, and facts of attenuation blocks that nothing
+    /// uses. The analysis only sees the token: a predicate that the verifier
+    /// provides with its own facts or rules is still reported as not produced
+    pub fn lint(&self) -> Vec<Lint> {
+        lint::lint(self)
+    }
+
     /// pretty printer for this token
     pub fn print(&self) -> String {
-        let authority = print_block(&self.symbols, &self.authority);
+        let keys = self.container.as_ref().map(|c| &c.keys[..]).unwrap_or(&[]);
+        let authority = print_block(&self.symbols, &self.authority, keys.first());
         let blocks: Vec<_> = self
             .blocks
             .iter()
-            .map(|b| print_block(&self.symbols, b))
+            .enumerate()
+            .map(|(i, b)| print_block(&self.symbols, b, keys.get(i + 1)))
             .collect();
 
         format!(
@@ -589,7 +945,93 @@ impl Biscuit {
     }
 }
 
-fn print_block(symbols: &SymbolTable, block: &Block) -> String {
+/// reports a failed block caveat, recognizing the expiration and not before
+/// caveats like the ones generated by `BlockBuilder::expiration_date` and
+/// `BlockBuilder::not_before`
+///
+/// a time caveat also fails when the verifier did not provide the current
+/// time, so it is only reported as expired or not yet valid if the world
+/// contains a `time(#ambient, $date)` fact
+pub(crate) fn failed_block_caveat(
+    symbols: &SymbolTable,
+    world: &World,
+    block_id: u32,
+    caveat_id: u32,
+    caveat: &Caveat,
+) -> error::FailedCaveat {
+    let failed = error::FailedBlockCaveat {
+        block_id,
+        caveat_id,
+        rule: symbols.print_caveat(caveat),
+    };
+
+    if !has_time(symbols, world) {
+        return error::FailedCaveat::Block(failed);
+    }
+
+    match time_caveat_kind(symbols, caveat) {
+        Some(DateConstraint::Before(_)) => error::FailedCaveat::Expired(failed),
+        Some(DateConstraint::After(_)) => error::FailedCaveat::NotYetValid(failed),
+        None => error::FailedCaveat::Block(failed),
+    }
+}
+
+fn has_time(symbols: &SymbolTable, world: &World) -> bool {
+    let (time, ambient) = match (symbols.get("time"), symbols.get("ambient")) {
+        (Some(time), Some(ambient)) => (time, ambient),
+        _ => return false,
+    };
+
+    world.facts.iter().any(|f| {
+        f.predicate.name == time && f.predicate.ids.first() == Some(&ID::Symbol(ambient))
+    })
+}
+
+/// a time caveat has one query, checking the `time(#ambient, $date)` fact
+/// with date constraints all in the same direction
+fn time_caveat_kind(symbols: &SymbolTable, caveat: &Caveat) -> Option<DateConstraint> {
+    let time = symbols.get("time")?;
+    let ambient = symbols.get("ambient")?;
+
+    let query = match caveat.queries.as_slice() {
+        [query] => query,
+        _ => return None,
+    };
+    let variable = match query.body.as_slice() {
+        [pred] if pred.name == time => match pred.ids.as_slice() {
+            [ID::Symbol(s), ID::Variable(v)] if *s == ambient => *v,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let mut kind = None;
+    for constraint in query.constraints.iter() {
+        let c = match &constraint.kind {
+            ConstraintKind::Date(c) if constraint.id == variable => c,
+            _ => return None,
+        };
+
+        match (&kind, c) {
+            (None, _) => kind = Some(c.clone()),
+            (Some(DateConstraint::Before(_)), DateConstraint::Before(_))
+            | (Some(DateConstraint::After(_)), DateConstraint::After(_)) => {}
+            _ => return None,
+        }
+    }
+
+    kind
+}
+
+/// the challenge is prefixed with a label so that a holder signature
+/// cannot be reused in another protocol
+pub(crate) fn challenge_message(challenge: &[u8]) -> Vec<u8> {
+    let mut message = b"biscuit holder challenge\0".to_vec();
+    message.extend_from_slice(challenge);
+    message
+}
+
+fn print_block(symbols: &SymbolTable, block: &Block, key: Option<&PublicKey>) -> String {
     let facts: Vec<_> = block.facts.iter().map(|f| symbols.print_fact(f)).collect();
     let rules: Vec<_> = block.rules.iter().map(|r| symbols.print_rule(r)).collect();
     let caveats: Vec<_> = block
@@ -615,11 +1057,22 @@ fn print_block(symbols: &SymbolTable, block: &Block) -> String {
       format!("\n                {}\n            ", caveats.join(",\n                "))
     };
 
+    // sealed tokens do not carry the public keys
+    let key = match key {
+        Some(key) => format!(
+            "\n            public key: \"{}\"\n            fingerprint: \"{}\"",
+            hex::encode(key.to_bytes()),
+            key.fingerprint()
+        ),
+        None => String::new(),
+    };
+
     format!(
-        "Block[{}] {{\n            symbols: {:?}\n            context: \"{}\"\n            facts: [{}]\n            rules: [{}]\n            caveats: [{}]\n        }}",
+        "Block[{}] {{\n            symbols: {:?}\n            context: \"{}\"{}\n            facts: [{}]\n            rules: [{}]\n            caveats: [{}]\n        }}",
         block.index,
         block.symbols.symbols,
         block.context.as_deref().unwrap_or(""),
+        key,
         facts,
         rules,
         caveats,
@@ -641,6 +1094,11 @@ pub struct Block {
     /// contextual information that can be looked up before the verification
     /// (as an example, a user id to query rights into a database)
     pub context: Option<String>,
+    /// for the authority block, which default symbol table the token's symbols
+    /// are declared after. It is `SYMBOLS_VERSION_INITIAL` for other blocks
+    pub version: u32,
+    /// how `symbols` is numbered, to add the block to a token
+    pub symbols_mode: SymbolsMode,
 }
 
 impl Block {
@@ -655,6 +1113,12 @@ impl Block {
             rules: vec![],
             caveats: vec![],
             context: None,
+            version: if index == 0 {
+                SYMBOLS_VERSION_EXTENDED
+            } else {
+                SYMBOLS_VERSION_INITIAL
+            },
+            symbols_mode: SymbolsMode::Full,
         }
     }
 
@@ -695,14 +1159,15 @@ impl Block {
             world.rules.push(rule);
         }
 
-        world.run();
+        world.run()?;
+        let counts = fact_counts(&world.facts);
 
         let mut errors = vec![];
         for (j, caveat) in self.caveats.iter().enumerate() {
             let mut successful = false;
 
             for query in caveat.queries.iter() {
-                let res = world.query_rule(query.clone());
+                let res = world.query_rule_with_counts(query.clone(), &counts);
                 if !res.is_empty() {
                     successful = true;
                     break;
@@ -710,11 +1175,7 @@ impl Block {
             }
 
             if !successful {
-                errors.push(error::FailedCaveat::Block(error::FailedBlockCaveat {
-                    block_id: i as u32,
-                    caveat_id: j as u32,
-                    rule: symbols.print_caveat(caveat),
-                }));
+                errors.push(failed_block_caveat(symbols, &world, i as u32, j as u32, caveat));
             }
         }
 
@@ -722,7 +1183,7 @@ impl Block {
             let mut successful = false;
 
             for query in caveat.queries.iter() {
-                let res = world.query_rule(query.clone());
+                let res = world.query_rule_with_counts(query.clone(), &counts);
                 if !res.is_empty() {
                     successful = true;
                     break;
@@ -738,7 +1199,7 @@ impl Block {
         }
 
         for (name, rule) in queries.iter() {
-          let res = world.query_rule(rule.clone());
+          let res = world.query_rule_with_counts(rule.clone(), &counts);
           if !res.is_empty() {
             let entry = query_results.entry(name.clone()).or_insert_with(HashMap::new);
             (*entry).insert(i as u32, res);
@@ -753,6 +1214,99 @@ impl Block {
     }
 }
 
+/// adds the symbols of a block to the token's table
+///
+/// depending on `block.symbols_mode`, every symbol of the block's table or
+/// only the declared ones are looked up in the token's table. Those that it
+/// already contains are not declared again, and the block is rewritten to use
+/// the token's indexes
+fn merge_symbols(symbols: &mut SymbolTable, mut block: Block) -> Block {
+    let start = symbols.symbols.len();
+    let first_index = match block.symbols_mode {
+        SymbolsMode::Full => 0,
+        SymbolsMode::Declared => start,
+    };
+
+    let mut indexes = HashMap::new();
+    for (i, symbol) in block.symbols.symbols.iter().enumerate() {
+        let index = symbols.insert(symbol);
+        if index != (first_index + i) as u64 {
+            indexes.insert((first_index + i) as u64, index);
+        }
+    }
+
+    if !indexes.is_empty() {
+        for fact in block.facts.iter_mut() {
+            remap_predicate(&mut fact.predicate, &indexes);
+        }
+        for rule in block.rules.iter_mut() {
+            remap_rule(rule, &indexes);
+        }
+        for caveat in block.caveats.iter_mut() {
+            for query in caveat.queries.iter_mut() {
+                remap_rule(query, &indexes);
+            }
+        }
+    }
+
+    block.symbols.symbols = symbols.symbols[start..].to_vec();
+    block.symbols_mode = SymbolsMode::Declared;
+    block
+}
+
+fn remap_rule(rule: &mut Rule, indexes: &HashMap<u64, u64>) {
+    remap_predicate(&mut rule.head, indexes);
+    for predicate in rule.body.iter_mut() {
+        remap_predicate(predicate, indexes);
+    }
+
+    for constraint in rule.constraints.iter_mut() {
+        // variable names are symbols too
+        constraint.id = remap_index(constraint.id as u64, indexes) as u32;
+        match &mut constraint.kind {
+            ConstraintKind::Symbol(SymbolConstraint::In(h))
+            | ConstraintKind::Symbol(SymbolConstraint::NotIn(h)) => {
+                *h = h.iter().map(|s| remap_index(*s, indexes)).collect();
+            }
+            ConstraintKind::Set(SetConstraint::Contains(set))
+            | ConstraintKind::Set(SetConstraint::Intersection(set)) => {
+                *set = remap_set(set, indexes);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn remap_predicate(predicate: &mut Predicate, indexes: &HashMap<u64, u64>) {
+    predicate.name = remap_index(predicate.name, indexes);
+    for id in predicate.ids.iter_mut() {
+        remap_id(id, indexes);
+    }
+}
+
+fn remap_id(id: &mut ID, indexes: &HashMap<u64, u64>) {
+    match id {
+        ID::Symbol(s) => *s = remap_index(*s, indexes),
+        ID::Variable(v) => *v = remap_index(*v as u64, indexes) as u32,
+        ID::Set(set) => *set = remap_set(set, indexes),
+        _ => {}
+    }
+}
+
+fn remap_set(set: &BTreeSet<ID>, indexes: &HashMap<u64, u64>) -> BTreeSet<ID> {
+    set.iter()
+        .cloned()
+        .map(|mut id| {
+            remap_id(&mut id, indexes);
+            id
+        })
+        .collect()
+}
+
+fn remap_index(index: u64, indexes: &HashMap<u64, u64>) -> u64 {
+    indexes.get(&index).cloned().unwrap_or(index)
+}
+
 #[cfg(test)]
 mod tests {
     use super::builder::{fact, pred, rule, s, var, int};
@@ -1097,55 +1651,283 @@ mod tests {
     }
 
     #[test]
-    fn verif_no_blocks() {
-      use crate::token::builder::*;
+    fn sealed_token_rotation() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
 
-      let mut rng: StdRng = SeedableRng::seed_from_u64(1234);
-      let root = KeyPair::new(&mut rng);
+        let old_secret = b"old secret".to_vec();
+        let new_secret = b"new secret".to_vec();
 
-      let mut builder = Biscuit::builder(&root);
+        let unidentified = biscuit1.seal(&old_secret).unwrap();
+        let sealed_old = biscuit1.seal_with_key_id(1, &old_secret).unwrap();
+        let sealed_new = biscuit1.seal_with_key_id(2, &new_secret).unwrap();
 
-      builder.add_authority_fact(fact("right", &[s("authority"), string("file1"), s("read")])).unwrap();
-      builder.add_authority_fact(fact("right", &[s("authority"), string("file2"), s("read")])).unwrap();
-      builder.add_authority_fact(fact("right", &[s("authority"), string("file1"), s("write")])).unwrap();
+        // rotation window: both secrets are accepted
+        let mut keyring = std::collections::HashMap::new();
+        keyring.insert(1, old_secret.clone());
+        keyring.insert(2, new_secret.clone());
 
-      let biscuit1 = builder.build(&mut rng).unwrap();
-      println!("{}", biscuit1.print());
+        for sealed in &[&unidentified, &sealed_old, &sealed_new] {
+            let biscuit = Biscuit::from_sealed_with_keyring(sealed, &keyring).unwrap();
+            let mut verifier = biscuit.verify_sealed().unwrap();
+            verifier.add_resource("/folder1/file1");
+            verifier.add_operation("read");
+            verifier.verify().unwrap();
+        }
 
-      let mut v = biscuit1.verify(root.public()).expect("omg verifier");
+        // a single secret can still be used for tokens with a key id
+        Biscuit::from_sealed(&sealed_old, &old_secret).unwrap();
 
-      v.add_caveat(rule("right",
-          &[s("right")],
-          &[pred("right", &[s("authority"), string("file2"), s("write")])]
-      )).unwrap();
+        // the key id is covered by the signature
+        let mut proto = schema::SealedBiscuit::decode(&sealed_old[..]).unwrap();
+        proto.key_id = Some(3);
+        let mut tampered = Vec::new();
+        proto.encode(&mut tampered).unwrap();
+        keyring.insert(3, old_secret.clone());
+        assert_eq!(
+            Biscuit::from_sealed_with_keyring(&tampered, &keyring).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+        keyring.remove(&3);
 
-      //assert!(v.verify().is_err());
-      let res = v.verify();
-      println!("res: {:?}", res);
-      assert_eq!(res,
-        Err(Token::FailedLogic(Logic::FailedCaveats(vec![
-          FailedCaveat::Verifier(FailedVerifierCaveat { caveat_id: 0, rule: String::from("*right(#right) <- right(#authority, \"file2\", #write)") }),
-      ]))));
+        // end of the rotation: the old secret is removed
+        keyring.remove(&1);
+        assert_eq!(
+            Biscuit::from_sealed_with_keyring(&sealed_old, &keyring).unwrap_err(),
+            Token::Format(Format::UnknownSealingKey(1))
+        );
+        assert_eq!(
+            Biscuit::from_sealed_with_keyring(&unidentified, &keyring).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+
+        keyring.insert(1, b"wrong secret".to_vec());
+        assert_eq!(
+            Biscuit::from_sealed_with_keyring(&sealed_old, &keyring).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
     }
 
     #[test]
-    fn verifier_queries() {
+    fn sealed_encrypted_token() {
         let mut rng: StdRng = SeedableRng::seed_from_u64(0);
         let root = KeyPair::new(&mut rng);
-
         let mut builder = Biscuit::builder(&root);
-
-        builder.add_right("file1", "read");
-        builder.add_right("file2", "read");
-
+        builder.add_authority_fact("user(#authority, \"user-secret-id-1234\")").unwrap();
+        builder.add_right("/folder1/file1", "read");
         let biscuit1 = builder.build(&mut rng).unwrap();
 
-        println!("biscuit1 (authority): {}", biscuit1.print());
-
         let mut block2 = biscuit1.create_block();
+        block2.check_right("read");
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
 
-        block2.expiration_date(SystemTime::now() + Duration::from_secs(30));
-        block2.revocation_id(1234);
+        let secret = b"secret key";
+        let sealed = biscuit2.seal_encrypted(&mut rng, &secret[..]).unwrap();
+
+        // the content is not readable without the secret
+        let needle = b"user-secret-id-1234";
+        assert!(!sealed.windows(needle.len()).any(|w| w == &needle[..]));
+        let plain = biscuit2.seal(&secret[..]).unwrap();
+        assert!(plain.windows(needle.len()).any(|w| w == &needle[..]));
+
+        let biscuit3 = Biscuit::from_sealed_encrypted(&sealed, &secret[..]).unwrap();
+        let mut verifier = biscuit3.verify_sealed().unwrap();
+        verifier.add_resource("/folder1/file1");
+        verifier.add_operation("read");
+        verifier.verify().unwrap();
+
+        assert_eq!(
+            Biscuit::from_sealed_encrypted(&sealed, &b"other secret"[..]).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert_eq!(
+            Biscuit::from_sealed_encrypted(&tampered, &secret[..]).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+    }
+
+    #[test]
+    fn sealed_encrypted_token_with_keyring() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let old_secret = b"old secret".to_vec();
+        let new_secret = b"new secret".to_vec();
+        let sealed_old = biscuit1.seal_encrypted_with_key_id(&mut rng, 1, &old_secret).unwrap();
+        let sealed_new = biscuit1.seal_encrypted_with_key_id(&mut rng, 2, &new_secret).unwrap();
+
+        let mut keyring = std::collections::HashMap::new();
+        keyring.insert(1, old_secret.clone());
+        keyring.insert(2, new_secret.clone());
+
+        for sealed in [&sealed_old, &sealed_new].iter() {
+            let biscuit = Biscuit::from_sealed_encrypted_with_keyring(sealed, &keyring).unwrap();
+            let mut verifier = biscuit.verify_sealed().unwrap();
+            verifier.add_resource("/folder1/file1");
+            verifier.add_operation("read");
+            verifier.verify().unwrap();
+        }
+
+        // the key id is authenticated: changing it fails even if the
+        // secret it now points to is the right one
+        let mut proto = schema::EncryptedBiscuit::decode(&sealed_old[..]).unwrap();
+        proto.key_id = Some(3);
+        let mut tampered = Vec::new();
+        proto.encode(&mut tampered).unwrap();
+        keyring.insert(3, old_secret.clone());
+        assert_eq!(
+            Biscuit::from_sealed_encrypted_with_keyring(&tampered, &keyring).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+        assert_eq!(
+            Biscuit::from_sealed_encrypted(&tampered, &old_secret).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+
+        keyring.remove(&1);
+        assert_eq!(
+            Biscuit::from_sealed_encrypted_with_keyring(&sealed_old, &keyring).unwrap_err(),
+            Token::Format(Format::UnknownSealingKey(1))
+        );
+    }
+
+    #[test]
+    fn sealed_token_tampering() {
+        use hmac::{Hmac, Mac, NewMac};
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let secret = b"secret key";
+        let sealed = biscuit1.seal(&secret[..]).unwrap();
+        Biscuit::from_sealed(&sealed, &secret[..]).unwrap();
+
+        // tokens sealed before key derivation used the raw secret
+        let mut legacy = sealed::SealedBiscuit::from_token(&biscuit1, &secret[..]).unwrap();
+        legacy.version = sealed::SEALED_VERSION_RAW_SECRET;
+        let mut mac = Hmac::<sha2::Sha256>::new_varkey(&secret[..]).unwrap();
+        mac.update(&legacy.authority);
+        legacy.signature = mac.finalize().into_bytes().to_vec();
+        let legacy = legacy.to_vec().unwrap();
+        Biscuit::from_sealed(&legacy, &secret[..]).unwrap();
+
+        // the signature was generated with a derived key, the raw secret cannot verify it
+        let mut proto = schema::SealedBiscuit::decode(&sealed[..]).unwrap();
+        proto.version = None;
+        let mut downgraded = Vec::new();
+        proto.encode(&mut downgraded).unwrap();
+        assert_eq!(
+            Biscuit::from_sealed(&downgraded, &secret[..]).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+
+        let mut proto = schema::SealedBiscuit::decode(&sealed[..]).unwrap();
+        proto.signature[0] ^= 1;
+        let mut tampered = Vec::new();
+        proto.encode(&mut tampered).unwrap();
+        assert_eq!(
+            Biscuit::from_sealed(&tampered, &secret[..]).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+
+        let mut proto = schema::SealedBiscuit::decode(&sealed[..]).unwrap();
+        proto.signature.truncate(16);
+        let mut short_signature = Vec::new();
+        proto.encode(&mut short_signature).unwrap();
+        assert_eq!(
+            Biscuit::from_sealed(&short_signature, &secret[..]).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+
+        // with the same bytes split differently between the blocks, the
+        // signature does not match
+        let block = biscuit1.create_block();
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block).unwrap();
+        let sealed2 = biscuit2.seal(&secret[..]).unwrap();
+        let mut proto = schema::SealedBiscuit::decode(&sealed2[..]).unwrap();
+        let moved = proto.blocks.remove(0);
+        proto.authority.extend(moved);
+        let mut merged = Vec::new();
+        proto.encode(&mut merged).unwrap();
+        assert_eq!(
+            Biscuit::from_sealed(&merged, &secret[..]).unwrap_err(),
+            Token::Format(Format::SealedSignature)
+        );
+
+        for len in &[0, 1, sealed.len() / 2, sealed.len() - 1] {
+            match Biscuit::from_sealed(&sealed[..*len], &secret[..]) {
+                Err(Token::Format(Format::DeserializationError(_)))
+                | Err(Token::Format(Format::SealedSignature)) => {}
+                res => panic!("truncated token at {} bytes should be rejected, got {:?}", len, res),
+            }
+        }
+    }
+
+    #[test]
+    fn verif_no_blocks() {
+      use crate::token::builder::*;
+
+      let mut rng: StdRng = SeedableRng::seed_from_u64(1234);
+      let root = KeyPair::new(&mut rng);
+
+      let mut builder = Biscuit::builder(&root);
+
+      builder.add_authority_fact(fact("right", &[s("authority"), string("file1"), s("read")])).unwrap();
+      builder.add_authority_fact(fact("right", &[s("authority"), string("file2"), s("read")])).unwrap();
+      builder.add_authority_fact(fact("right", &[s("authority"), string("file1"), s("write")])).unwrap();
+
+      let biscuit1 = builder.build(&mut rng).unwrap();
+      println!("{}", biscuit1.print());
+
+      let mut v = biscuit1.verify(root.public()).expect("omg verifier");
+
+      v.add_caveat(rule("right",
+          &[s("right")],
+          &[pred("right", &[s("authority"), string("file2"), s("write")])]
+      )).unwrap();
+
+      //assert!(v.verify().is_err());
+      let res = v.verify();
+      println!("res: {:?}", res);
+      assert_eq!(res,
+        Err(Token::FailedLogic(Logic::FailedCaveats(vec![
+          FailedCaveat::Verifier(FailedVerifierCaveat { caveat_id: 0, rule: String::from("*right(#right) <- right(#authority, \"file2\", #write)") }),
+      ]))));
+    }
+
+    #[test]
+    fn verifier_queries() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut builder = Biscuit::builder(&root);
+
+        builder.add_right("file1", "read");
+        builder.add_right("file2", "read");
+
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        println!("biscuit1 (authority): {}", biscuit1.print());
+
+        let mut block2 = biscuit1.create_block();
+
+        block2.expiration_date(SystemTime::now() + Duration::from_secs(30));
+        block2.revocation_id(1234);
 
         let keypair2 = KeyPair::new(&mut rng);
         let biscuit2 = biscuit1
@@ -1311,4 +2093,1000 @@ mod tests {
         println!("query result: {:x?}", res);
         println!("query result: {}", res[0]);
     }
+
+    #[test]
+    fn set_constraints() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_authority_fact("operations(#authority, [#read, #write], true)").unwrap();
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        block2.add_caveat("*can_read($ops) <- operations(#authority, $ops, true) @ $ops contains #read").unwrap();
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1
+            .append(&mut rng, &keypair2, block2)
+            .unwrap();
+
+        let serialized = biscuit2.to_vec().unwrap();
+        let biscuit2 = Biscuit::from(&serialized).unwrap();
+        println!("biscuit2: {}", biscuit2.print());
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.add_caveat("*ok($ops) <- operations(#authority, $ops, true) @ $ops intersection [#write, #delete]").unwrap();
+        verifier.add_caveat("*admin($ops) <- operations(#authority, $ops, true) @ $ops contains [#read, #admin]").unwrap();
+
+        let res = verifier.verify();
+        println!("res1: {:?}", res);
+        assert_eq!(
+            res,
+            Err(Token::FailedLogic(Logic::FailedCaveats(vec![
+                FailedCaveat::Verifier(FailedVerifierCaveat {
+                    caveat_id: 1,
+                    rule: String::from("*admin($ops) <- operations(#authority, $ops, true) @ $ops contains [#read, #admin]"),
+                }),
+            ]))));
+
+        // an empty set constraint could not be deserialized
+        let mut block3 = biscuit2.create_block();
+        let res = block3.add_caveat(builder::constrained_rule(
+            "empty",
+            &[var("ops")],
+            &[pred("operations", &[s("authority"), var("ops"), builder::boolean(true)])],
+            &[builder::Constraint {
+                id: "ops".to_string(),
+                kind: builder::ConstraintKind::Set(builder::SetConstraint::Intersection(BTreeSet::new())),
+            }],
+        ));
+        assert_eq!(
+            res,
+            Err(Token::InvalidSet(
+                "*empty($ops) <- operations(#authority, $ops, true) @ $ops intersection []".to_string()
+            ))
+        );
+
+        // neither could a set containing a variable or another set
+        let nested = builder::set([builder::set(BTreeSet::new())].iter().cloned().collect());
+        assert_eq!(
+            block3.add_fact(fact("nested", &[nested])),
+            Err(Token::InvalidSet("nested([[]])".to_string()))
+        );
+        let mut builder = Biscuit::builder(&root);
+        let variable = builder::set([var("ops")].iter().cloned().collect());
+        assert_eq!(
+            builder.add_authority_rule(rule("variable", &[var("ops")], &[pred("operations", &[variable])])),
+            Err(Token::InvalidSet("*variable($ops) <- operations([$ops])".to_string()))
+        );
+    }
+
+    #[test]
+    fn unsafe_rules() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("file1", "read");
+        assert_eq!(
+            builder.add_authority_rule("*right(#authority, $0, $1) <- resource(#ambient, $0)"),
+            Err(Token::UnsafeRule(UnsafeRule {
+                rule: String::from("*right(#authority, $0, $1) <- resource(#ambient, $0)"),
+                variables: vec![String::from("1")],
+            }))
+        );
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        assert_eq!(
+            block2.add_caveat("*expiration($date) <- time(#ambient, $0) @ $date <= 2030-12-31T12:59:59+00:00"),
+            Err(Token::UnsafeRule(UnsafeRule {
+                rule: String::from("*expiration($date) <- time(#ambient, $0) @ $date <= 2030-12-31T12:59:59+00:00"),
+                variables: vec![String::from("date")],
+            }))
+        );
+        assert!(block2.caveats.is_empty());
+
+        let mut verifier = biscuit1.verify(root.public()).unwrap();
+        assert!(verifier.add_rule("*a($x) <- b($y)").is_err());
+        assert!(verifier.query("*a($x) <- b($y)").is_err());
+        assert!(verifier.add_caveat("*a(#ok) <- b($y) || *c($z) <- d(#ok)").is_err());
+
+        // a block built without the builder can still contain an unsafe rule,
+        // it must be rejected when the token is deserialized
+        let mut symbols = default_symbol_table();
+        let mut block = Block::new(0, SymbolTable::new());
+        block.rules.push(
+            builder::rule("right", &[s("authority"), var("0"), var("1")], &[
+                pred("resource", &[s("ambient"), var("0")]),
+            ])
+            .convert(&mut symbols),
+        );
+        block.symbols.symbols = symbols.symbols.split_off(default_symbol_table().symbols.len());
+        block.symbols_mode = SymbolsMode::Declared;
+
+        let biscuit2 = Biscuit::new(&mut rng, &root, default_symbol_table(), block).unwrap();
+        let serialized = biscuit2.to_vec().unwrap();
+        match Biscuit::from(&serialized) {
+            Err(Token::Format(Format::UnsafeRule(_))) => {},
+            res => panic!("expected an unsafe rule error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn incremental_verification() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_authority_fact("owner(#authority, \"alice\", \"file1\")").unwrap();
+        builder.add_authority_rule("*right(#authority, $file, #read) <- resource(#ambient, $file), owner(#authority, \"alice\", $file)").unwrap();
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        block2.add_caveat("*check($file) <- resource(#ambient, $file), operation(#ambient, #read), right(#authority, $file, #read)").unwrap();
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1
+            .append(&mut rng, &keypair2, block2)
+            .unwrap();
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+
+        // the same verifier is reused for multiple requests, only the new
+        // ambient facts are evaluated
+        for (file, expected) in &[("file1", true), ("file2", false), ("file1", true)] {
+            verifier.reset();
+            verifier.add_resource(file);
+            verifier.add_operation("read");
+
+            let res = verifier.verify();
+            println!("{}: {:?}", file, res);
+            assert_eq!(res.is_ok(), *expected);
+        }
+
+        // adding a rule after the facts requires evaluating the whole world again
+        verifier.reset();
+        verifier.add_resource("file2");
+        verifier.add_operation("read");
+        verifier.add_fact("owner(#authority, \"alice\", \"file2\")").unwrap();
+        verifier.add_rule("*right(#authority, $file, #read) <- owner(#authority, \"alice\", $file)").unwrap();
+        let res = verifier.verify();
+        println!("with rule: {:?}", res);
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn holder_possession() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        {
+            let verifier = biscuit1.verify(root.public()).unwrap();
+            assert_eq!(
+                verifier.check_possession(b"challenge", &[0u8; 64]).unwrap_err(),
+                Token::Possession(Possession::MissingHolderKey)
+            );
+        }
+
+        // an attenuation block cannot bind the token
+        let holder = KeyPair::new(&mut rng);
+        let mut block2 = biscuit1.create_block();
+        block2.add_fact(fact("holder", &[builder::bytes(&holder.public().to_bytes())])).unwrap();
+        let keypair2 = KeyPair::new(&mut rng);
+        let unbound = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+        assert_eq!(
+            unbound.holder_key().unwrap_err(),
+            Token::Possession(Possession::MissingHolderKey)
+        );
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        builder.bind_holder(&holder.public());
+        let biscuit2 = builder.build(&mut rng).unwrap();
+        assert_eq!(biscuit2.holder_key().unwrap(), holder.public());
+
+        let challenge = b"nonce: 1234, resource: /folder1/file1, operation: read";
+        let signature = biscuit2.sign_challenge(&mut rng, &holder, &challenge[..]).unwrap();
+
+        let other = KeyPair::new(&mut rng);
+        assert_eq!(
+            biscuit2.sign_challenge(&mut rng, &other, &challenge[..]).unwrap_err(),
+            Token::Possession(Possession::KeyMismatch)
+        );
+
+        {
+            let verifier = biscuit2.verify(root.public()).unwrap();
+            verifier.check_possession(&challenge[..], &signature).unwrap();
+            assert_eq!(
+                verifier.check_possession(b"another challenge", &signature).unwrap_err(),
+                Token::Possession(Possession::InvalidSignature)
+            );
+            assert_eq!(
+                verifier.check_possession(&challenge[..], &signature[..32]).unwrap_err(),
+                Token::Possession(Possession::InvalidSignature)
+            );
+        }
+
+        // appending a block cannot transfer the token to another holder
+        let mut block3 = biscuit2.create_block();
+        block3.add_fact(fact("holder", &[builder::bytes(&other.public().to_bytes())])).unwrap();
+        let keypair3 = KeyPair::new(&mut rng);
+        let biscuit3 = biscuit2.append(&mut rng, &keypair3, block3).unwrap();
+        assert_eq!(biscuit3.holder_key().unwrap(), holder.public());
+        biscuit3.sign_challenge(&mut rng, &holder, &challenge[..]).unwrap();
+        assert_eq!(
+            biscuit3.sign_challenge(&mut rng, &other, &challenge[..]).unwrap_err(),
+            Token::Possession(Possession::KeyMismatch)
+        );
+    }
+
+    #[test]
+    fn batch_signature_verification() {
+        use crate::format::SerializedBiscuit;
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut tokens = Vec::new();
+        for i in 0..4 {
+            let mut builder = Biscuit::builder(&root);
+            builder.add_right(&format!("/folder1/file{}", i), "read");
+            let biscuit1 = builder.build(&mut rng).unwrap();
+
+            let mut block2 = biscuit1.create_block();
+            block2.check_operation("read");
+            let keypair2 = KeyPair::new(&mut rng);
+            let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+            tokens.push(biscuit2.container().unwrap().clone());
+        }
+
+        let res = SerializedBiscuit::verify_batch(&mut rng, &tokens);
+        assert_eq!(res, vec![Ok(()), Ok(()), Ok(()), Ok(())]);
+
+        tokens[2].blocks[0][0] ^= 1;
+        tokens[3].keys.clear();
+        let res = SerializedBiscuit::verify_batch(&mut rng, &tokens);
+        assert_eq!(
+            res,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Format::Signature(Signature::InvalidSignature)),
+                Err(Format::EmptyKeys),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_fingerprints() {
+        use super::builder::{fingerprint, rule};
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, biscuit1.create_block()).unwrap();
+
+        let fingerprint1 = root.public().fingerprint();
+        let fingerprint2 = keypair2.public().fingerprint();
+        assert_eq!(fingerprint1.len(), 32);
+        assert_ne!(fingerprint1, fingerprint2);
+        assert_eq!(fingerprint1, PublicKey::from_bytes(&root.public().to_bytes()).unwrap().fingerprint());
+
+        let printed = biscuit2.print();
+        println!("{}", printed);
+        assert!(printed.contains(&format!("fingerprint: \"{}\"", fingerprint1)));
+        assert!(printed.contains(&format!("fingerprint: \"{}\"", fingerprint2)));
+        assert!(printed.contains(&format!("public key: \"{}\"", hex::encode(keypair2.public().to_bytes()))));
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.add_block_keys();
+        verifier
+            .add_caveat(rule(
+                "signed",
+                &[int(1)],
+                &[pred("block_key", &[s("ambient"), int(1), fingerprint(&keypair2.public())])],
+            ))
+            .unwrap();
+        verifier.verify().unwrap();
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.add_block_keys();
+        verifier
+            .add_caveat(rule(
+                "signed",
+                &[int(1)],
+                &[pred("block_key", &[s("ambient"), int(1), fingerprint(&root.public())])],
+            ))
+            .unwrap();
+        assert!(verifier.verify().is_err());
+
+        // sealed tokens do not carry their keys
+        let sealed = Biscuit::from_sealed(&biscuit2.seal(&b"secret"[..]).unwrap(), &b"secret"[..]).unwrap();
+        assert!(!sealed.print().contains("fingerprint"));
+    }
+
+    #[test]
+    fn deserialization_limits() {
+        use crate::format::limits::DeserializationLimits;
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        builder.add_right("/folder1/file2", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        block2.check_operation("read");
+        block2.add_caveat(rule(
+            "check",
+            &[var("resource")],
+            &[pred("resource", &[s("ambient"), var("resource")])],
+        )).unwrap();
+        block2.set_context("a rather long context".to_string());
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+        let serialized = biscuit2.to_vec().unwrap();
+
+        Biscuit::from(&serialized).unwrap();
+        Biscuit::from_with_limits(&serialized, &DeserializationLimits::unlimited()).unwrap();
+
+        let limited = |f: &dyn Fn(&mut DeserializationLimits)| {
+            let mut limits = DeserializationLimits::default();
+            f(&mut limits);
+            Biscuit::from_with_limits(&serialized, &limits).unwrap_err()
+        };
+
+        assert_eq!(
+            limited(&|l| l.max_size = 16),
+            Token::Format(Format::TooLarge(serialized.len()))
+        );
+        assert_eq!(limited(&|l| l.max_blocks = 1), Token::Format(Format::TooManyBlocks(2)));
+        assert_eq!(limited(&|l| l.max_facts = 1), Token::Format(Format::TooManyFacts(2)));
+        assert_eq!(limited(&|l| l.max_caveats = 1), Token::Format(Format::TooManyCaveats(2)));
+        assert_eq!(limited(&|l| l.max_string_length = 10), Token::Format(Format::StringTooLong(14)));
+
+        let symbols = biscuit2.authority.symbols.symbols.len() + biscuit2.blocks[0].symbols.symbols.len();
+        assert_eq!(
+            limited(&|l| l.max_symbols = symbols - 1),
+            Token::Format(Format::TooManySymbols(symbols))
+        );
+
+        // sealed and encrypted tokens are checked too
+        let secret = b"secret key";
+        let sealed = biscuit2.seal(&secret[..]).unwrap();
+        let encrypted = biscuit2.seal_encrypted(&mut rng, &secret[..]).unwrap();
+        let mut keyring = HashMap::new();
+        keyring.insert(1, secret.to_vec());
+
+        let mut limits = DeserializationLimits::default();
+        limits.max_size = 16;
+        assert_eq!(
+            Biscuit::from_sealed_with_limits(&sealed, &secret[..], &limits).unwrap_err(),
+            Token::Format(Format::TooLarge(sealed.len()))
+        );
+        assert_eq!(
+            Biscuit::from_sealed_encrypted_with_limits(&encrypted, &secret[..], &limits).unwrap_err(),
+            Token::Format(Format::TooLarge(encrypted.len()))
+        );
+
+        let mut limits = DeserializationLimits::default();
+        limits.max_blocks = 1;
+        assert_eq!(
+            Biscuit::from_sealed_with_keyring_and_limits(&sealed, &keyring, &limits).unwrap_err(),
+            Token::Format(Format::TooManyBlocks(2))
+        );
+        assert_eq!(
+            Biscuit::from_sealed_encrypted_with_keyring_and_limits(&encrypted, &keyring, &limits).unwrap_err(),
+            Token::Format(Format::TooManyBlocks(2))
+        );
+
+        let mut limits = DeserializationLimits::default();
+        limits.max_caveats = 1;
+        assert_eq!(
+            Biscuit::from_sealed_with_limits(&sealed, &secret[..], &limits).unwrap_err(),
+            Token::Format(Format::TooManyCaveats(2))
+        );
+    }
+
+    #[test]
+    fn invalid_regex() {
+        use super::builder::{constrained_rule, Constraint, ConstraintKind};
+        use crate::datalog::StrConstraint;
+        use crate::format::convert::{proto_block_to_token_block, token_block_to_proto_block};
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+
+        builder.add_authority_rule("*file($0) <- resource(#ambient, $0) @ $0 matches /file[0-9]/").unwrap();
+        assert_eq!(
+            builder.add_authority_rule("*file($0) <- resource(#ambient, $0) @ $0 matches /file[0-9/"),
+            Err(Token::ParseError)
+        );
+
+        // the builder API cannot hold an invalid regex
+        assert!(builder::RegexPattern::new("file[0-9").is_err());
+
+        builder.add_authority_caveat(constrained_rule(
+            "check",
+            &[var("0")],
+            &[pred("resource", &[s("ambient"), var("0")])],
+            &[Constraint {
+                id: "0".to_string(),
+                kind: ConstraintKind::String(StrConstraint::Regex(
+                    builder::RegexPattern::new("file[0-9]+").unwrap(),
+                )),
+            }],
+        )).unwrap();
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        // a serialized block is rejected if the regex is invalid, or too large
+        // to be compiled
+        let mut proto = token_block_to_proto_block(&biscuit1.authority);
+        proto_block_to_token_block(&proto).unwrap();
+
+        let regex = &mut proto.caveats[0].queries[0].constraints[0].str.as_mut().unwrap().regex;
+        assert_eq!(regex.as_deref(), Some("file[0-9]+"));
+        *regex = Some("file[0-9".to_string());
+        match proto_block_to_token_block(&proto) {
+            Err(Format::InvalidRegex(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        proto.caveats[0].queries[0].constraints[0].str.as_mut().unwrap().regex = Some("a".repeat(2000));
+        assert_eq!(
+            proto_block_to_token_block(&proto).unwrap_err(),
+            Format::RegexTooLarge(2000)
+        );
+    }
+
+    #[test]
+    fn path_caveats() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_authority_rule("*right(#authority, $0, #read) <- resource(#ambient, $0)").unwrap();
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        block2.resource_path_prefix("/buckets/b1");
+        block2.resource_glob("/buckets/*/objects/**");
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+
+        // the constraints survive serialization
+        let biscuit2 = Biscuit::from(&biscuit2.to_vec().unwrap()).unwrap();
+        println!("{}", biscuit2.print());
+
+        for (resource, valid) in &[
+            ("/buckets/b1/objects/o1", true),
+            ("/buckets/b1/objects/dir/o1", true),
+            ("/buckets/b10/objects/o1", false),
+            ("/buckets/b1/metadata", false),
+            ("/buckets/b1/objects/../../b2/objects/o1", false),
+        ] {
+            let mut verifier = biscuit2.verify(root.public()).unwrap();
+            verifier.add_resource(resource);
+            assert_eq!(verifier.verify().is_ok(), *valid, "resource: {}", resource);
+        }
+    }
+
+    #[test]
+    fn relative_expiration() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        block2.valid_for(Duration::from_secs(300));
+        block2.add_caveat("*check($0) <- time(#ambient, $0) @ $0 <= 2030-12-31T12:59:59+00:00 + P1D").unwrap();
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+        println!("{}", biscuit2.print());
+        assert!(biscuit2.print().contains("issued_at("));
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.set_time();
+        verifier.verify().unwrap();
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.add_fact(fact("time", &[s("ambient"), super::builder::date(&(SystemTime::now() + Duration::from_secs(400)))])).unwrap();
+        assert!(verifier.verify().is_err());
+    }
+
+    #[test]
+    fn not_before() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+        let now = SystemTime::now();
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        builder.not_before(now + Duration::from_secs(3600));
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        block2.expiration_date(now + Duration::from_secs(60));
+        block2.not_before(now - Duration::from_secs(60));
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.set_time();
+        let res = verifier.verify();
+        println!("res: {:?}", res);
+        match res {
+            Err(Token::FailedLogic(Logic::FailedCaveats(v))) => {
+                assert_eq!(v.len(), 1);
+                match &v[0] {
+                    FailedCaveat::NotYetValid(FailedBlockCaveat { block_id: 0, caveat_id: 0, .. }) => {}
+                    e => panic!("unexpected error: {:?}", e),
+                }
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.add_fact(fact("time", &[s("ambient"), super::builder::date(&(now + Duration::from_secs(7200)))])).unwrap();
+        let res = verifier.verify();
+        println!("res: {:?}", res);
+        match res {
+            Err(Token::FailedLogic(Logic::FailedCaveats(v))) => {
+                assert_eq!(v.len(), 1);
+                match &v[0] {
+                    FailedCaveat::Expired(FailedBlockCaveat { block_id: 1, caveat_id: 0, .. }) => {}
+                    e => panic!("unexpected error: {:?}", e),
+                }
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // other caveats on the time are reported as usual
+        let mut block3 = biscuit2.create_block();
+        block3.add_caveat("*check($0) <- time(#ambient, $0) @ $0 <= 2000-01-01T00:00:00Z, $0 >= 1999-01-01T00:00:00Z").unwrap();
+        let keypair3 = KeyPair::new(&mut rng);
+        let biscuit3 = biscuit2.append(&mut rng, &keypair3, block3).unwrap();
+        let mut verifier = biscuit3.verify(root.public()).unwrap();
+        verifier.add_fact(fact("time", &[s("ambient"), super::builder::date(&(now + Duration::from_secs(3660)))])).unwrap();
+        match verifier.verify() {
+            Err(Token::FailedLogic(Logic::FailedCaveats(v))) => {
+                assert_eq!(v.len(), 2);
+                assert!(matches!(v[0], FailedCaveat::Expired(_)));
+                assert!(matches!(v[1], FailedCaveat::Block(FailedBlockCaveat { block_id: 2, .. })));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // without the current time, the token is neither expired nor not yet valid
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        match verifier.verify() {
+            Err(Token::FailedLogic(Logic::FailedCaveats(v))) => {
+                assert_eq!(v.len(), 3);
+                assert!(v.iter().all(|c| matches!(c, FailedCaveat::Block(_))));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn parameters() {
+        use super::builder::{self, string, Atom};
+        use crate::error::Parameters;
+        use std::{collections::HashMap, convert::TryFrom};
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        // a value that would change the fact if it was formatted in the string
+        let user = "alice\", #admin";
+        let mut params = HashMap::new();
+        params.insert("user".to_string(), string(user));
+
+        let mut builder = Biscuit::builder(&root);
+        builder
+            .add_authority_fact_with_params("user(#authority, {user})", &params)
+            .unwrap();
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        params.insert("resource".to_string(), string("file1"));
+        // the value of a constraint parameter must match the operator
+        assert_eq!(
+            block2.add_caveat_with_params(
+                "*check($user) <- user(#authority, $user), resource(#ambient, {resource}) @ $user > {user}",
+                &params,
+            ),
+            Err(Token::Parameters(Parameters::InvalidValue("user".to_string())))
+        );
+        let mut check_user = builder::Caveat::try_from(
+            "*check($user) <- user(#authority, $user), resource(#ambient, {resource}) @ $user == {user}",
+        )
+        .unwrap();
+        check_user.apply_parameters(&params).unwrap();
+        assert_eq!(
+            check_user.to_string(),
+            "*check($user) <- user(#authority, $user), resource(#ambient, \"file1\") @ $user == \"alice\\\", #admin\""
+        );
+        block2.add_caveat(check_user).unwrap();
+        block2
+            .add_caveat_with_params(
+                "*check({resource}) <- user(#authority, {user}), resource(#ambient, {resource})",
+                &params,
+            )
+            .unwrap();
+
+        assert_eq!(
+            block2.add_fact_with_params("file({resource})", &params),
+            Err(Token::Parameters(Parameters::Unused(vec!["user".to_string()])))
+        );
+        assert_eq!(
+            block2.add_fact_with_params("file({resource}, {owner})", &params),
+            Err(Token::Parameters(Parameters::Missing(vec!["owner".to_string()])))
+        );
+        assert_eq!(
+            block2.add_fact("file({resource})"),
+            Err(Token::Parameters(Parameters::Missing(vec!["resource".to_string()])))
+        );
+        let mut variable = HashMap::new();
+        variable.insert("resource".to_string(), var("0"));
+        assert_eq!(
+            block2.add_fact_with_params("file({resource})", &variable),
+            Err(Token::Parameters(Parameters::InvalidValue("resource".to_string())))
+        );
+        // parameters and variables are also found inside sets
+        assert_eq!(
+            block2.add_fact(fact("file", &[builder::set(vec![Atom::Parameter("resource".to_string())].into_iter().collect())])),
+            Err(Token::Parameters(Parameters::Missing(vec!["resource".to_string()])))
+        );
+        let mut nested = HashMap::new();
+        nested.insert("resource".to_string(), builder::set(vec![var("0")].into_iter().collect()));
+        assert_eq!(
+            block2.add_fact_with_params("file({resource})", &nested),
+            Err(Token::Parameters(Parameters::InvalidValue("resource".to_string())))
+        );
+        // a parameter inside a set cannot be replaced by another set
+        let mut set = HashMap::new();
+        set.insert("x".to_string(), builder::set(vec![int(1)].into_iter().collect()));
+        let in_set = builder::set(vec![Atom::Parameter("x".to_string())].into_iter().collect());
+        assert_eq!(
+            block2.add_fact_with_params(fact("f", &[in_set]), &set),
+            Err(Token::Parameters(Parameters::InvalidValue("x".to_string())))
+        );
+        let check = builder::constrained_rule(
+            "check",
+            &[var("0")],
+            &[pred("file", &[var("0")])],
+            &[builder::Constraint {
+                id: "0".to_string(),
+                kind: builder::ConstraintKind::Set(builder::SetConstraint::Intersection(
+                    vec![Atom::Parameter("x".to_string())].into_iter().collect(),
+                )),
+            }],
+        );
+        assert_eq!(
+            block2.add_caveat_with_params(check, &set),
+            Err(Token::Parameters(Parameters::InvalidValue("x".to_string())))
+        );
+        assert_eq!(block2.facts.len(), 0);
+        assert_eq!(block2.caveats.len(), 2);
+
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+        println!("biscuit2:\n{}", biscuit2.print());
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.add_resource("file1");
+        let res: Vec<builder::Fact> = verifier.query("*data($u) <- user(#authority, $u)").unwrap();
+        assert_eq!(res, vec![fact("data", &[string(user)])]);
+        verifier.verify().unwrap();
+
+        let mut verifier = biscuit2.verify(root.public()).unwrap();
+        verifier.add_resource("file2");
+        verifier.verify().unwrap_err();
+
+        assert!(matches!(
+            verifier.add_fact(fact("user", &[s("ambient"), Atom::Parameter("user".to_string())])),
+            Err(Token::Parameters(Parameters::Missing(_)))
+        ));
+    }
+
+    #[test]
+    fn diff() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("/folder1/file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+
+        let mut block2 = biscuit1.create_block();
+        block2.check_resource("/folder1/file1");
+        block2.add_fact("owner(\"alice\", \"/folder1/file1\")").unwrap();
+        block2.set_context("alice".to_string());
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+
+        let mut block3 = biscuit2.create_block();
+        block3.check_operation("read");
+        let keypair3 = KeyPair::new(&mut rng);
+        let biscuit3 = biscuit2.append(&mut rng, &keypair3, block3).unwrap();
+
+        let diff = biscuit1.diff(&biscuit3).unwrap();
+        assert!(diff.is_extension());
+        assert_eq!(diff.common_blocks, 1);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.added.len(), 2);
+        assert_eq!(
+            diff.added[0].to_string(),
+            "owner(\"alice\", \"/folder1/file1\");\ncaveat *resource_check(#resource_check) <- resource(#ambient, \"/folder1/file1\");\n"
+        );
+        assert_eq!(diff.added[0].context, Some("alice".to_string()));
+        assert_eq!(
+            diff.added[1].to_string(),
+            "caveat *operation_check(#operation_check) <- operation(#ambient, #read);\n"
+        );
+
+        // the printed blocks can be parsed back
+        let parsed = crate::parser::block(&diff.added[1].to_string()).unwrap().1;
+        assert_eq!(parsed.caveats, diff.added[1].caveats);
+
+        // serialization does not change the comparison
+        let deserialized = Biscuit::from(&biscuit2.to_vec().unwrap()).unwrap();
+        let diff = deserialized.diff(&biscuit3).unwrap();
+        assert_eq!(diff.common_blocks, 2);
+        assert_eq!(diff.added.len(), 1);
+
+        // the same block appended with another key is a different token
+        let mut block3 = biscuit2.create_block();
+        block3.check_operation("read");
+        let keypair3bis = KeyPair::new(&mut rng);
+        let biscuit3bis = biscuit2.append(&mut rng, &keypair3bis, block3).unwrap();
+        let diff = biscuit3.diff(&biscuit3bis).unwrap();
+        assert!(!diff.is_extension());
+        assert_eq!(diff.common_blocks, 2);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.added.len(), 1);
+
+        // a token from another root shares nothing
+        let root2 = KeyPair::new(&mut rng);
+        let mut builder = Biscuit::builder(&root2);
+        builder.add_right("/folder1/file1", "read");
+        let other = builder.build(&mut rng).unwrap();
+        let diff = biscuit1.diff(&other).unwrap();
+        assert!(!diff.is_extension());
+        assert_eq!(diff.common_blocks, 0);
+
+        assert_eq!(biscuit3.diff(&biscuit1).unwrap().removed.len(), 2);
+        assert!(!biscuit3.diff(&biscuit1).unwrap().is_extension());
+        assert!(biscuit2.diff(&biscuit2).unwrap().is_extension());
+
+        // sealed tokens have no keys to compare
+        let sealed1 = Biscuit::from_sealed(&biscuit2.seal(b"secret1").unwrap(), b"secret1").unwrap();
+        let sealed2 = Biscuit::from_sealed(&biscuit2.seal(b"secret2").unwrap(), b"secret2").unwrap();
+        assert_eq!(sealed1.diff(&sealed2), Err(Token::Sealed));
+        assert_eq!(biscuit1.diff(&sealed1), Err(Token::Sealed));
+    }
+
+    #[test]
+    fn lint() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+        assert_eq!(biscuit1.lint(), vec![]);
+
+        let mut block2 = biscuit1.create_block();
+        block2.add_fact("owner(\"alice\", \"file1\")").unwrap();
+        block2.add_fact("unused(\"x\")").unwrap();
+        let caveats = [
+            "*c($0) <- resource(#ambient, $0), right(#authority, $0, #read)",
+            "*c($0) <- resource(#ambient, $0), owner(\"alice\", $0)",
+            "*c(#read) <- right(#authority, \"file1\", #read)",
+            "*c($0) <- resource(#ambient, $0) @ $0 == \"a\", $0 == \"b\"",
+            "*c($0) <- resource(#ambient, $0), missing(\"x\", $0)",
+            "*c($0) <- level(#ambient, $0) @ $0 > 5, $0 < 1",
+            "*c($0) <- level(#ambient, $0) @ $0 >= 1, $0 <= 2, $0 not in [1, 2]",
+            "*c($0) <- time(#ambient, $0) @ $0 <= 2020-01-01T00:00:00+00:00, $0 >= 2021-01-01T00:00:00+00:00",
+            "*c($0) <- resource(#ambient, $0) @ $0 == \"a\" || *c($0) <- resource(#ambient, $0) @ $0 == 1",
+        ];
+        for caveat in caveats.iter() {
+            block2.add_caveat(*caveat).unwrap();
+        }
+        // read by the verifier, or kept as information
+        block2.revocation_id(1);
+        block2.valid_for(Duration::from_secs(3600));
+        let keypair2 = KeyPair::new(&mut rng);
+        let biscuit2 = biscuit1.append(&mut rng, &keypair2, block2).unwrap();
+
+        let lints = biscuit2.lint();
+        assert_eq!(
+            lints[0].to_string(),
+            "block 1: `missing(\"x\", $0)` is not produced by the token, in `*c($0) <- resource(#ambient, $0), missing(\"x\", $0)`"
+        );
+        assert_eq!(
+            lints,
+            vec![
+                Lint::UnreachablePredicate {
+                    block_id: 1,
+                    predicate: "missing(\"x\", $0)".to_string(),
+                    rule: caveats[4].to_string(),
+                },
+                Lint::CaveatAlwaysSucceeds {
+                    block_id: 1,
+                    caveat_id: 2,
+                    caveat: caveats[2].to_string(),
+                },
+                Lint::CaveatNeverSucceeds {
+                    block_id: 1,
+                    caveat_id: 3,
+                    caveat: caveats[3].to_string(),
+                },
+                Lint::CaveatNeverSucceeds {
+                    block_id: 1,
+                    caveat_id: 5,
+                    caveat: caveats[5].to_string(),
+                },
+                Lint::CaveatNeverSucceeds {
+                    block_id: 1,
+                    caveat_id: 6,
+                    caveat: caveats[6].to_string(),
+                },
+                Lint::CaveatNeverSucceeds {
+                    block_id: 1,
+                    caveat_id: 7,
+                    caveat: caveats[7].to_string(),
+                },
+                Lint::UnusedFact {
+                    block_id: 1,
+                    fact: "unused(\"x\")".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn symbol_reuse() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        // common words are in the default table
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+        assert!(biscuit1.authority.symbols.symbols.is_empty());
+        assert_eq!(biscuit1.authority.version, SYMBOLS_VERSION_EXTENDED);
+
+        // a block built from a copy of the token's table
+        let mut block = Block::new(0, default_symbol_table());
+        let fact = builder::fact("right", &[s("authority"), builder::string("file1"), s("owner")]);
+        block.facts.push(fact.convert(&mut block.symbols));
+        let biscuit2 = Biscuit::new(&mut rng, &root, default_symbol_table(), block).unwrap();
+        assert_eq!(biscuit2.authority.symbols.symbols, vec!["owner".to_string()]);
+
+        // a block declaring symbols that are already in the table
+        let mut symbols = default_symbol_table();
+        symbols.insert("file");
+        symbols.insert("read");
+        symbols.insert("owner");
+        let mut block = Block::new(0, SymbolTable::new());
+        let fact = builder::fact("right", &[s("authority"), s("file"), s("read"), s("owner")]);
+        block.facts.push(fact.convert(&mut symbols));
+        block.symbols.symbols = symbols.symbols.split_off(default_symbol_table().symbols.len());
+        block.symbols_mode = SymbolsMode::Declared;
+        let biscuit3 = Biscuit::new(&mut rng, &root, default_symbol_table(), block).unwrap();
+        assert_eq!(
+            biscuit3.authority.symbols.symbols,
+            vec!["file".to_string(), "owner".to_string()]
+        );
+
+        let mut block4 = biscuit3.create_block();
+        block4.check_operation("read");
+        block4.add_fact("owner(\"alice\", #owner)").unwrap();
+        let keypair4 = KeyPair::new(&mut rng);
+        let biscuit4 = biscuit3.append(&mut rng, &keypair4, block4).unwrap();
+        assert!(!biscuit4.blocks[0].symbols.symbols.contains(&"owner".to_string()));
+
+        let deser = Biscuit::from(&biscuit4.to_vec().unwrap()).unwrap();
+        let mut verifier = deser.verify(root.public()).unwrap();
+        verifier.add_operation("read");
+        let res = verifier
+            .query("*data($0) <- right(#authority, #file, #read, $0), owner(\"alice\", $0)")
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        verifier.verify().unwrap();
+
+        // tokens created with the initial default table are still read
+        let mut symbols = initial_symbol_table();
+        let mut block = Block::new(0, SymbolTable::new());
+        let fact = builder::fact("right", &[s("authority"), builder::string("file1"), s("read")]);
+        block.facts.push(fact.convert(&mut symbols));
+        block.symbols.symbols = symbols.symbols.split_off(initial_symbol_table().symbols.len());
+        block.symbols_mode = SymbolsMode::Declared;
+        block.version = SYMBOLS_VERSION_INITIAL;
+        let biscuit5 = Biscuit::new(&mut rng, &root, initial_symbol_table(), block).unwrap();
+        assert_eq!(biscuit5.authority.symbols.symbols, vec!["read".to_string()]);
+
+        let deser = Biscuit::from(&biscuit5.to_vec().unwrap()).unwrap();
+        assert_eq!(deser.symbols, biscuit5.symbols);
+        let mut verifier = deser.verify(root.public()).unwrap();
+        verifier.add_resource("file1");
+        verifier.add_operation("read");
+        verifier.add_caveat("*check($0) <- resource(#ambient, $0), operation(#ambient, #read), right(#authority, $0, #read)").unwrap();
+        verifier.verify().unwrap();
+
+        // a block created with another table than the token's is remapped
+        let mut block = Block::new(0, SymbolTable::new());
+        let fact = builder::fact("right", &[s("authority"), s("file"), s("read")]);
+        block.facts.push(fact.convert(&mut block.symbols));
+        let biscuit6 = Biscuit::new(&mut rng, &root, default_symbol_table(), block).unwrap();
+        assert_eq!(biscuit6.authority.symbols.symbols, vec!["file".to_string()]);
+        let mut verifier = biscuit6.verify(root.public()).unwrap();
+        verifier.add_caveat("*check() <- right(#authority, #file, #read)").unwrap();
+        verifier.verify().unwrap();
+    }
+
+    #[test]
+    fn symbols_version() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new(&mut rng);
+
+        let mut builder = Biscuit::builder(&root);
+        builder.add_right("file1", "read");
+        let biscuit1 = builder.build(&mut rng).unwrap();
+        let serialized = biscuit1.to_vec().unwrap();
+
+        // readers that ignore the version would use the initial table: the
+        // signature does not match the blocks as they see them
+        let container = SerializedBiscuit::from_slice(&serialized).unwrap();
+        assert_eq!(container.version, SYMBOLS_VERSION_EXTENDED);
+        assert!(container
+            .signature
+            .verify(&container.keys, &[container.authority.clone()])
+            .is_err());
+
+        let mut proto = schema::Biscuit::decode(&serialized[..]).unwrap();
+        proto.version = None;
+        let mut downgraded = Vec::new();
+        proto.encode(&mut downgraded).unwrap();
+        assert!(matches!(
+            Biscuit::from(&downgraded),
+            Err(Token::Format(Format::Signature(_)))
+        ));
+
+        // versions from the future are rejected
+        proto.version = Some(SYMBOLS_VERSION_EXTENDED + 1);
+        let mut upgraded = Vec::new();
+        proto.encode(&mut upgraded).unwrap();
+        assert!(matches!(
+            Biscuit::from(&upgraded),
+            Err(Token::Format(Format::DeserializationError(_)))
+        ));
+
+        let mut block = crate::format::convert::token_block_to_proto_block(&biscuit1.authority);
+        block.version = Some(SYMBOLS_VERSION_EXTENDED + 1);
+        assert!(matches!(
+            crate::format::convert::proto_block_to_token_block(&block),
+            Err(Format::BlockDeserializationError(_))
+        ));
+
+        let mut authority = biscuit1.authority.clone();
+        authority.version = SYMBOLS_VERSION_EXTENDED + 1;
+        assert!(matches!(
+            Biscuit::new(&mut rng, &root, default_symbol_table(), authority),
+            Err(Token::Format(Format::SerializationError(_)))
+        ));
+    }
 }
@@ -1,22 +1,56 @@
 use super::Biscuit;
 use crate::error;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
 use hmac::{Hmac, Mac, NewMac};
+use rand_core::{CryptoRng, RngCore};
 use sha2::Sha256;
+use std::collections::HashMap;
+use std::convert::TryInto;
 
-use crate::format::{convert::token_block_to_proto_block, schema};
+use crate::format::{convert::token_block_to_proto_block, limits::DeserializationLimits, schema};
 use crate::prost::Message;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// label used to derive the encryption key from the sealing secret
+const ENCRYPTION_KEY_LABEL: &[u8] = b"biscuit sealed token encryption key";
+/// label used to derive the signature key from the sealing secret
+const SIGNATURE_KEY_LABEL: &[u8] = b"biscuit sealed token signature key";
+
+/// the raw secret is used as the HMAC key
+pub const SEALED_VERSION_RAW_SECRET: u32 = 0;
+/// the HMAC key is derived from the secret with HKDF
+pub const SEALED_VERSION_DERIVED_KEY: u32 = 1;
+
 #[derive(Clone, Debug)]
 pub struct SealedBiscuit {
     pub authority: Vec<u8>,
     pub blocks: Vec<Vec<u8>>,
     pub signature: Vec<u8>,
+    /// identifies the secret used to seal the token, to rotate secrets
+    pub key_id: Option<u32>,
+    /// how the signature key is obtained from the secret. Tokens sealed before
+    /// key derivation was introduced have no version, and use the raw secret
+    pub version: u32,
 }
 
 impl SealedBiscuit {
     pub fn from_token(token: &Biscuit, secret: &[u8]) -> Result<Self, error::Format> {
+        SealedBiscuit::seal(token, None, secret)
+    }
+
+    /// seals the token, storing the id of the secret along with the signature
+    pub fn from_token_with_key_id(
+        token: &Biscuit,
+        key_id: u32,
+        secret: &[u8],
+    ) -> Result<Self, error::Format> {
+        SealedBiscuit::seal(token, Some(key_id), secret)
+    }
+
+    fn seal(token: &Biscuit, key_id: Option<u32>, secret: &[u8]) -> Result<Self, error::Format> {
         let mut authority = Vec::new();
         token_block_to_proto_block(&token.authority)
             .encode(&mut authority)
@@ -42,42 +76,247 @@ impl SealedBiscuit {
             }
         }
 
-        let mut mac = HmacSha256::new_varkey(secret).unwrap();
-        mac.update(&authority);
-        for block in blocks.iter() {
-            mac.update(&block);
+        let mut sealed = SealedBiscuit {
+            authority,
+            blocks,
+            signature: Vec::new(),
+            key_id,
+            version: SEALED_VERSION_DERIVED_KEY,
+        };
+        sealed.signature = sealed.mac(secret)?.finalize().into_bytes().to_vec();
+
+        Ok(sealed)
+    }
+
+    pub fn from_slice(slice: &[u8], secret: &[u8]) -> Result<Self, error::Format> {
+        SealedBiscuit::from_slice_with_limits(slice, secret, &DeserializationLimits::default())
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret,
+    /// rejecting it if it exceeds the limits
+    pub fn from_slice_with_limits(
+        slice: &[u8],
+        secret: &[u8],
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Format> {
+        let deser = SealedBiscuit::decode(slice, limits)?;
+        deser.verify(secret)?;
+
+        Ok(deser)
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret
+    /// matching its key id
+    ///
+    /// tokens sealed without a key id are checked against every secret
+    pub fn from_slice_with_keyring(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+    ) -> Result<Self, error::Format> {
+        SealedBiscuit::from_slice_with_keyring_and_limits(slice, keyring, &DeserializationLimits::default())
+    }
+
+    /// deserializes a sealed token and checks its signature with the secret
+    /// matching its key id, rejecting it if it exceeds the limits
+    pub fn from_slice_with_keyring_and_limits(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Format> {
+        let deser = SealedBiscuit::decode(slice, limits)?;
+
+        match deser.key_id {
+            Some(id) => {
+                let secret = keyring
+                    .get(&id)
+                    .ok_or(error::Format::UnknownSealingKey(id))?;
+                deser.verify(secret)?;
+            }
+            None => {
+                if !keyring.values().any(|secret| deser.verify(secret).is_ok()) {
+                    return Err(error::Format::SealedSignature);
+                }
+            }
         }
 
-        let signature: Vec<u8> = mac.finalize().into_bytes().to_vec();
+        Ok(deser)
+    }
 
-        Ok(SealedBiscuit {
-            authority,
-            blocks,
-            signature,
+    /// serializes the token with the authority and blocks encrypted with
+    /// ChaCha20-Poly1305, under a key derived from the secret
+    ///
+    /// the AEAD authenticates the content, so the HMAC signature is not included.
+    /// The key id is stored in clear, to select the secret, and authenticated
+    /// as associated data
+    pub fn to_encrypted_vec<T: RngCore + CryptoRng>(
+        &self,
+        rng: &mut T,
+        secret: &[u8],
+    ) -> Result<Vec<u8>, error::Format> {
+        let content = schema::SealedContent {
+            authority: self.authority.clone(),
+            blocks: self.blocks.clone(),
+        };
+
+        let mut plaintext = Vec::new();
+        content
+            .encode(&mut plaintext)
+            .map_err(|e| error::Format::SerializationError(format!("serialization error: {:?}", e)))?;
+
+        let mut nonce = [0u8; 12];
+        rng.fill_bytes(&mut nonce);
+
+        let aad = associated_data(self.key_id);
+        let ciphertext = encryption_cipher(secret)
+            .encrypt(&Nonce::from(nonce), Payload { msg: &plaintext[..], aad: &aad[..] })
+            .map_err(|_| error::Format::SerializationError("encryption error".to_string()))?;
+
+        let proto = schema::EncryptedBiscuit {
+            nonce: nonce.to_vec(),
+            ciphertext,
+            key_id: self.key_id,
+        };
+
+        let mut v = Vec::new();
+        proto
+            .encode(&mut v)
+            .map(|_| v)
+            .map_err(|e| error::Format::SerializationError(format!("serialization error: {:?}", e)))
+    }
+
+    /// decrypts a token serialized with `to_encrypted_vec`
+    ///
+    /// the returned token has no signature, since it was authenticated by the decryption
+    pub fn from_encrypted_slice(slice: &[u8], secret: &[u8]) -> Result<Self, error::Format> {
+        SealedBiscuit::from_encrypted_slice_with_limits(slice, secret, &DeserializationLimits::default())
+    }
+
+    /// decrypts a token serialized with `to_encrypted_vec`, rejecting it if it
+    /// exceeds the limits
+    pub fn from_encrypted_slice_with_limits(
+        slice: &[u8],
+        secret: &[u8],
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Format> {
+        let deser = SealedBiscuit::decode_encrypted(slice, limits)?.decrypt(secret)?;
+        limits.check_blocks(1 + deser.blocks.len())?;
+
+        Ok(deser)
+    }
+
+    /// decrypts a token serialized with `to_encrypted_vec`, with the secret
+    /// matching its key id
+    ///
+    /// tokens encrypted without a key id are decrypted with every secret
+    pub fn from_encrypted_slice_with_keyring(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+    ) -> Result<Self, error::Format> {
+        SealedBiscuit::from_encrypted_slice_with_keyring_and_limits(
+            slice,
+            keyring,
+            &DeserializationLimits::default(),
+        )
+    }
+
+    /// decrypts a token serialized with `to_encrypted_vec`, with the secret
+    /// matching its key id, rejecting it if it exceeds the limits
+    pub fn from_encrypted_slice_with_keyring_and_limits(
+        slice: &[u8],
+        keyring: &HashMap<u32, Vec<u8>>,
+        limits: &DeserializationLimits,
+    ) -> Result<Self, error::Format> {
+        let proto = SealedBiscuit::decode_encrypted(slice, limits)?;
+
+        let deser = match proto.key_id {
+            Some(id) => {
+                let secret = keyring
+                    .get(&id)
+                    .ok_or(error::Format::UnknownSealingKey(id))?;
+                proto.decrypt(secret)?
+            }
+            None => keyring
+                .values()
+                .find_map(|secret| proto.decrypt(secret).ok())
+                .ok_or(error::Format::SealedSignature)?,
+        };
+        limits.check_blocks(1 + deser.blocks.len())?;
+
+        Ok(deser)
+    }
+
+    /// the blocks are encrypted, so their count is only checked after decryption
+    fn decode_encrypted(
+        slice: &[u8],
+        limits: &DeserializationLimits,
+    ) -> Result<schema::EncryptedBiscuit, error::Format> {
+        limits.check_size(slice.len())?;
+
+        schema::EncryptedBiscuit::decode(slice).map_err(|e| {
+            error::Format::DeserializationError(format!("deserialization error: {:?}", e))
         })
     }
 
-    pub fn from_slice(slice: &[u8], secret: &[u8]) -> Result<Self, error::Format> {
+    fn decode(slice: &[u8], limits: &DeserializationLimits) -> Result<Self, error::Format> {
+        limits.check_size(slice.len())?;
+
         let proto: schema::SealedBiscuit = schema::SealedBiscuit::decode(slice).map_err(|e| {
             error::Format::DeserializationError(format!("deserialization error: {:?}", e))
         })?;
 
-        let deser = SealedBiscuit {
+        limits.check_blocks(1 + proto.blocks.len())?;
+
+        Ok(SealedBiscuit {
             authority: proto.authority,
             blocks: proto.blocks,
             signature: proto.signature,
-        };
+            key_id: proto.key_id,
+            version: proto.version.unwrap_or(SEALED_VERSION_RAW_SECRET),
+        })
+    }
 
-        let mut mac = HmacSha256::new_varkey(secret).unwrap();
-        mac.update(&deser.authority);
-        for block in deser.blocks.iter() {
-            mac.update(&block);
+    fn proto_version(&self) -> Option<u32> {
+        if self.version == SEALED_VERSION_RAW_SECRET {
+            None
+        } else {
+            Some(self.version)
         }
+    }
 
-        mac.verify(&deser.signature)
-            .map_err(|_| error::Format::SealedSignature)?;
+    /// `Mac::verify` compares the signatures in constant time
+    fn verify(&self, secret: &[u8]) -> Result<(), error::Format> {
+        self.mac(secret)?
+            .verify(&self.signature)
+            .map_err(|_| error::Format::SealedSignature)
+    }
 
-        Ok(deser)
+    /// the version and key id are part of the signed data, so that they cannot
+    /// be changed to select another key. Since the derived key version, each
+    /// block is prefixed by its length, so that bytes cannot be moved from one
+    /// block to the next. Tokens without a version keep the signature format
+    /// from before it was introduced
+    fn mac(&self, secret: &[u8]) -> Result<HmacSha256, error::Format> {
+        let mut mac = match self.version {
+            SEALED_VERSION_RAW_SECRET => HmacSha256::new_varkey(secret).unwrap(),
+            SEALED_VERSION_DERIVED_KEY => {
+                let mut mac = HmacSha256::new_varkey(&derive_key(secret, SIGNATURE_KEY_LABEL)).unwrap();
+                mac.update(&self.version.to_le_bytes());
+                mac
+            }
+            _ => return Err(error::Format::SealedSignature),
+        };
+
+        if let Some(id) = self.key_id {
+            mac.update(&id.to_le_bytes());
+        }
+        let framed = self.version != SEALED_VERSION_RAW_SECRET;
+        for block in std::iter::once(&self.authority).chain(self.blocks.iter()) {
+            if framed {
+                mac.update(&(block.len() as u64).to_le_bytes());
+            }
+            mac.update(block);
+        }
+        Ok(mac)
     }
 
     pub fn to_vec(&self) -> Result<Vec<u8>, error::Format> {
@@ -85,6 +324,8 @@ impl SealedBiscuit {
             authority: self.authority.clone(),
             blocks: self.blocks.clone(),
             signature: self.signature.clone(),
+            key_id: self.key_id,
+            version: self.proto_version(),
         };
 
         let mut v = Vec::new();
@@ -99,8 +340,57 @@ impl SealedBiscuit {
             authority: self.authority.clone(),
             blocks: self.blocks.clone(),
             signature: self.signature.clone(),
+            key_id: self.key_id,
+            version: self.proto_version(),
         };
 
         proto.encoded_len()
     }
 }
+
+impl schema::EncryptedBiscuit {
+    fn decrypt(&self, secret: &[u8]) -> Result<SealedBiscuit, error::Format> {
+        let nonce: [u8; 12] = self.nonce[..].try_into().map_err(|_| {
+            error::Format::DeserializationError(
+                "deserialization error: invalid nonce length".to_string(),
+            )
+        })?;
+
+        let aad = associated_data(self.key_id);
+        let plaintext = encryption_cipher(secret)
+            .decrypt(&Nonce::from(nonce), Payload { msg: &self.ciphertext[..], aad: &aad[..] })
+            .map_err(|_| error::Format::SealedSignature)?;
+
+        let content = schema::SealedContent::decode(&plaintext[..]).map_err(|e| {
+            error::Format::DeserializationError(format!("deserialization error: {:?}", e))
+        })?;
+
+        Ok(SealedBiscuit {
+            authority: content.authority,
+            blocks: content.blocks,
+            signature: Vec::new(),
+            key_id: self.key_id,
+            version: SEALED_VERSION_DERIVED_KEY,
+        })
+    }
+}
+
+/// the key id is authenticated along with the ciphertext, so that it cannot
+/// be changed to select another secret
+fn associated_data(key_id: Option<u32>) -> Vec<u8> {
+    key_id.map(|id| id.to_le_bytes().to_vec()).unwrap_or_default()
+}
+
+fn encryption_cipher(secret: &[u8]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(&Key::from(derive_key(secret, ENCRYPTION_KEY_LABEL)))
+}
+
+/// derives a key for one purpose from the sealing secret, so that the
+/// same secret is never used directly by different algorithms
+fn derive_key(secret: &[u8], label: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, secret)
+        .expand(label, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
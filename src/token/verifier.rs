@@ -1,11 +1,12 @@
 use super::builder::{
-    constrained_rule, date, fact, pred, s, string, Constraint, ConstraintKind, Fact,
+    constrained_rule, date, fact, fingerprint, int, pred, s, string, Constraint, ConstraintKind, Fact,
     IntConstraint, Rule, Caveat, var,
 };
-use super::Biscuit;
+use super::{challenge_message, failed_block_caveat, Biscuit};
+use crate::crypto;
 use crate::datalog;
 use crate::error;
-use std::{convert::TryInto, time::SystemTime};
+use std::{collections::HashSet, convert::TryInto, time::SystemTime};
 
 pub struct Verifier<'a> {
     token: &'a Biscuit,
@@ -14,10 +15,16 @@ pub struct Verifier<'a> {
     world: datalog::World,
     symbols: datalog::SymbolTable,
     caveats: Vec<Caveat>,
+    /// facts added since the last time the world was run
+    new_facts: HashSet<datalog::Fact>,
+    /// rules were added since the last time the world was run
+    new_rules: bool,
 }
 
 impl<'a> Verifier<'a> {
     pub(crate) fn new(token: &'a Biscuit) -> Result<Self, error::Logic> {
+        // the token's world is run once here, so that verifying with
+        // different ambient facts only derives the consequences of those facts
         let base_world = token.generate_world(&token.symbols)?;
         let base_symbols = token.symbols.clone();
         let world = base_world.clone();
@@ -30,6 +37,8 @@ impl<'a> Verifier<'a> {
             world,
             symbols,
             caveats: vec![],
+            new_facts: HashSet::new(),
+            new_rules: false,
         })
     }
 
@@ -37,22 +46,31 @@ impl<'a> Verifier<'a> {
         self.caveats.clear();
         self.world = self.base_world.clone();
         self.symbols = self.base_symbols.clone();
+        self.new_facts.clear();
+        self.new_rules = false;
     }
 
-    pub fn snapshot(&mut self) {
+    pub fn snapshot(&mut self) -> Result<(), error::Token> {
+        self.run()?;
         self.base_world = self.world.clone();
         self.base_symbols = self.symbols.clone();
+        Ok(())
     }
 
     pub fn add_fact<F: TryInto<Fact>>(&mut self, fact: F) -> Result<(), error::Token> {
         let fact = fact.try_into().map_err(|_| error::Token::ParseError)?;
-        self.world.facts.insert(fact.convert(&mut self.symbols));
+        fact.validate_parameters()?;
+        let fact = fact.convert(&mut self.symbols);
+        self.insert_fact(fact);
         Ok(())
     }
 
     pub fn add_rule<R: TryInto<Rule>>(&mut self, rule: R) -> Result<(), error::Token> {
         let rule = rule.try_into().map_err(|_| error::Token::ParseError)?;
+        rule.validate_parameters()?;
+        rule.validate_variables()?;
         self.world.rules.push(rule.convert(&mut self.symbols));
+        self.new_rules = true;
         Ok(())
     }
 
@@ -61,7 +79,9 @@ impl<'a> Verifier<'a> {
         rule: R,
     ) -> Result<Vec<Fact>, error::Token> {
         let rule = rule.try_into().map_err(|_| error::Token::ParseError)?;
-        self.world.run();
+        rule.validate_parameters()?;
+        rule.validate_variables()?;
+        self.run()?;
         let mut res = self.world.query_rule(rule.convert(&mut self.symbols));
 
         Ok(res
@@ -73,23 +93,67 @@ impl<'a> Verifier<'a> {
     /// verifier caveats
     pub fn add_caveat<R: TryInto<Caveat>>(&mut self, caveat: R) -> Result<(), error::Token> {
         let caveat = caveat.try_into().map_err(|_| error::Token::ParseError)?;
+        caveat.validate_parameters()?;
+        caveat.validate_variables()?;
         self.caveats.push(caveat);
         Ok(())
     }
 
     pub fn add_resource(&mut self, resource: &str) {
         let fact = fact("resource", &[s("ambient"), string(resource)]);
-        self.world.facts.insert(fact.convert(&mut self.symbols));
+        let fact = fact.convert(&mut self.symbols);
+        self.insert_fact(fact);
     }
 
     pub fn add_operation(&mut self, operation: &str) {
         let fact = fact("operation", &[s("ambient"), s(operation)]);
-        self.world.facts.insert(fact.convert(&mut self.symbols));
+        let fact = fact.convert(&mut self.symbols);
+        self.insert_fact(fact);
     }
 
     pub fn set_time(&mut self) {
         let fact = fact("time", &[s("ambient"), date(&SystemTime::now())]);
-        self.world.facts.insert(fact.convert(&mut self.symbols));
+        let fact = fact.convert(&mut self.symbols);
+        self.insert_fact(fact);
+    }
+
+    /// adds a `block_key(#ambient, block_index, fingerprint)` fact for the
+    /// public key of each block, to check which key signed which block
+    ///
+    /// sealed tokens do not carry their public keys, so no fact is added for them
+    pub fn add_block_keys(&mut self) {
+        let keys = match self.token.container() {
+            Some(container) => container.keys.clone(),
+            None => return,
+        };
+
+        for (i, key) in keys.iter().enumerate() {
+            let fact = fact("block_key", &[s("ambient"), int(i as i64), fingerprint(key)]);
+            let fact = fact.convert(&mut self.symbols);
+            self.insert_fact(fact);
+        }
+    }
+
+    fn insert_fact(&mut self, fact: datalog::Fact) {
+        self.world.facts.insert(fact.clone());
+        self.new_facts.insert(fact);
+    }
+
+    /// brings the world to its fixpoint
+    ///
+    /// if only facts were added since the last run, only their consequences
+    /// are generated, otherwise the whole world is run again. If the world
+    /// could not reach its fixpoint, it will be run again entirely next time
+    fn run(&mut self) -> Result<(), error::Token> {
+        let new_facts = std::mem::take(&mut self.new_facts);
+        let res = if self.new_rules {
+            self.world.run()
+        } else {
+            self.world.run_incremental(new_facts)
+        };
+
+        self.new_rules = res.is_err();
+        res.map_err(error::Token::FailedLogic)
     }
 
     pub fn revocation_check(&mut self, ids: &[i64]) {
@@ -111,7 +175,8 @@ impl<'a> Verifier<'a> {
             return Err(error::Token::MissingSymbols);
         }
 
-        self.world.run();
+        self.run()?;
+        let counts = datalog::fact_counts(&self.world.facts);
 
         let mut errors = vec![];
         for (i, caveat) in self.caveats.iter().enumerate() {
@@ -119,7 +184,7 @@ impl<'a> Verifier<'a> {
             let mut successful = false;
 
             for query in caveat.queries.iter() {
-                let res = self.world.query_rule(query.convert(&mut self.symbols));
+                let res = self.world.query_rule_with_counts(query.convert(&mut self.symbols), &counts);
                 if !res.is_empty() {
                     successful = true;
                     break;
@@ -139,7 +204,7 @@ impl<'a> Verifier<'a> {
                 let mut successful = false;
 
                 for query in caveat.queries.iter() {
-                    let res = self.world.query_rule(query.clone());
+                    let res = self.world.query_rule_with_counts(query.clone(), &counts);
                     if !res.is_empty() {
                         successful = true;
                         break;
@@ -147,11 +212,7 @@ impl<'a> Verifier<'a> {
                 }
 
                 if !successful {
-                    errors.push(error::FailedCaveat::Block(error::FailedBlockCaveat {
-                        block_id: i as u32,
-                        caveat_id: j as u32,
-                        rule: self.symbols.print_caveat(caveat),
-                    }));
+                    errors.push(failed_block_caveat(&self.symbols, &self.world, i as u32, j as u32, caveat));
                 }
             }
         }
@@ -165,6 +226,23 @@ impl<'a> Verifier<'a> {
         }
     }
 
+    /// checks that the token holder signed the challenge with the private
+    /// key matching the holder key bound to the token
+    ///
+    /// the challenge is provided by the verifier, and should contain a fresh
+    /// nonce along with the request data
+    pub fn check_possession(&self, challenge: &[u8], signature: &[u8]) -> Result<(), error::Token> {
+        let holder = self.token.holder_key()?;
+        let signature = crypto::signature_from_bytes(signature)
+            .ok_or(error::Token::Possession(error::Possession::InvalidSignature))?;
+
+        if crypto::verify(&holder.0, &challenge_message(challenge), &signature) {
+            Ok(())
+        } else {
+            Err(error::Token::Possession(error::Possession::InvalidSignature))
+        }
+    }
+
     pub fn print_world(&self) -> String {
         let facts = self.world
             .facts
@@ -192,6 +270,16 @@ impl<'a> Verifier<'a> {
         format!("World {{\n  facts: {:#?}\n  rules: {:#?}\n  caveats: {:#?}\n}}", facts, rules, caveats)
     }
 
+    /// prints the execution plan of each rule, for the current facts
+    pub fn print_plans(&self) -> Vec<String> {
+        self.world
+            .rules
+            .iter()
+            .zip(self.world.plans())
+            .map(|(rule, plan)| self.symbols.print_plan(rule, &plan))
+            .collect()
+    }
+
     pub fn dump(&self) -> (Vec<Fact>, Vec<Rule>, Vec<Caveat>) {
         (self.world.facts.iter().map(|f| Fact::convert_from(f, &self.symbols)).collect(),
          self.world.rules.iter().map(|r| Rule::convert_from(r, &self.symbols)).collect(),
@@ -118,8 +118,8 @@ verifier error(code = 20): caveat validation failed
 failed caveats (2):
   Verifier caveat 0: *right(#abcd) <- right(#efgh)
   Block 1, caveat 0: *op(#read) <- operation(#ambient, #read)
-serialized size: 273
-wrote 273 bytes
+serialized size: 271
+wrote 271 bytes
 "#);
     }
 
@@ -150,4 +150,186 @@ wrote 273 bytes
         .success()
         .stdout("key_pair creation error? (null)\n");
     }
+
+    #[test]
+    fn query_and_print() {
+        (assert_c! {
+            #include <stdio.h>
+            #include <string.h>
+            #include "biscuit_auth.h"
+
+            int main() {
+                char *seed = "abcdefghabcdefghabcdefghabcdefgh";
+
+                KeyPair * root_kp = key_pair_new((const uint8_t *) seed, strlen(seed));
+                PublicKey* root = key_pair_public(root_kp);
+                char* fingerprint = public_key_fingerprint(root);
+                printf("root key fingerprint: %s\n", fingerprint);
+                string_free(fingerprint);
+
+                BiscuitBuilder* b = biscuit_builder(root_kp);
+                biscuit_builder_add_authority_fact(b, "right(#authority, \"file1\", #read)");
+                biscuit_builder_add_authority_fact(b, "revocation_id(1)");
+                Biscuit * biscuit = biscuit_builder_build(b, (const uint8_t * ) seed, strlen(seed));
+
+                BlockBuilder* bb = biscuit_create_block(biscuit);
+                block_builder_set_context(bb, "user 1234");
+                block_builder_add_caveat(bb, "*check($file) <- resource(#ambient, $file), operation(#ambient, #read), right(#authority, $file, #read)");
+
+                char *seed2 = "ijklmnopijklmnopijklmnopijklmnop";
+                char *seed3 = "ABCDEFGHABCDEFGHABCDEFGHABCDEFGH";
+                KeyPair * kp2 = key_pair_new((const uint8_t *) seed2, strlen(seed2));
+                Biscuit* b2 = biscuit_append_block(biscuit, bb, kp2, (const uint8_t*) seed3, strlen(seed3));
+
+                size_t block_count = biscuit_block_count(b2);
+                printf("block count: %zu\n", block_count);
+                for(size_t i = 0; i < block_count; i++) {
+                    char* context = biscuit_block_context(b2, i);
+                    printf("block %zu context: %s\n", i, context == NULL ? "(none)" : context);
+                    string_free(context);
+                }
+
+                char* biscuit_str = biscuit_print(b2);
+                printf("%s\n", biscuit_str);
+                string_free(biscuit_str);
+
+                Verifier * verifier = biscuit_verify(b2, root);
+                verifier_add_resource(verifier, "file1");
+                verifier_add_operation(verifier, "read");
+                verifier_set_time(verifier);
+                verifier_add_block_keys(verifier);
+                int64_t revoked[] = { 1234 };
+                verifier_revocation_check(verifier, revoked, 1);
+                printf("verifier succeeded? %d\n", verifier_verify(verifier));
+
+                QueryResult* result = verifier_query(verifier, "*readable($file) <- right(#authority, $file, #read), resource(#ambient, $file)");
+                printf("query error? %s\n", error_message());
+                size_t count = query_result_count(result);
+                for(size_t i = 0; i < count; i++) {
+                    char* fact = query_result_fact(result, i);
+                    printf("query result %zu: %s\n", i, fact);
+                    string_free(fact);
+                }
+
+                query_result_free(result);
+                verifier_free(verifier);
+                block_builder_free(bb);
+                biscuit_free(b2);
+                key_pair_free(kp2);
+                biscuit_free(biscuit);
+                public_key_free(root);
+                key_pair_free(root_kp);
+
+                return 0;
+            }
+        })
+        .success()
+        .stdout(r#"root key fingerprint: b4bb8d2e9e0644eec812207e63b957e0
+block count: 2
+block 0 context: (none)
+block 1 context: user 1234
+Biscuit {
+    symbols: ["authority", "ambient", "resource", "operation", "right", "current_time", "revocation_id", "read", "write", "time", "expiration", "check", "file"]
+    authority: Block[0] {
+            symbols: []
+            context: ""
+            public key: "d0a9a11ebaa13460ad8686c4dc3f866b618a051323b536dd572b0cbda22d1767"
+            fingerprint: "b4bb8d2e9e0644eec812207e63b957e0"
+            facts: [
+                right(#authority, "file1", #read),
+                revocation_id(1)
+            ]
+            rules: []
+            caveats: []
+        }
+    blocks: [
+        Block[1] {
+            symbols: ["check", "file"]
+            context: "user 1234"
+            public key: "8ccc86323481d1c89624cbf05be16e06425f5470a445406b5de5e46ac909b15f"
+            fingerprint: "03adae0893764c8f6395fdb2cea7cbfa"
+            facts: []
+            rules: []
+            caveats: [
+                *check($file) <- resource(#ambient, $file), operation(#ambient, #read), right(#authority, $file, #read)
+            ]
+        }
+    ]
+}
+verifier succeeded? 1
+query error? (null)
+query result 0: readable("file1")
+"#);
+    }
+
+    #[test]
+    fn error_handle() {
+        (assert_c! {
+            #include <stdio.h>
+            #include <string.h>
+            #include "biscuit_auth.h"
+
+            int main() {
+                char *seed = "abcdefghabcdefghabcdefghabcdefgh";
+
+                KeyPair * root_kp = key_pair_new((const uint8_t *) seed, strlen(seed));
+                PublicKey* root = key_pair_public(root_kp);
+
+                BiscuitBuilder* b = biscuit_builder(root_kp);
+                biscuit_builder_add_authority_fact(b, "right(#authority, \"file1\", #read)");
+                Biscuit * biscuit = biscuit_builder_build(b, (const uint8_t * ) seed, strlen(seed));
+
+                BiscuitError* invalid_seed = NULL;
+                KeyPair * kp = key_pair_new_with_error((const uint8_t *) seed, 3, &invalid_seed);
+                printf("invalid seed: key pair = %p, error = %s\n", (void*) kp, biscuit_error_message(invalid_seed));
+
+                Verifier * verifier = biscuit_verify(biscuit, root);
+                verifier_add_caveat(verifier, "*right(#abcd) <- right(#efgh)");
+
+                BiscuitError* verify_error = NULL;
+                if(!verifier_verify_with_error(verifier, &verify_error)) {
+                    printf("verifier error(failed caveats? %d): %s\n",
+                        biscuit_error_kind(verify_error) == LogicFailedCaveats,
+                        biscuit_error_message(verify_error));
+                }
+
+                // a second failure does not overwrite the first error
+                BiscuitError* parse_error = NULL;
+                verifier_add_caveat_with_error(verifier, "invalid", &parse_error);
+                printf("parse error(parse error? %d): %s\n",
+                    biscuit_error_kind(parse_error) == ParseError,
+                    biscuit_error_message(parse_error));
+
+                uint64_t error_count = biscuit_error_caveat_count(verify_error);
+                printf("failed caveats (%ld):\n", error_count);
+                for(uint64_t i = 0; i < error_count; i++) {
+                    printf("  verifier caveat? %d, caveat %ld: %s\n",
+                        biscuit_error_caveat_is_verifier(verify_error, i),
+                        biscuit_error_caveat_id(verify_error, i),
+                        biscuit_error_caveat_rule(verify_error, i));
+                }
+
+                // the thread local error is still available
+                printf("last error: %s\n", error_message());
+
+                biscuit_error_free(parse_error);
+                biscuit_error_free(verify_error);
+                biscuit_error_free(invalid_seed);
+                verifier_free(verifier);
+                biscuit_free(biscuit);
+                public_key_free(root);
+                key_pair_free(root_kp);
+
+                return 0;
+            }
+        })
+        .success()
+        .stdout(r#"invalid seed: key pair = (nil), error = invalid argument
+verifier error(failed caveats? 1): caveat validation failed
+parse error(parse error? 1): Datalog parsing error
+failed caveats (1):
+  verifier caveat? 1, caveat 0: *right(#abcd) <- right(#efgh)
+last error: Datalog parsing error
+"#);
+    }
 }